@@ -0,0 +1,106 @@
+use phirepass_common::protocol::node::WebUser;
+use std::collections::{BTreeMap, BTreeSet};
+use uuid::Uuid;
+
+/// Local accounts phirepass users may log in as, keyed by `user:<uuid>`,
+/// `role:<name>` or `*` for every user. An account is allowed when any
+/// entry matching the user lists it.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LocalAccounts {
+    anyone: BTreeSet<String>,
+    users: BTreeMap<Uuid, BTreeSet<String>>,
+    roles: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl LocalAccounts {
+    pub(crate) fn parse(value: &str) -> anyhow::Result<Self> {
+        let entries: BTreeMap<String, BTreeSet<String>> = serde_json::from_str(value)
+            .map_err(|err| anyhow::anyhow!("LOCAL_ACCOUNTS must map users to accounts: {err}"))?;
+
+        let mut accounts = Self::default();
+        for (key, names) in entries {
+            if names.iter().any(|name| name.trim().is_empty()) {
+                anyhow::bail!("LOCAL_ACCOUNTS entry {key} lists an empty account");
+            }
+
+            if key == "*" {
+                accounts.anyone.extend(names);
+            } else if let Some(user_id) = key.strip_prefix("user:") {
+                let user_id = Uuid::parse_str(user_id)
+                    .map_err(|err| anyhow::anyhow!("invalid LOCAL_ACCOUNTS key {key}: {err}"))?;
+                accounts.users.entry(user_id).or_default().extend(names);
+            } else if let Some(role) = key.strip_prefix("role:")
+                && !role.is_empty()
+            {
+                accounts
+                    .roles
+                    .entry(role.to_string())
+                    .or_default()
+                    .extend(names);
+            } else {
+                anyhow::bail!(
+                    "invalid LOCAL_ACCOUNTS key {key}: expected user:<uuid>, role:<name> or *"
+                );
+            }
+        }
+
+        Ok(accounts)
+    }
+
+    /// Accounts open to `user`; one the server did not identify only gets
+    /// those listed under `*`.
+    pub(crate) fn allowed<'a>(&'a self, user: Option<&WebUser>) -> BTreeSet<&'a str> {
+        let mut allowed: BTreeSet<&str> = self.anyone.iter().map(String::as_str).collect();
+
+        if let Some(user) = user {
+            if let Some(names) = self.users.get(&user.user_id) {
+                allowed.extend(names.iter().map(String::as_str));
+            }
+            for role in &user.roles {
+                if let Some(names) = self.roles.get(role) {
+                    allowed.extend(names.iter().map(String::as_str));
+                }
+            }
+        }
+
+        allowed
+    }
+
+    pub(crate) fn permits(&self, user: Option<&WebUser>, username: &str) -> bool {
+        self.allowed(user).contains(username)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_users_and_roles_to_accounts() {
+        let user_id = Uuid::new_v4();
+        let accounts = LocalAccounts::parse(&format!(
+            r#"{{"*": ["deploy"], "role:admin": ["root"], "user:{user_id}": ["alice"]}}"#
+        ))
+        .unwrap();
+
+        let admin = WebUser {
+            user_id: Uuid::new_v4(),
+            roles: vec!["user".into(), "admin".into()],
+        };
+        let alice = WebUser {
+            user_id,
+            roles: vec!["user".into()],
+        };
+
+        assert!(accounts.permits(Some(&admin), "root"));
+        assert!(accounts.permits(Some(&admin), "deploy"));
+        assert!(!accounts.permits(Some(&admin), "alice"));
+        assert!(accounts.permits(Some(&alice), "alice"));
+        assert!(!accounts.permits(Some(&alice), "root"));
+        assert!(accounts.permits(None, "deploy"));
+        assert!(!accounts.permits(None, "root"));
+
+        assert!(LocalAccounts::parse(r#"{"admin": ["root"]}"#).is_err());
+        assert!(LocalAccounts::parse(r#"{"user:nope": ["root"]}"#).is_err());
+    }
+}
@@ -0,0 +1,403 @@
+use crate::drain::Drain;
+use crate::session::TunnelSessions;
+use crate::sftp::{SFTPActiveDownloads, SFTPActiveUploads};
+use crate::usage::LinkUsage;
+use axum::Json;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use log::info;
+use phirepass_common::time::now_millis;
+use serde::Serialize;
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// Where the admin API listens: a unix socket path (`unix:/run/phirepass/admin.sock`)
+/// or a loopback `host:port`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum AdminListen {
+    Unix(std::path::PathBuf),
+    Tcp(std::net::SocketAddr),
+}
+
+impl AdminListen {
+    pub(crate) fn parse(value: &str) -> anyhow::Result<Self> {
+        let value = value.trim();
+        if let Some(path) = value.strip_prefix("unix:") {
+            if path.is_empty() {
+                anyhow::bail!("admin socket path is empty");
+            }
+            return Ok(Self::Unix(path.into()));
+        }
+
+        let addr: std::net::SocketAddr = value
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid admin listen address {value}"))?;
+        if !addr.ip().is_loopback() {
+            anyhow::bail!("admin api must listen on a loopback address, got {addr}");
+        }
+
+        Ok(Self::Tcp(addr))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ConnectionState {
+    Connecting,
+    Connected,
+    Disconnected,
+    /// Waiting for `retry_at` before the next reconnect.
+    BackingOff,
+    /// The server rejects the node (or no identity is stored); still
+    /// retried, but with the backoff delay.
+    AuthFailing,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ConnectionStatus {
+    state: ConnectionState,
+    node_id: Option<Uuid>,
+    since: u64,
+    attempt: u32,
+    retry_at: Option<u64>,
+    last_error: Option<String>,
+}
+
+/// Connection state without errors or node ids, for the public `/version`
+/// endpoint.
+#[derive(Debug, Serialize)]
+pub(crate) struct ConnectionSummary {
+    profile: Option<String>,
+    server_host: String,
+    state: ConnectionState,
+    attempt: u32,
+    retry_at: Option<u64>,
+}
+
+/// One websocket link to a server. The session and transfer maps outlive
+/// reconnects so the admin API always sees the current ones.
+pub(crate) struct Connection {
+    pub(crate) profile: Option<String>,
+    pub(crate) server_host: String,
+    pub(crate) server_port: u16,
+    pub(crate) sessions: TunnelSessions,
+    pub(crate) uploads: SFTPActiveUploads,
+    pub(crate) downloads: SFTPActiveDownloads,
+    pub(crate) drain: Arc<Drain>,
+    pub(crate) usage: Arc<LinkUsage>,
+    status: RwLock<ConnectionStatus>,
+    heartbeat_rtt_ms: AtomicU64,
+}
+
+impl Connection {
+    pub(crate) fn new(profile: Option<String>, server_host: String, server_port: u16) -> Self {
+        Self {
+            profile,
+            server_host,
+            server_port,
+            sessions: Arc::new(Default::default()),
+            uploads: Arc::new(Default::default()),
+            downloads: Arc::new(Default::default()),
+            drain: Arc::new(Drain::default()),
+            usage: Arc::new(LinkUsage::default()),
+            status: RwLock::new(ConnectionStatus {
+                state: ConnectionState::Connecting,
+                node_id: None,
+                since: now_millis(),
+                attempt: 0,
+                retry_at: None,
+                last_error: None,
+            }),
+            heartbeat_rtt_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Round trip time of the last acknowledged heartbeat on this link.
+    pub(crate) fn set_heartbeat_rtt(&self, millis: u64) {
+        self.heartbeat_rtt_ms.store(millis, Ordering::Relaxed);
+    }
+
+    pub(crate) fn heartbeat_rtt_ms(&self) -> u64 {
+        self.heartbeat_rtt_ms.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_state(&self, state: ConnectionState, node_id: Option<Uuid>) {
+        let mut status = self.status.write().unwrap_or_else(|err| err.into_inner());
+        if status.state != state {
+            status.since = now_millis();
+        }
+        status.state = state;
+        status.retry_at = None;
+        if node_id.is_some() {
+            status.node_id = node_id;
+        }
+        if state == ConnectionState::Connected {
+            status.attempt = 0;
+            status.last_error = None;
+        }
+    }
+
+    pub(crate) fn set_backoff(&self, attempt: u32, delay: Duration, auth_failing: bool) {
+        let state = if auth_failing {
+            ConnectionState::AuthFailing
+        } else {
+            ConnectionState::BackingOff
+        };
+
+        self.set_state(state, None);
+        let mut status = self.status.write().unwrap_or_else(|err| err.into_inner());
+        status.attempt = attempt;
+        status.retry_at = Some(now_millis().saturating_add(delay.as_millis() as u64));
+    }
+
+    pub(crate) fn summary(&self) -> ConnectionSummary {
+        let status = self.status();
+        ConnectionSummary {
+            profile: self.profile.clone(),
+            server_host: self.server_host.clone(),
+            state: status.state,
+            attempt: status.attempt,
+            retry_at: status.retry_at,
+        }
+    }
+
+    pub(crate) fn set_error(&self, error: impl ToString) {
+        self.set_state(ConnectionState::Disconnected, None);
+        let mut status = self.status.write().unwrap_or_else(|err| err.into_inner());
+        status.last_error = Some(error.to_string());
+    }
+
+    pub(crate) fn state(&self) -> ConnectionState {
+        self.status
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .state
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.status
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    /// Shuts down matching sessions and returns how many were closed. The
+    /// tunnel tasks report `TunnelClosed` to the web client on their way out.
+    async fn kill_sessions(&self, filter: impl Fn(&(Uuid, u32)) -> bool) -> usize {
+        let keys: Vec<_> = self
+            .sessions
+            .iter()
+            .map(|entry| *entry.key())
+            .filter(|key| filter(key))
+            .collect();
+
+        let mut killed = 0;
+        for key in keys {
+            if let Some((_, handle)) = self.sessions.remove(&key) {
+                info!("admin api closing session {}:{}", key.0, key.1);
+                handle.shutdown().await;
+                killed += 1;
+            }
+        }
+
+        killed
+    }
+}
+
+pub(crate) type Connections = Arc<Vec<Arc<Connection>>>;
+
+#[derive(Serialize)]
+struct ConnectionView {
+    profile: Option<String>,
+    server_host: String,
+    server_port: u16,
+    #[serde(flatten)]
+    status: ConnectionStatus,
+    sessions: usize,
+    uploads: usize,
+    downloads: usize,
+    draining: bool,
+}
+
+#[derive(Serialize)]
+struct SessionView {
+    profile: Option<String>,
+    cid: Uuid,
+    sid: u32,
+    protocol: &'static str,
+    username: String,
+    started_at: u64,
+}
+
+#[derive(Serialize)]
+struct TransferView {
+    profile: Option<String>,
+    direction: &'static str,
+    cid: Uuid,
+    id: u32,
+    filename: String,
+    path: Option<String>,
+    total_size: u64,
+    total_chunks: u32,
+    started_at: u64,
+    last_updated: u64,
+}
+
+pub(crate) async fn list_connections(State(connections): State<Connections>) -> impl IntoResponse {
+    let views: Vec<_> = connections
+        .iter()
+        .map(|conn| ConnectionView {
+            profile: conn.profile.clone(),
+            server_host: conn.server_host.clone(),
+            server_port: conn.server_port,
+            status: conn.status(),
+            sessions: conn.sessions.len(),
+            uploads: conn.uploads.len(),
+            downloads: conn.downloads.len(),
+            draining: conn.drain.is_active(),
+        })
+        .collect();
+
+    Json(views)
+}
+
+pub(crate) async fn list_sessions(State(connections): State<Connections>) -> impl IntoResponse {
+    let mut views = Vec::new();
+    for conn in connections.iter() {
+        for entry in conn.sessions.iter() {
+            let (cid, sid) = *entry.key();
+            let handle = entry.value();
+            views.push(SessionView {
+                profile: conn.profile.clone(),
+                cid,
+                sid,
+                protocol: handle.protocol(),
+                username: handle.username().to_string(),
+                started_at: handle.started_at(),
+            });
+        }
+    }
+
+    Json(views)
+}
+
+pub(crate) async fn list_transfers(State(connections): State<Connections>) -> impl IntoResponse {
+    let mut views = Vec::new();
+    for conn in connections.iter() {
+        for entry in conn.uploads.iter() {
+            let (cid, id) = *entry.key();
+            let upload = entry.value();
+            views.push(TransferView {
+                profile: conn.profile.clone(),
+                direction: "upload",
+                cid,
+                id,
+                filename: upload.filename.clone(),
+                path: Some(upload.remote_path.clone()),
+                total_size: upload.total_size,
+                total_chunks: upload.total_chunks,
+                started_at: millis(upload.started_at),
+                last_updated: millis(upload.last_updated),
+            });
+        }
+
+        for entry in conn.downloads.iter() {
+            let (cid, id) = *entry.key();
+            let download = entry.value();
+            views.push(TransferView {
+                profile: conn.profile.clone(),
+                direction: "download",
+                cid,
+                id,
+                filename: download.filename.clone(),
+                path: None,
+                total_size: download.total_size,
+                total_chunks: download.total_chunks,
+                started_at: millis(download.started_at),
+                last_updated: millis(download.last_updated),
+            });
+        }
+    }
+
+    Json(views)
+}
+
+pub(crate) async fn kill_session(
+    State(connections): State<Connections>,
+    Path((cid, sid)): Path<(Uuid, u32)>,
+) -> impl IntoResponse {
+    let mut killed = 0;
+    for conn in connections.iter() {
+        killed += conn.kill_sessions(|key| *key == (cid, sid)).await;
+    }
+
+    if killed == 0 {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "success": false,
+                "code": "SESSION_NOT_FOUND",
+                "error": "session not found"
+            })),
+        );
+    }
+
+    (
+        StatusCode::OK,
+        Json(json!({ "success": true, "closed": killed })),
+    )
+}
+
+/// Closes every session and drops in-flight transfers on all connections;
+/// the websocket links themselves stay up.
+pub(crate) async fn kill_all_sessions(State(connections): State<Connections>) -> impl IntoResponse {
+    let mut killed = 0;
+    for conn in connections.iter() {
+        killed += conn.kill_sessions(|_| true).await;
+
+        let upload_keys: Vec<_> = conn.uploads.iter().map(|entry| *entry.key()).collect();
+        for key in upload_keys {
+            if let Some((_, file_upload)) = conn.uploads.remove(&key) {
+                let _ = file_upload.sftp_file.sync_all().await;
+            }
+        }
+
+        conn.downloads.clear();
+    }
+
+    info!("admin api closed {killed} session(s)");
+
+    Json(json!({ "success": true, "closed": killed }))
+}
+
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_listen_addresses() {
+        assert_eq!(
+            AdminListen::parse("unix:/run/phirepass/admin.sock").unwrap(),
+            AdminListen::Unix("/run/phirepass/admin.sock".into())
+        );
+        assert_eq!(
+            AdminListen::parse("127.0.0.1:8082").unwrap(),
+            AdminListen::Tcp("127.0.0.1:8082".parse().unwrap())
+        );
+        assert!(AdminListen::parse("[::1]:8082").is_ok());
+
+        assert!(AdminListen::parse("0.0.0.0:8082").is_err());
+        assert!(AdminListen::parse("unix:").is_err());
+        assert!(AdminListen::parse("localhost").is_err());
+    }
+}
@@ -1,65 +1,239 @@
-use crate::creds::{StoredState, TokenStore};
+use crate::accounts::LocalAccounts;
+use crate::admin::{self, AdminListen, Connection, ConnectionState, Connections};
+use crate::audit;
+use crate::backoff::Backoff;
+use crate::creds::{KeySettings, StoredState, TokenStore};
+use crate::drain;
 use crate::env::Env;
-use crate::http::{AppState, get_version};
+use crate::error::RequestError;
+use crate::health;
+use crate::http::{AppState, get_metrics, get_version};
+use crate::metrics::METRICS;
+use crate::proxy;
+use crate::ssh::pool;
+use crate::systemd;
 use crate::ws;
 use anyhow::Context;
 use axum::Router;
-use axum::routing::get;
+use axum::routing::{delete, get};
 use base64::Engine;
 use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use ed25519_dalek::{Signer, SigningKey};
 use log::{debug, info, warn};
 use phirepass_common::stats::Stats;
-use phirepass_common::token::mask_after_10;
-use secrecy::SecretString;
+use phirepass_common::token::{key_rotation_message, mask_after_10};
+use secrecy::{ExposeSecret, SecretString};
 use serde::Deserialize;
 use serde_json::json;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::fs;
 use tokio::signal;
 use tokio::sync::broadcast;
 use uuid::Uuid;
 
-pub(crate) async fn start(config: Env) -> anyhow::Result<()> {
+/// Starts the agent. Without profiles it connects to the configured server;
+/// otherwise it keeps one connection per profile, each to the server the
+/// profile logged in to. `local_accounts` restricts the accounts web users
+/// may log in as.
+pub(crate) async fn start(
+    config: Env,
+    local_accounts: Option<LocalAccounts>,
+    profiles: Vec<String>,
+) -> anyhow::Result<()> {
     info!("running server on {} mode", config.mode);
 
+    audit::init(
+        config.audit_log.as_deref(),
+        config.audit_log_max_bytes,
+        config.audit_log_max_files,
+    )?;
+    pool::init(config.get_ssh_pool_idle_timeout());
+
     let host = config.host.clone();
+    let (ssh_host, ssh_port) = (config.ssh_host.clone(), config.ssh_port);
     let stats_refresh_interval = config.stats_refresh_interval;
     let (shutdown_tx, _) = broadcast::channel(1);
 
-    let state = AppState::new(Arc::new(config));
-    let ws_task = start_ws_connection(&state, shutdown_tx.subscribe());
+    let admin_listen = config
+        .admin_listen
+        .as_deref()
+        .filter(|value| !value.trim().is_empty())
+        .map(AdminListen::parse)
+        .transpose()?;
+
+    // Bound up front: a listener that cannot bind fails startup instead of
+    // ending a running agent
+    let admin_listener = match admin_listen {
+        Some(listen) => Some(bind_admin_listener(listen).await?),
+        None => None,
+    };
+
+    let targets = connection_targets(&config, &profiles)?;
+    let local_accounts = local_accounts.map(Arc::new);
+    let connections: Connections = Arc::new(
+        targets
+            .iter()
+            .map(|(profile, env)| {
+                Arc::new(Connection::new(
+                    profile.clone(),
+                    env.server_host.clone(),
+                    env.server_port,
+                ))
+            })
+            .collect(),
+    );
+    let ws_tasks: Vec<_> = targets
+        .into_iter()
+        .zip(connections.iter())
+        .map(|((profile, env), link)| {
+            start_ws_connection(
+                profile,
+                env,
+                Arc::clone(link),
+                Arc::clone(&connections),
+                local_accounts.clone(),
+                shutdown_tx.subscribe(),
+            )
+        })
+        .collect();
+    let ws_task = futures_util::future::select_all(ws_tasks);
+
+    let admin_task = match admin_listener {
+        Some(listener) => {
+            start_admin_server(listener, Arc::clone(&connections), shutdown_tx.subscribe())
+        }
+        None => tokio::spawn(std::future::pending()),
+    };
+
+    let shutdown_drain = config.drain_request("the agent is shutting down", true, None);
+    let state = AppState::new(Arc::new(config), Arc::clone(&connections));
     let http_task = start_http_server(state, shutdown_tx.subscribe());
     let stats_task =
         spawn_stats_logger(host, stats_refresh_interval as u64, shutdown_tx.subscribe());
+    let health_task = health::spawn_prober(
+        ssh_host,
+        ssh_port,
+        Duration::from_secs(stats_refresh_interval as u64),
+        shutdown_tx.subscribe(),
+    );
 
+    let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())
+        .context("failed to listen for SIGTERM")?;
     let shutdown_signal = async {
-        if let Err(err) = signal::ctrl_c().await {
-            warn!("failed to listen for shutdown signal: {}", err);
-        } else {
-            info!("ctrl+c pressed, shutting down");
+        tokio::select! {
+            res = signal::ctrl_c() => match res {
+                Ok(()) => info!("ctrl+c pressed, shutting down"),
+                Err(err) => {
+                    warn!("failed to listen for shutdown signal: {}", err);
+                    std::future::pending::<()>().await
+                }
+            },
+            _ = terminate.recv() => info!("SIGTERM received, shutting down"),
         }
     };
 
-    tokio::select! {
-        _ = ws_task => warn!("ws task ended"),
-        _ = http_task => warn!("http task ended"),
-        _ = stats_task => warn!("stats logger task ended"),
-        _ = shutdown_signal => info!("shutdown signal received"),
+    let signalled = tokio::select! {
+        _ = ws_task => { warn!("ws task ended"); false }
+        _ = http_task => { warn!("http task ended"); false }
+        _ = admin_task => { warn!("admin api task ended"); false }
+        _ = stats_task => { warn!("stats logger task ended"); false }
+        _ = health_task => { warn!("ssh target probe task ended"); false }
+        _ = shutdown_signal => { info!("shutdown signal received"); true }
+    };
+
+    systemd::notify("STOPPING=1");
+
+    if signalled {
+        info!("draining sessions before shutting down");
+        tokio::select! {
+            _ = drain::all(&connections, shutdown_drain) => info!("drain complete"),
+            _ = signal::ctrl_c() => warn!("interrupted again, skipping the drain"),
+        }
     }
 
     let _ = shutdown_tx.send(());
 
     info!("waiting for tasks to shut down gracefully...");
     tokio::time::sleep(Duration::from_millis(500)).await;
+    audit::flush().await;
 
     Ok(())
 }
 
+pub(crate) fn connection_targets(
+    config: &Env,
+    profiles: &[String],
+) -> anyhow::Result<Vec<(Option<String>, Arc<Env>)>> {
+    if profiles.is_empty() {
+        return Ok(vec![(None, Arc::new(config.clone()))]);
+    }
+
+    let keys = config.key_settings();
+    profiles
+        .iter()
+        .map(|profile| {
+            let stored = open_token_store(&keys, Some(profile), "")?
+                .load()
+                .with_context(|| format!("no login found for profile {profile}"))?;
+
+            let mut env = config.clone();
+            if !stored.server_host.is_empty() {
+                env.server_host = stored.server_host;
+            }
+            if let Some(server_port) = stored.server_port {
+                env.server_port = server_port;
+            }
+
+            info!(
+                "profile {profile} connects to {}:{}",
+                env.server_host, env.server_port
+            );
+            Ok((Some(profile.clone()), Arc::new(env)))
+        })
+        .collect()
+}
+
+/// Identities are stored per profile, named after the server host unless
+/// one is given. An empty `server_host` accepts the identity whatever server
+/// it belongs to.
+pub(crate) fn open_token_store(
+    keys: &KeySettings,
+    profile: Option<&str>,
+    server_host: &str,
+) -> std::io::Result<TokenStore> {
+    TokenStore::new(
+        "phirepass",
+        "agent",
+        profile.unwrap_or(server_host),
+        server_host,
+        keys.clone(),
+    )
+}
+
+/// With an explicit profile the server it logged in to wins over the
+/// command line defaults.
+fn stored_server(
+    stored: &StoredState,
+    profile: Option<&str>,
+    server_host: &str,
+    server_port: u16,
+) -> (String, u16) {
+    if profile.is_some() && !stored.server_host.is_empty() {
+        (
+            stored.server_host.clone(),
+            stored.server_port.unwrap_or(server_port),
+        )
+    } else {
+        (server_host.to_string(), server_port)
+    }
+}
+
 pub(crate) async fn login(
+    keys: KeySettings,
+    profile: Option<String>,
     server_host: String,
     server_port: u16,
     file: Option<PathBuf>,
@@ -89,10 +263,19 @@ pub(crate) async fn login(
         rpassword::prompt_password("Enter authentication token: ")?
     };
 
-    bootstrap_identity(server_host, server_port, pat.as_str()).await
+    bootstrap_identity(
+        &keys,
+        profile.as_deref(),
+        server_host,
+        server_port,
+        pat.as_str(),
+    )
+    .await
 }
 
 pub(crate) async fn save_token(
+    keys: &KeySettings,
+    profile: Option<&str>,
     server_host: &str,
     server_port: u16,
     token: &str,
@@ -107,14 +290,18 @@ pub(crate) async fn save_token(
     debug!("token to save: {}", mask_after_10(token));
     debug!("server host: {}, server port: {}", server_host, server_port);
 
-    bootstrap_identity(server_host, server_port, token.trim()).await
+    bootstrap_identity(keys, profile, server_host, server_port, token.trim()).await
 }
 
-pub(crate) fn load_creds_for_server(server_host: &str) -> Option<(String, StoredState)> {
-    let ts = TokenStore::new("phirepass", "agent", server_host).ok()?;
+pub(crate) fn load_creds(
+    keys: &KeySettings,
+    profile: Option<&str>,
+    server_host: &str,
+) -> Option<StoredState> {
+    let ts = open_token_store(keys, profile, server_host).ok()?;
 
     match ts.load() {
-        Ok(state) => Some((server_host.to_string(), state)),
+        Ok(state) => Some(state),
         Err(err) => {
             warn!("failed to load node identity: {}", err);
             None
@@ -122,17 +309,161 @@ pub(crate) fn load_creds_for_server(server_host: &str) -> Option<(String, Stored
     }
 }
 
-pub(crate) async fn logout(server_host: String, server_port: u16) -> anyhow::Result<()> {
-    let _ = server_port;
+pub(crate) async fn logout(
+    keys: KeySettings,
+    profile: Option<String>,
+    server_host: String,
+    server_port: u16,
+    local_only: bool,
+) -> anyhow::Result<()> {
+    let server_host = server_host
+        .split_once("://")
+        .map(|(_, rest)| rest)
+        .unwrap_or(server_host.as_str());
 
-    let ts = TokenStore::new("phirepass", "agent", server_host.as_str())?;
-    let _ = ts
+    let profile = profile.as_deref();
+    let ts = open_token_store(
+        &keys,
+        profile,
+        if profile.is_some() { "" } else { server_host },
+    )?;
+    let stored = ts
         .load()
         .context("no active login found - please login first")?;
 
+    if !local_only {
+        let (server_host, server_port) = stored_server(&stored, profile, server_host, server_port);
+        revoke_identity(&server_host, server_port, &stored)
+            .await
+            .context("failed to revoke node on server; use --local-only to only delete the local identity")?;
+        info!("node {} revoked on {server_host}", stored.node_id);
+    }
+
     ts.delete().context("failed to delete local identity")?;
     info!("local identity deleted");
-    println!("Successfully logged out locally.");
+
+    if local_only {
+        println!("Successfully logged out locally.");
+    } else {
+        println!(
+            "Successfully logged out and revoked node {}.",
+            stored.node_id
+        );
+    }
+
+    Ok(())
+}
+
+async fn revoke_identity(
+    server_host: &str,
+    server_port: u16,
+    state: &StoredState,
+) -> anyhow::Result<()> {
+    let session_token = fetch_session_jwt(server_host, server_port, state).await?;
+    let base_url = generate_http_endpoint(server_host, server_port);
+
+    let response = proxy::http_client()?
+        .post(format!("{}/api/nodes/revoke", base_url))
+        .bearer_auth(session_token.expose_secret())
+        .send()
+        .await?;
+
+    let _: serde_json::Value = parse_json_response(response).await?;
+    Ok(())
+}
+
+/// Replaces the node keypair. The server swaps the public key only after
+/// both the current and the new key have signed the rotation message; the
+/// new identity is persisted once the server has accepted it.
+pub(crate) async fn rotate_key(
+    keys: KeySettings,
+    profile: Option<String>,
+    server_host: String,
+    server_port: u16,
+) -> anyhow::Result<()> {
+    let server_host = server_host
+        .split_once("://")
+        .map(|(_, rest)| rest)
+        .unwrap_or(server_host.as_str());
+
+    let profile = profile.as_deref();
+    let ts = open_token_store(
+        &keys,
+        profile,
+        if profile.is_some() { "" } else { server_host },
+    )?;
+    let stored = ts
+        .load()
+        .context("no active login found - please login first")?;
+
+    let (server_host, server_port) = stored_server(&stored, profile, server_host, server_port);
+    let server_host = server_host.as_str();
+    info!("rotating node key with {server_host}:{server_port}");
+
+    if let Some((private_key, public_key)) = ts.load_pending_identity()? {
+        let pending = StoredState {
+            private_key,
+            public_key,
+            ..stored.clone()
+        };
+
+        // A previous rotation may have reached the server before the agent
+        // could switch to the new key
+        if fetch_session_jwt(server_host, server_port, &pending)
+            .await
+            .is_ok()
+        {
+            ts.activate_pending_identity(server_host, server_port)
+                .context("failed to activate the pending node key")?;
+            info!(
+                "completed interrupted key rotation; node_id={}",
+                stored.node_id
+            );
+            println!("Completed an interrupted node key rotation.");
+            return Ok(());
+        }
+
+        fetch_session_jwt(server_host, server_port, &stored)
+            .await
+            .context("the server accepts neither the active nor the pending node key")?;
+        warn!("discarding pending node key the server never switched to");
+        ts.discard_pending_identity()?;
+    }
+
+    let client = proxy::http_client()?;
+    let base_url = generate_http_endpoint(server_host, server_port);
+
+    let challenge = request_challenge(&client, &base_url, stored.node_id).await?;
+    let identity = generate_identity();
+    let message = key_rotation_message(&challenge, &identity.public_key);
+
+    // Saved first: the server must never hold a key the agent cannot use
+    ts.save_pending_identity(identity.private_key.clone(), identity.public_key.clone())
+        .context("failed to save the new node key; the key was not rotated")?;
+
+    let rotated: RotateKeyResponse = post_json(
+        &client,
+        &format!("{}/api/nodes/auth/rotate", base_url),
+        &json!({
+            "node_id": stored.node_id,
+            "challenge": challenge,
+            "new_public_key": identity.public_key,
+            "signature": sign(&stored.private_key, &message)?,
+            "new_signature": sign(&identity.private_key, &message)?,
+        }),
+    )
+    .await
+    .context("failed to rotate node key; run rotate-key again to finish or undo it")?;
+
+    if rotated.public_key != identity.public_key {
+        anyhow::bail!("server confirmed an unexpected public key");
+    }
+
+    ts.activate_pending_identity(server_host, server_port)
+        .context("failed to activate the rotated node key; run rotate-key again to finish")?;
+
+    info!("node key rotated; node_id={}", stored.node_id);
+    println!("Successfully rotated node key.");
 
     Ok(())
 }
@@ -146,6 +477,7 @@ fn start_http_server(
     tokio::spawn(async move {
         let app = Router::new()
             .route("/version", get(get_version))
+            .route("/metrics", get(get_metrics))
             .with_state(state);
 
         let listener = match tokio::net::TcpListener::bind(host).await {
@@ -175,13 +507,97 @@ fn start_http_server(
     })
 }
 
+enum AdminListener {
+    Tcp(tokio::net::TcpListener),
+    Unix(tokio::net::UnixListener, PathBuf),
+}
+
+/// Binds the admin api on a loopback address or a unix socket. The socket
+/// is created with mode `0600` so only the agent's user can reach it; a
+/// loopback port is open to every local user.
+async fn bind_admin_listener(listen: AdminListen) -> anyhow::Result<AdminListener> {
+    match listen {
+        AdminListen::Tcp(addr) => {
+            let listener = tokio::net::TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("failed to bind admin listener {addr}"))?;
+            warn!("admin api listening on: {addr}; every local user can reach it");
+            Ok(AdminListener::Tcp(listener))
+        }
+        AdminListen::Unix(path) => {
+            let listener = bind_admin_socket(&path)
+                .with_context(|| format!("failed to bind admin socket {}", path.display()))?;
+            info!("admin api listening on: {}", path.display());
+            Ok(AdminListener::Unix(listener, path))
+        }
+    }
+}
+
+fn start_admin_server(
+    listener: AdminListener,
+    connections: Connections,
+    mut shutdown: broadcast::Receiver<()>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let app = Router::new()
+            .route("/admin/connections", get(admin::list_connections))
+            .route(
+                "/admin/sessions",
+                get(admin::list_sessions).delete(admin::kill_all_sessions),
+            )
+            .route("/admin/sessions/{cid}/{sid}", delete(admin::kill_session))
+            .route("/admin/transfers", get(admin::list_transfers))
+            .with_state(connections);
+
+        let shutdown = async move {
+            let _ = shutdown.recv().await;
+        };
+
+        let result = match listener {
+            AdminListener::Tcp(listener) => {
+                axum::serve(listener, app)
+                    .with_graceful_shutdown(shutdown)
+                    .await
+            }
+            AdminListener::Unix(listener, path) => {
+                let result = axum::serve(listener, app)
+                    .with_graceful_shutdown(shutdown)
+                    .await;
+                let _ = std::fs::remove_file(&path);
+                result
+            }
+        };
+
+        if let Err(err) = result {
+            warn!("admin api error: {err}");
+        }
+    })
+}
+
+fn bind_admin_socket(path: &std::path::Path) -> std::io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::PermissionsExt;
+
+    // A socket left behind by an unclean exit would make bind fail
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = tokio::net::UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
 fn start_ws_connection(
-    state: &AppState,
+    profile: Option<String>,
+    env: Arc<Env>,
+    link: Arc<Connection>,
+    links: Connections,
+    local_accounts: Option<Arc<LocalAccounts>>,
     mut shutdown: broadcast::Receiver<()>,
 ) -> tokio::task::JoinHandle<()> {
-    let env = Arc::clone(&state.env);
     tokio::spawn(async move {
-        let mut attempt: u32 = 0;
+        let mut backoff = Backoff::from_env(&env);
+        let keys = env.key_settings();
 
         let server_host = env.server_host.clone();
         let server_host = env
@@ -190,18 +606,43 @@ fn start_ws_connection(
             .map(|(_, rest)| rest)
             .unwrap_or(server_host.as_str());
 
+        // A named profile may belong to any server; its host was already
+        // taken from the identity itself
+        let expected_host = if profile.is_some() { "" } else { server_host };
+
         loop {
-            let creds_result = load_creds_for_server(server_host);
+            link.set_state(ConnectionState::Connecting, None);
+            let creds_result = load_creds(&keys, profile.as_deref(), expected_host);
+            let mut auth_failing = false;
 
-            if let Some((_, identity)) = creds_result {
-                match fetch_session_jwt(&env, &identity).await {
+            if let Some(identity) = creds_result {
+                match fetch_session_jwt(&env.server_host, env.server_port, &identity).await {
                     Ok(session_token) => {
-                        let conn = ws::WebSocketConnection::new(identity.node_id, session_token);
+                        let conn = ws::WebSocketConnection::new(
+                            identity.node_id,
+                            session_token,
+                            Arc::clone(&link),
+                            Arc::clone(&links),
+                            local_accounts.clone(),
+                        );
+                        let started = Instant::now();
                         tokio::select! {
                             res = conn.connect(Arc::clone(&env)) => {
+                                backoff.connection_ended(started.elapsed());
+                                if link.drain.is_stopping() {
+                                    info!("ws connection drained for shutdown");
+                                    link.set_state(ConnectionState::Disconnected, None);
+                                    break;
+                                }
                                 match res {
-                                    Ok(()) => warn!("ws connection ended, attempting reconnect"),
-                                    Err(err) => warn!("ws client error: {err}, attempting reconnect"),
+                                    Ok(()) => {
+                                        warn!("ws connection ended, attempting reconnect");
+                                        link.set_state(ConnectionState::Disconnected, None);
+                                    }
+                                    Err(err) => {
+                                        warn!("ws client error: {err}, attempting reconnect");
+                                        link.set_error(err);
+                                    }
                                 }
                             }
                             _ = shutdown.recv() => {
@@ -212,17 +653,32 @@ fn start_ws_connection(
                     }
                     Err(err) => {
                         warn!("failed to obtain node session token: {err}");
+                        auth_failing = err
+                            .chain()
+                            .filter_map(|cause| cause.downcast_ref::<RequestError>())
+                            .any(RequestError::is_auth_rejected);
+                        link.set_error(err);
                     }
                 }
             } else {
                 warn!("node identity not found");
                 info!("please login first");
+                auth_failing = true;
+                link.set_error("node identity not found");
             }
 
-            attempt = attempt.saturating_add(1);
-            let backoff_secs = 2u64.saturating_pow(attempt.min(4));
+            METRICS.reconnect_attempt();
+            systemd::watchdog();
+            let delay = backoff.next_delay();
+            link.set_backoff(backoff.attempt(), delay, auth_failing);
+            info!(
+                "reconnecting in {}ms (attempt {})",
+                delay.as_millis(),
+                backoff.attempt()
+            );
+
             tokio::select! {
-                _ = tokio::time::sleep(Duration::from_secs(backoff_secs)) => {},
+                _ = tokio::time::sleep(delay) => {},
                 _ = shutdown.recv() => {
                     info!("ws connection shutting down");
                     break;
@@ -247,6 +703,11 @@ struct VerifyResponse {
     access_token: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct RotateKeyResponse {
+    public_key: String,
+}
+
 #[derive(Debug)]
 struct LocalIdentity {
     private_key: String,
@@ -273,7 +734,7 @@ fn local_hostname() -> String {
         .unwrap_or_else(|| "unknown-host".to_string())
 }
 
-fn generate_http_endpoint(server_host: &str, server_port: u16) -> String {
+pub(crate) fn generate_http_endpoint(server_host: &str, server_port: u16) -> String {
     let server_host = server_host
         .split_once("://")
         .map(|(_, rest)| rest)
@@ -292,6 +753,8 @@ fn generate_http_endpoint(server_host: &str, server_port: u16) -> String {
 }
 
 async fn bootstrap_identity(
+    keys: &KeySettings,
+    profile: Option<&str>,
     server_host: &str,
     server_port: u16,
     pat_token: &str,
@@ -299,7 +762,7 @@ async fn bootstrap_identity(
     info!("token found: {}", mask_after_10(pat_token));
 
     let username = whoami::username()?;
-    let ts = TokenStore::new("phirepass", "agent", server_host)?;
+    let ts = open_token_store(keys, profile, server_host)?;
 
     // Reuse existing identity for the same server, so relogin does not create stale node rows.
     let identity = match ts.load() {
@@ -317,7 +780,7 @@ async fn bootstrap_identity(
     };
     let hostname = local_hostname();
 
-    let client = reqwest::Client::new();
+    let client = proxy::http_client()?;
     let base_url = generate_http_endpoint(server_host, server_port);
 
     let claim: ClaimResponse = post_with_pat(
@@ -336,8 +799,14 @@ async fn bootstrap_identity(
     .await
     .context("failed to claim node identity")?;
 
-    ts.save_identity(claim.node_id, identity.private_key, identity.public_key)
-        .context("failed to persist local node identity")?;
+    ts.save_identity(
+        claim.node_id,
+        identity.private_key,
+        identity.public_key,
+        server_host,
+        server_port,
+    )
+    .context("failed to persist local node identity")?;
 
     info!(
         "node identity bootstrap complete; node_id={}",
@@ -346,20 +815,51 @@ async fn bootstrap_identity(
     Ok(())
 }
 
-async fn fetch_session_jwt(env: &Env, state: &StoredState) -> anyhow::Result<SecretString> {
-    let base_url = generate_http_endpoint(&env.server_host, env.server_port);
-    let client = reqwest::Client::new();
+pub(crate) async fn fetch_session_jwt(
+    server_host: &str,
+    server_port: u16,
+    state: &StoredState,
+) -> anyhow::Result<SecretString> {
+    let base_url = generate_http_endpoint(server_host, server_port);
+    let client = proxy::http_client()?;
 
-    let challenge: ChallengeResponse = post_json(
+    let challenge = request_challenge(&client, &base_url, state.node_id).await?;
+    let signature = sign(&state.private_key, &challenge)?;
+
+    let verify: VerifyResponse = post_json(
         &client,
+        &format!("{}/api/nodes/auth/verify", base_url),
+        &json!({
+            "node_id": state.node_id,
+            "challenge": challenge,
+            "signature": signature,
+        }),
+    )
+    .await
+    .context("failed to verify auth challenge")?;
+
+    Ok(SecretString::from(verify.access_token))
+}
+
+async fn request_challenge(
+    client: &reqwest::Client,
+    base_url: &str,
+    node_id: Uuid,
+) -> anyhow::Result<String> {
+    let challenge: ChallengeResponse = post_json(
+        client,
         &format!("{}/api/nodes/auth/challenge", base_url),
-        &json!({ "node_id": state.node_id }),
+        &json!({ "node_id": node_id }),
     )
     .await
     .context("failed to request auth challenge")?;
 
+    Ok(challenge.challenge)
+}
+
+fn sign(private_key: &str, message: &str) -> anyhow::Result<String> {
     let private_key_bytes = URL_SAFE_NO_PAD
-        .decode(&state.private_key)
+        .decode(private_key)
         .context("invalid private key encoding")?;
 
     let private_key_bytes: [u8; 32] = private_key_bytes
@@ -367,22 +867,8 @@ async fn fetch_session_jwt(env: &Env, state: &StoredState) -> anyhow::Result<Sec
         .map_err(|_| anyhow::anyhow!("private key must decode to 32 bytes"))?;
 
     let signing_key = SigningKey::from_bytes(&private_key_bytes);
-    let signature = signing_key.sign(challenge.challenge.as_bytes());
-    let signature = URL_SAFE_NO_PAD.encode(signature.to_bytes());
-
-    let verify: VerifyResponse = post_json(
-        &client,
-        &format!("{}/api/nodes/auth/verify", base_url),
-        &json!({
-            "node_id": state.node_id,
-            "challenge": challenge.challenge,
-            "signature": signature,
-        }),
-    )
-    .await
-    .context("failed to verify auth challenge")?;
-
-    Ok(SecretString::from(verify.access_token))
+    let signature = signing_key.sign(message.as_bytes());
+    Ok(URL_SAFE_NO_PAD.encode(signature.to_bytes()))
 }
 
 async fn post_with_pat<T: for<'de> Deserialize<'de>>(
@@ -420,7 +906,11 @@ async fn parse_json_response<T: for<'de> Deserialize<'de>>(
             .and_then(|value| value.as_str())
             .unwrap_or("request failed");
 
-        anyhow::bail!("request failed ({}): {}", status, error);
+        return Err(RequestError {
+            status,
+            message: error.to_string(),
+        }
+        .into());
     }
 
     let parsed = serde_json::from_str::<T>(&body_text)
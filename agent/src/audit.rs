@@ -0,0 +1,320 @@
+use crate::error::AgentError;
+use crate::usage::{LinkUsage, SessionMeter, TransferMeter};
+use anyhow::Context;
+use log::{info, warn};
+use phirepass_common::protocol::Protocol;
+use phirepass_common::protocol::node::TransferUsage;
+use phirepass_common::time::now_millis;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, OnceLock};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+static AUDIT: OnceLock<Sender<Command>> = OnceLock::new();
+
+enum Command {
+    Record(Vec<u8>),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Append-only JSON lines file, rotated to `<path>.1` .. `<path>.<max_files>`
+/// once it would grow past `max_bytes`.
+struct AuditLog {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: u32,
+    file: File,
+    size: u64,
+}
+
+impl AuditLog {
+    fn open(path: &Path, max_bytes: u64, max_files: u32) -> std::io::Result<Self> {
+        let file = open_append(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_bytes,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    fn append(&mut self, line: &[u8]) -> std::io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        if self.max_files == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.max_files).rev() {
+                match std::fs::rename(self.rotated(n), self.rotated(n + 1)) {
+                    Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+                    _ => {}
+                }
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+        }
+
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn rotated(&self, n: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        path.into()
+    }
+}
+
+fn open_append(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(path)
+}
+
+/// Opens the audit log; without a path auditing stays off.
+pub(crate) fn init(path: Option<&str>, max_bytes: u64, max_files: u32) -> anyhow::Result<()> {
+    let Some(path) = path.filter(|path| !path.trim().is_empty()) else {
+        return Ok(());
+    };
+
+    let log = AuditLog::open(Path::new(path), max_bytes, max_files)
+        .with_context(|| format!("failed to open audit log {path}"))?;
+
+    // File writes and rotation run on their own thread, so records can be
+    // sent from the async workers without blocking them
+    let (tx, rx) = mpsc::channel();
+    std::thread::Builder::new()
+        .name("audit-log".into())
+        .spawn(move || run_writer(log, rx))
+        .context("failed to start the audit log writer")?;
+    info!("writing audit records to {path}");
+
+    let _ = AUDIT.set(tx);
+    Ok(())
+}
+
+fn run_writer(mut log: AuditLog, rx: Receiver<Command>) {
+    for command in rx {
+        match command {
+            Command::Record(line) => {
+                if let Err(err) = log.append(&line) {
+                    warn!(
+                        "failed to write audit record to {}: {err}",
+                        log.path.display()
+                    );
+                }
+            }
+            Command::Flush(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+/// Waits until every record sent so far is written, before the agent exits.
+pub(crate) async fn flush() {
+    let Some(audit) = AUDIT.get() else {
+        return;
+    };
+
+    let (done, written) = oneshot::channel();
+    if audit.send(Command::Flush(done)).is_ok() {
+        let _ = written.await;
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Record<'a> {
+    ts: u64,
+    event: &'a str,
+    cid: Uuid,
+    sid: u32,
+    protocol: &'static str,
+    username: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_ip: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    destination: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    completed: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+fn write(record: &Record) {
+    let Some(audit) = AUDIT.get() else {
+        return;
+    };
+
+    let mut line = match serde_json::to_vec(record) {
+        Ok(line) => line,
+        Err(err) => {
+            warn!("failed to encode audit record: {err}");
+            return;
+        }
+    };
+    line.push(b'\n');
+
+    if audit.send(Command::Record(line)).is_err() {
+        warn!("audit log writer has stopped; dropping record");
+    }
+}
+
+/// Who a tunnel belongs to; every record of the tunnel carries it.
+#[derive(Debug, Clone)]
+pub(crate) struct Access {
+    cid: Uuid,
+    sid: u32,
+    protocol: Protocol,
+    username: String,
+    client_ip: Option<String>,
+    usage: Arc<SessionMeter>,
+}
+
+impl Access {
+    pub(crate) fn new(
+        cid: Uuid,
+        sid: u32,
+        protocol: Protocol,
+        username: &str,
+        client_ip: Option<String>,
+        link: &Arc<LinkUsage>,
+    ) -> Self {
+        Self {
+            cid,
+            sid,
+            protocol,
+            username: username.to_string(),
+            client_ip,
+            usage: SessionMeter::new(username, protocol, link),
+        }
+    }
+
+    /// Bytes and transfers of the tunnel, reported when it closes.
+    pub(crate) fn usage(&self) -> &Arc<SessionMeter> {
+        &self.usage
+    }
+
+    fn record<'a>(&'a self, event: &'a str) -> Record<'a> {
+        Record {
+            ts: now_millis(),
+            event,
+            cid: self.cid,
+            sid: self.sid,
+            protocol: match self.protocol {
+                Protocol::SSH => "ssh",
+                Protocol::SFTP => "sftp",
+            },
+            username: &self.username,
+            client_ip: self.client_ip.as_deref(),
+            path: None,
+            destination: None,
+            bytes: None,
+            completed: None,
+            error: None,
+        }
+    }
+
+    pub(crate) fn opened(&self) {
+        write(&self.record("tunnel_opened"));
+    }
+
+    pub(crate) fn closed(&self) {
+        write(&self.record("tunnel_closed"));
+    }
+
+    pub(crate) fn failed(&self, error: &AgentError) {
+        let event = match error {
+            AgentError::AuthFailed(_) => "auth_failed",
+            _ => "tunnel_failed",
+        };
+
+        write(&Record {
+            error: Some(error.to_string()),
+            ..self.record(event)
+        });
+    }
+
+    /// An SFTP operation on `path`: `sftp_list` or `sftp_delete`.
+    pub(crate) fn sftp(&self, event: &str, path: &str) {
+        write(&Record {
+            path: Some(path),
+            ..self.record(event)
+        });
+    }
+
+    /// An SFTP operation from `path` into `destination`: `sftp_copy` or
+    /// `sftp_extract`.
+    pub(crate) fn sftp_to(&self, event: &str, path: &str, destination: &str) {
+        write(&Record {
+            path: Some(path),
+            destination: Some(destination),
+            ..self.record(event)
+        });
+    }
+
+    /// Meters an upload or download of `path`; it is audited with the bytes
+    /// actually moved once the transfer finishes or is abandoned.
+    pub(crate) fn transfer(&self, upload: bool, path: &str) -> TransferMeter {
+        self.usage.transfer(upload, path).audited(self.clone())
+    }
+
+    pub(crate) fn transferred(&self, transfer: &TransferUsage) {
+        let event = if transfer.upload {
+            "sftp_upload"
+        } else {
+            "sftp_download"
+        };
+
+        write(&Record {
+            path: Some(&transfer.path),
+            bytes: Some(transfer.bytes),
+            completed: Some(transfer.completed),
+            ..self.record(event)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_and_keeps_max_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let mut log = AuditLog::open(&path, 10, 2).unwrap();
+
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            log.append(line.as_bytes()).unwrap();
+        }
+
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("audit.log"), "fourth\n");
+        assert_eq!(read("audit.log.1"), "third\n");
+        assert_eq!(read("audit.log.2"), "second\n");
+        assert!(!dir.path().join("audit.log.3").exists());
+    }
+}
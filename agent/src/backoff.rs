@@ -0,0 +1,137 @@
+use crate::env::Env;
+use rand::Rng;
+use std::time::Duration;
+
+/// Exponential reconnect backoff. With full jitter every delay is drawn
+/// uniformly from zero up to the current ceiling, so agents dropped by the
+/// same server restart spread their reconnects instead of retrying in
+/// lockstep.
+#[derive(Debug, Clone)]
+pub(crate) struct Backoff {
+    initial: Duration,
+    max: Duration,
+    multiplier: f64,
+    jitter: bool,
+    reset_after: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub(crate) fn new(
+        initial: Duration,
+        max: Duration,
+        multiplier: f64,
+        jitter: bool,
+        reset_after: Duration,
+    ) -> Self {
+        Self {
+            initial,
+            max: max.max(initial),
+            multiplier: multiplier.max(1.0),
+            jitter,
+            reset_after,
+            attempt: 0,
+        }
+    }
+
+    pub(crate) fn from_env(env: &Env) -> Self {
+        Self::new(
+            Duration::from_millis(env.reconnect_initial_delay_ms),
+            Duration::from_millis(env.reconnect_max_delay_ms),
+            env.reconnect_multiplier,
+            env.reconnect_jitter,
+            Duration::from_secs(env.reconnect_reset_after_secs),
+        )
+    }
+
+    pub(crate) fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// Delay before the next reconnect; advances the attempt counter.
+    pub(crate) fn next_delay(&mut self) -> Duration {
+        self.next_delay_with(&mut rand::thread_rng())
+    }
+
+    fn next_delay_with(&mut self, rng: &mut impl Rng) -> Duration {
+        let ceiling = self.ceiling();
+        self.attempt = self.attempt.saturating_add(1);
+
+        if self.jitter {
+            Duration::from_millis(rng.gen_range(0..=ceiling.as_millis() as u64))
+        } else {
+            ceiling
+        }
+    }
+
+    /// Starts over from the initial delay when the last connection stayed
+    /// up for at least the reset period.
+    pub(crate) fn connection_ended(&mut self, lasted: Duration) {
+        if lasted >= self.reset_after {
+            self.attempt = 0;
+        }
+    }
+
+    fn ceiling(&self) -> Duration {
+        let factor = self.multiplier.powi(self.attempt.min(64) as i32);
+        let millis = self.initial.as_millis() as f64 * factor;
+        let max = self.max.as_millis() as f64;
+        Duration::from_millis(millis.min(max) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    fn backoff(jitter: bool) -> Backoff {
+        Backoff::new(
+            Duration::from_secs(1),
+            Duration::from_secs(30),
+            2.0,
+            jitter,
+            Duration::from_secs(60),
+        )
+    }
+
+    #[test]
+    fn grows_exponentially_up_to_max() {
+        let mut backoff = backoff(false);
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let delays: Vec<_> = (0..7)
+            .map(|_| backoff.next_delay_with(&mut rng).as_secs())
+            .collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 30, 30]);
+        assert_eq!(backoff.attempt(), 7);
+    }
+
+    #[test]
+    fn full_jitter_stays_below_ceiling() {
+        let mut backoff = backoff(true);
+        let mut rng = StdRng::seed_from_u64(7);
+
+        for _ in 0..10 {
+            let ceiling = backoff.ceiling();
+            assert!(backoff.next_delay_with(&mut rng) <= ceiling);
+        }
+    }
+
+    #[test]
+    fn resets_after_stable_connection() {
+        let mut backoff = backoff(false);
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..4 {
+            backoff.next_delay_with(&mut rng);
+        }
+
+        backoff.connection_ended(Duration::from_secs(5));
+        assert_eq!(backoff.attempt(), 4);
+
+        backoff.connection_ended(Duration::from_secs(60));
+        assert_eq!(backoff.attempt(), 0);
+        assert_eq!(backoff.next_delay_with(&mut rng), Duration::from_secs(1));
+    }
+}
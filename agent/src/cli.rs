@@ -4,6 +4,10 @@ use std::path::PathBuf;
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub(crate) struct Cli {
+    /// Path to the agent config file (defaults to PHIREPASS_CONFIG or /etc/phirepass/agent.toml)
+    #[arg(long, global = true, value_name = "PATH")]
+    pub(crate) config: Option<PathBuf>,
+
     #[command(subcommand)]
     pub(crate) command: Option<Commands>,
 }
@@ -14,8 +18,18 @@ pub(crate) enum Commands {
     Start(StartArgs),
     /// Login
     Login(LoginArgs),
-    /// Logout and delete local node identity
+    /// Logout, revoke the node on the server and delete local node identity
     Logout(LogoutArgs),
+    /// Replace the node keypair with a new one
+    RotateKey(RotateKeyArgs),
+    /// Check connectivity to the server and the local sshd step by step
+    Doctor(DoctorArgs),
+    /// Inspect the agent configuration
+    #[command(subcommand)]
+    Config(ConfigCommands),
+    /// Manage the systemd service
+    #[command(subcommand)]
+    Service(ServiceCommands),
     /// Print version information
     Version,
 }
@@ -26,6 +40,10 @@ pub(crate) struct StartArgs {
     #[arg(long, value_name = "PATH")]
     pub token_from_file: Option<PathBuf>,
 
+    /// Identity profile to connect with; repeat to connect to several servers at once
+    #[arg(long = "profile", value_name = "PROFILE")]
+    pub profiles: Vec<String>,
+
     /// Server host to connect to
     #[arg(long)]
     pub server_host: Option<String>,
@@ -35,8 +53,31 @@ pub(crate) struct StartArgs {
     pub server_port: Option<u16>,
 }
 
+#[derive(Args, Debug)]
+pub(crate) struct DoctorArgs {
+    /// Identity profile to check
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    /// Server host to connect to
+    #[arg(long)]
+    pub server_host: Option<String>,
+
+    /// Server port to connect to
+    #[arg(long)]
+    pub server_port: Option<u16>,
+
+    /// Local user for the test login to SSH_HOST:SSH_PORT; skipped when unset
+    #[arg(long)]
+    pub ssh_user: Option<String>,
+}
+
 #[derive(Args, Debug)]
 pub(crate) struct LoginArgs {
+    /// Identity profile to use (defaults to one named after the server host)
+    #[arg(long)]
+    pub profile: Option<String>,
+
     /// Read token from a mounted file (recommended for CI/K8s/Docker secrets)
     #[arg(long, value_name = "PATH")]
     pub from_file: Option<PathBuf>,
@@ -46,27 +87,94 @@ pub(crate) struct LoginArgs {
     pub from_stdin: bool,
 
     /// Server host to connect to
-    #[cfg_attr(debug_assertions, arg(long, default_value = "localhost"))]
-    #[cfg_attr(not(debug_assertions), arg(long, default_value = "api.phirepass.com"))]
-    pub server_host: String,
+    #[arg(long)]
+    pub server_host: Option<String>,
 
     /// Server port to connect to
-    #[cfg_attr(debug_assertions, arg(long, default_value_t = 8080))]
-    #[cfg_attr(not(debug_assertions), arg(long, default_value_t = 443))]
-    pub server_port: u16,
+    #[arg(long)]
+    pub server_port: Option<u16>,
 }
 
 #[derive(Args, Debug)]
 pub(crate) struct LogoutArgs {
+    /// Identity profile to use (defaults to one named after the server host)
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    /// Only delete the local identity, leaving the node active on the server
+    #[arg(long)]
+    pub local_only: bool,
+
     /// Server host to connect to
-    #[cfg_attr(debug_assertions, arg(long, default_value = "localhost"))]
-    #[cfg_attr(not(debug_assertions), arg(long, default_value = "api.phirepass.com"))]
-    pub server_host: String,
+    #[arg(long)]
+    pub server_host: Option<String>,
 
     /// Server port to connect to
-    #[cfg_attr(debug_assertions, arg(long, default_value_t = 8080))]
-    #[cfg_attr(not(debug_assertions), arg(long, default_value_t = 443))]
-    pub server_port: u16,
+    #[arg(long)]
+    pub server_port: Option<u16>,
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct RotateKeyArgs {
+    /// Identity profile to use (defaults to one named after the server host)
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    /// Server host to connect to
+    #[arg(long)]
+    pub server_host: Option<String>,
+
+    /// Server port to connect to
+    #[arg(long)]
+    pub server_port: Option<u16>,
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum ConfigCommands {
+    /// Print the effective configuration with secrets redacted
+    Check,
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum ServiceCommands {
+    /// Write a hardened systemd unit that runs `start`
+    Install(ServiceInstallArgs),
+    /// Stop, disable and remove the systemd unit
+    Uninstall(ServiceUninstallArgs),
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct ServiceInstallArgs {
+    /// User the service runs as (defaults to the current user)
+    #[arg(long)]
+    pub user: Option<String>,
+
+    /// Identity profile to connect with; repeat to connect to several servers at once
+    #[arg(long = "profile", value_name = "PROFILE")]
+    pub profiles: Vec<String>,
+
+    /// Unit name
+    #[arg(long, default_value = "phirepass-agent")]
+    pub name: String,
+
+    /// Directory the unit file is written to
+    #[arg(long, value_name = "PATH", default_value = "/etc/systemd/system")]
+    pub unit_dir: PathBuf,
+
+    /// Enable and start the service right away
+    #[arg(long)]
+    pub enable: bool,
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct ServiceUninstallArgs {
+    /// Unit name
+    #[arg(long, default_value = "phirepass-agent")]
+    pub name: String,
+
+    /// Directory the unit file was written to
+    #[arg(long, value_name = "PATH", default_value = "/etc/systemd/system")]
+    pub unit_dir: PathBuf,
 }
 
 pub(crate) fn parse() -> Cli {
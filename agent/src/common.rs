@@ -61,6 +61,61 @@ pub fn send_requires_username_error(sender: &Sender<Frame>, cid: Uuid, msg_id: O
     );
 }
 
+#[inline]
+pub fn send_session_limit_error(
+    sender: &Sender<Frame>,
+    cid: Uuid,
+    msg_id: Option<u32>,
+    message: String,
+) {
+    send_frame_data(
+        sender,
+        NodeFrameData::WebFrame {
+            id: WebFrameId::ConnectionId(cid),
+            frame: WebFrameData::Error {
+                kind: FrameError::SessionLimit,
+                message,
+                msg_id,
+            },
+        },
+    );
+}
+
+#[inline]
+pub fn send_account_denied_error(
+    sender: &Sender<Frame>,
+    cid: Uuid,
+    msg_id: Option<u32>,
+    username: &str,
+) {
+    send_frame_data(
+        sender,
+        NodeFrameData::WebFrame {
+            id: WebFrameId::ConnectionId(cid),
+            frame: WebFrameData::Error {
+                kind: FrameError::AccountDenied,
+                message: format!("You are not allowed to log in as {username} on this node"),
+                msg_id,
+            },
+        },
+    );
+}
+
+#[inline]
+pub fn send_draining_error(sender: &Sender<Frame>, cid: Uuid, msg_id: Option<u32>) {
+    send_frame_data(
+        sender,
+        NodeFrameData::WebFrame {
+            id: WebFrameId::ConnectionId(cid),
+            frame: WebFrameData::Error {
+                kind: FrameError::Draining,
+                message: "The node is draining and does not accept new sessions".to_string(),
+                msg_id,
+            },
+        },
+    );
+}
+
 #[inline]
 pub fn send_requires_password_error(sender: &Sender<Frame>, cid: Uuid, msg_id: Option<u32>) {
     send_frame_data(
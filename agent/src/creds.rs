@@ -1,8 +1,14 @@
 use anyhow::Context;
+use argon2::{Algorithm, Argon2, Params, Version};
 use base64::Engine;
 use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
 use directories::ProjectDirs;
 use log::{debug, info, warn};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use secrecy::{ExposeSecret, SecretString};
 use serde::{Deserialize, Serialize};
 use std::{
     fs,
@@ -13,35 +19,122 @@ use uuid::Uuid;
 
 #[derive(Debug)]
 pub struct TokenStore {
+    profile: String,
     service: String,
     state_path: PathBuf,
+    legacy_path: PathBuf,
+    keys: KeySettings,
 }
 
+/// How the private key is kept at rest, taken from the `IDENTITY_KEY_FILE`,
+/// `IDENTITY_PASSPHRASE` and `IDENTITY_PASSPHRASE_FILE` settings.
+#[derive(Debug, Clone, Default)]
+pub struct KeySettings {
+    pub passphrase: Option<SecretString>,
+    pub passphrase_file: Option<PathBuf>,
+    pub key_file: Option<PathBuf>,
+}
+
+const KDF_ARGON2ID: &str = "argon2id";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct StoredState {
     pub node_id: Uuid,
+    /// Plain key material. Empty on disk when the key is encrypted or kept
+    /// in an external file; `load` always fills it in.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub private_key: String,
     pub public_key: String,
     #[serde(default)]
     pub server_host: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted_private_key: Option<EncryptedKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private_key_file: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending: Option<PendingKey>,
+}
+
+/// A rotated key saved before the server is asked to switch to it, so a
+/// failed write or a crash cannot leave the server holding a key the agent
+/// does not have. Kept at rest the same way as the active key.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendingKey {
+    pub public_key: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub private_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted_private_key: Option<EncryptedKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private_key_file: Option<PathBuf>,
+}
+
+/// Private key sealed with ChaCha20-Poly1305 under an Argon2id derived key.
+/// The node id is bound as associated data.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EncryptedKey {
+    pub kdf: String,
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+    pub salt: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+enum KeyProtection {
+    Plain,
+    Passphrase(SecretString),
+    KeyFile(PathBuf),
+}
+
+impl KeyProtection {
+    fn from_settings(keys: &KeySettings) -> anyhow::Result<Self> {
+        let passphrase = read_passphrase(keys)?;
+
+        match (keys.key_file.clone(), passphrase) {
+            (Some(_), Some(_)) => {
+                anyhow::bail!("IDENTITY_KEY_FILE and IDENTITY_PASSPHRASE cannot be used together")
+            }
+            (Some(path), None) => Ok(Self::KeyFile(path)),
+            (None, Some(passphrase)) => Ok(Self::Passphrase(passphrase)),
+            (None, None) => Ok(Self::Plain),
+        }
+    }
 }
 
 impl TokenStore {
-    pub fn new(org: &str, app: &str, service: &str) -> std::io::Result<Self> {
+    /// Opens the identity stored under `profile`. When `service` is not
+    /// empty, loading fails unless the identity belongs to that server.
+    pub fn new(
+        org: &str,
+        app: &str,
+        profile: &str,
+        service: &str,
+        keys: KeySettings,
+    ) -> std::io::Result<Self> {
         let proj = ProjectDirs::from("com", org, app)
             .ok_or_else(|| std::io::Error::other("No project dirs"))?;
 
         let dir = proj.data_local_dir();
-        fs::create_dir_all(dir)?;
+        let identities = dir.join("identities");
+        fs::create_dir_all(&identities)?;
 
-        debug!(
-            "creating identity store in {}",
-            dir.join("state.json").display()
-        );
+        let profile = profile_file_name(profile)?;
+        let state_path = identities.join(format!("{profile}.json"));
+
+        debug!("creating identity store in {}", state_path.display());
 
         Ok(Self {
+            profile,
             service: service.to_string(),
-            state_path: dir.join("state.json"),
+            state_path,
+            legacy_path: dir.join("state.json"),
+            keys,
         })
     }
 
@@ -50,23 +143,110 @@ impl TokenStore {
         node_id: Uuid,
         private_key: String,
         public_key: String,
+        server_host: &str,
+        server_port: u16,
     ) -> anyhow::Result<()> {
+        let sealed = seal_private_key(&self.keys, node_id, private_key, false)?;
         let state = StoredState {
             node_id,
-            private_key,
             public_key,
-            server_host: self.service.clone(),
+            server_host: server_host.to_string(),
+            server_port: Some(server_port),
+            private_key: sealed.private_key,
+            encrypted_private_key: sealed.encrypted_private_key,
+            private_key_file: sealed.private_key_file,
+            pending: None,
         };
 
         self.save_state(&state)
     }
 
+    /// Stores the next key next to the active one. Fails, and so stops a
+    /// rotation before it starts, when the key cannot be written.
+    pub fn save_pending_identity(
+        &self,
+        private_key: String,
+        public_key: String,
+    ) -> anyhow::Result<()> {
+        let mut state = self.load_state()?.context("no stored identity to rotate")?;
+
+        let sealed = seal_private_key(&self.keys, state.node_id, private_key, true)?;
+        state.pending = Some(PendingKey {
+            public_key,
+            private_key: sealed.private_key,
+            encrypted_private_key: sealed.encrypted_private_key,
+            private_key_file: sealed.private_key_file,
+        });
+
+        self.save_state(&state)
+    }
+
+    /// The pending key as `(private_key, public_key)`, if a rotation has not
+    /// been completed or discarded.
+    pub fn load_pending_identity(&self) -> anyhow::Result<Option<(String, String)>> {
+        let Some(state) = self.load_state()? else {
+            return Ok(None);
+        };
+        let Some(pending) = state.pending else {
+            return Ok(None);
+        };
+
+        let private_key = unlock_private_key(
+            &self.keys,
+            state.node_id,
+            &pending.private_key,
+            pending.encrypted_private_key.as_ref(),
+            pending.private_key_file.as_deref(),
+        )?;
+        validate_b64_len(&private_key, 32, "pending private_key")?;
+
+        Ok(Some((private_key, pending.public_key)))
+    }
+
+    /// Makes the pending key the active one, once the server uses it.
+    pub fn activate_pending_identity(
+        &self,
+        server_host: &str,
+        server_port: u16,
+    ) -> anyhow::Result<()> {
+        let state = self.load_state()?.context("no stored identity to rotate")?;
+        let (private_key, public_key) = self
+            .load_pending_identity()?
+            .context("no pending node key to activate")?;
+
+        self.save_identity(
+            state.node_id,
+            private_key,
+            public_key,
+            server_host,
+            server_port,
+        )?;
+        remove_pending_key_file(state.pending.as_ref());
+        Ok(())
+    }
+
+    /// Drops the pending key after the server turned out to keep the
+    /// active one.
+    pub fn discard_pending_identity(&self) -> anyhow::Result<()> {
+        let Some(mut state) = self.load_state()? else {
+            return Ok(());
+        };
+
+        let pending = state.pending.take();
+        self.save_state(&state)?;
+        remove_pending_key_file(pending.as_ref());
+        Ok(())
+    }
+
     pub fn load(&self) -> anyhow::Result<StoredState> {
         debug!("loading node identity");
 
-        let state = self.load_state()?.unwrap_or_default();
+        let mut state = self.load_state()?.unwrap_or_default();
 
-        if !state.server_host.is_empty() && state.server_host != self.service {
+        if !self.service.is_empty()
+            && !state.server_host.is_empty()
+            && state.server_host != self.service
+        {
             anyhow::bail!(
                 "Server mismatch: identity is for '{}' but attempting to connect to '{}'",
                 state.server_host,
@@ -78,6 +258,14 @@ impl TokenStore {
             anyhow::bail!("stored node_id is nil; run login first")
         }
 
+        state.private_key = unlock_private_key(
+            &self.keys,
+            state.node_id,
+            &state.private_key,
+            state.encrypted_private_key.as_ref(),
+            state.private_key_file.as_deref(),
+        )?;
+
         validate_b64_len(&state.private_key, 32, "private_key")?;
         validate_b64_len(&state.public_key, 32, "public_key")?;
 
@@ -89,7 +277,44 @@ impl TokenStore {
     }
 
     fn load_state(&self) -> anyhow::Result<Option<StoredState>> {
-        self.load_state_from_file()
+        match self.load_state_from_file()? {
+            Some(state) => Ok(Some(state)),
+            None => self.migrate_legacy_state(),
+        }
+    }
+
+    /// Moves the single `state.json` used before profiles existed into the
+    /// profile named after its server.
+    fn migrate_legacy_state(&self) -> anyhow::Result<Option<StoredState>> {
+        let bytes = match fs::read(&self.legacy_path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let Ok(state) = serde_json::from_slice::<StoredState>(&bytes) else {
+            warn!("ignoring unreadable legacy state {:?}", self.legacy_path);
+            return Ok(None);
+        };
+
+        let legacy_profile = profile_file_name(&state.server_host).ok();
+        if !state.server_host.is_empty() && legacy_profile.as_deref() != Some(&self.profile) {
+            return Ok(None);
+        }
+
+        self.save_state(&state)?;
+        if let Err(e) = fs::remove_file(&self.legacy_path) {
+            warn!(
+                "failed to remove legacy state {:?}: {}",
+                self.legacy_path, e
+            );
+        }
+
+        info!(
+            "migrated legacy identity {:?} to profile {}",
+            self.legacy_path, self.profile
+        );
+        Ok(Some(state))
     }
 
     fn save_state(&self, state: &StoredState) -> anyhow::Result<()> {
@@ -123,6 +348,242 @@ impl TokenStore {
     }
 }
 
+/// Profiles default to the server host, so anything outside a conservative
+/// file name alphabet (e.g. the `:` of a port) is replaced.
+fn profile_file_name(profile: &str) -> std::io::Result<String> {
+    let name: String = profile
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    if name.is_empty() || name.chars().all(|c| c == '.') {
+        return Err(std::io::Error::other(format!(
+            "invalid profile name '{}'",
+            profile
+        )));
+    }
+
+    Ok(name)
+}
+
+struct SealedKey {
+    private_key: String,
+    encrypted_private_key: Option<EncryptedKey>,
+    private_key_file: Option<PathBuf>,
+}
+
+/// Protects `private_key` as configured. A pending key goes to
+/// `<IDENTITY_KEY_FILE>.pending` so the active key file stays intact.
+fn seal_private_key(
+    keys: &KeySettings,
+    node_id: Uuid,
+    private_key: String,
+    pending: bool,
+) -> anyhow::Result<SealedKey> {
+    let mut sealed = SealedKey {
+        private_key: String::new(),
+        encrypted_private_key: None,
+        private_key_file: None,
+    };
+
+    match KeyProtection::from_settings(keys)? {
+        KeyProtection::Plain => sealed.private_key = private_key,
+        KeyProtection::Passphrase(passphrase) => {
+            info!("encrypting node private key with passphrase");
+            sealed.encrypted_private_key = Some(encrypt_private_key(
+                &private_key,
+                &passphrase,
+                node_id.as_bytes(),
+                &Params::default(),
+            )?);
+        }
+        KeyProtection::KeyFile(path) => {
+            let path = if pending {
+                pending_key_path(&path)
+            } else {
+                path
+            };
+            info!("storing node private key in {}", path.display());
+            write_key_file(&path, &private_key)?;
+            sealed.private_key_file = Some(path);
+        }
+    }
+
+    Ok(sealed)
+}
+
+fn pending_key_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".pending");
+    PathBuf::from(name)
+}
+
+fn remove_pending_key_file(pending: Option<&PendingKey>) {
+    if let Some(path) = pending.and_then(|pending| pending.private_key_file.as_ref())
+        && let Err(err) = fs::remove_file(path)
+        && err.kind() != std::io::ErrorKind::NotFound
+    {
+        warn!(
+            "failed to remove pending key file {}: {err}",
+            path.display()
+        );
+    }
+}
+
+fn unlock_private_key(
+    keys: &KeySettings,
+    node_id: Uuid,
+    private_key: &str,
+    encrypted: Option<&EncryptedKey>,
+    key_file: Option<&Path>,
+) -> anyhow::Result<String> {
+    if let Some(encrypted) = encrypted {
+        let passphrase = read_passphrase(keys)?.context(
+            "node private key is encrypted; set IDENTITY_PASSPHRASE or IDENTITY_PASSPHRASE_FILE",
+        )?;
+        return decrypt_private_key(encrypted, &passphrase, node_id.as_bytes());
+    }
+
+    if let Some(path) = key_file {
+        let key = fs::read_to_string(path)
+            .with_context(|| format!("failed to read private key from {}", path.display()))?;
+        return Ok(key.trim().to_string());
+    }
+
+    Ok(private_key.to_string())
+}
+
+fn read_passphrase(keys: &KeySettings) -> anyhow::Result<Option<SecretString>> {
+    if let Some(passphrase) = &keys.passphrase {
+        return Ok(Some(passphrase.clone()));
+    }
+
+    if let Some(path) = &keys.passphrase_file {
+        let passphrase = fs::read_to_string(path)
+            .with_context(|| format!("failed to read passphrase file {}", path.display()))?;
+        let passphrase = passphrase.trim_end_matches(['\r', '\n']);
+        if passphrase.is_empty() {
+            anyhow::bail!("passphrase file {} is empty", path.display());
+        }
+        return Ok(Some(SecretString::from(passphrase)));
+    }
+
+    Ok(None)
+}
+
+fn derive_key(passphrase: &SecretString, salt: &[u8], params: &Params) -> anyhow::Result<[u8; 32]> {
+    let params = Params::new(params.m_cost(), params.t_cost(), params.p_cost(), Some(32))
+        .map_err(|err| anyhow::anyhow!("invalid kdf parameters: {err}"))?;
+
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.expose_secret().as_bytes(), salt, &mut key)
+        .map_err(|err| anyhow::anyhow!("failed to derive key: {err}"))?;
+
+    Ok(key)
+}
+
+fn encrypt_private_key(
+    private_key: &str,
+    passphrase: &SecretString,
+    aad: &[u8],
+    params: &Params,
+) -> anyhow::Result<EncryptedKey> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+
+    let key = derive_key(passphrase, &salt, params)?;
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&key))
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: private_key.as_bytes(),
+                aad,
+            },
+        )
+        .map_err(|_| anyhow::anyhow!("failed to encrypt private key"))?;
+
+    Ok(EncryptedKey {
+        kdf: KDF_ARGON2ID.to_string(),
+        m_cost: params.m_cost(),
+        t_cost: params.t_cost(),
+        p_cost: params.p_cost(),
+        salt: URL_SAFE_NO_PAD.encode(salt),
+        nonce: URL_SAFE_NO_PAD.encode(nonce),
+        ciphertext: URL_SAFE_NO_PAD.encode(ciphertext),
+    })
+}
+
+fn decrypt_private_key(
+    encrypted: &EncryptedKey,
+    passphrase: &SecretString,
+    aad: &[u8],
+) -> anyhow::Result<String> {
+    if encrypted.kdf != KDF_ARGON2ID {
+        anyhow::bail!("unsupported key derivation function {}", encrypted.kdf);
+    }
+
+    let decode = |value: &str, field: &str| {
+        URL_SAFE_NO_PAD
+            .decode(value)
+            .with_context(|| format!("failed to decode {}", field))
+    };
+
+    let salt = decode(&encrypted.salt, "salt")?;
+    let nonce = decode(&encrypted.nonce, "nonce")?;
+    let ciphertext = decode(&encrypted.ciphertext, "ciphertext")?;
+
+    if nonce.len() != NONCE_LEN {
+        anyhow::bail!(
+            "nonce decoded to {} bytes, expected {}",
+            nonce.len(),
+            NONCE_LEN
+        );
+    }
+
+    let params = Params::new(encrypted.m_cost, encrypted.t_cost, encrypted.p_cost, None)
+        .map_err(|err| anyhow::anyhow!("invalid kdf parameters: {err}"))?;
+    let key = derive_key(passphrase, &salt, &params)?;
+
+    let plaintext = ChaCha20Poly1305::new(Key::from_slice(&key))
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: &ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| anyhow::anyhow!("failed to decrypt private key; wrong passphrase?"))?;
+
+    String::from_utf8(plaintext).context("decrypted private key is not valid utf-8")
+}
+
+/// Keeps an existing identical key file untouched, so read-only secret
+/// mounts work as long as the key does not change.
+fn write_key_file(path: &Path, private_key: &str) -> anyhow::Result<()> {
+    if let Ok(existing) = fs::read_to_string(path)
+        && existing.trim() == private_key
+    {
+        return Ok(());
+    }
+
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+
+    persist(dir, path, private_key.as_bytes())
+}
+
 fn validate_b64_len(value: &str, expected_len: usize, field: &str) -> anyhow::Result<()> {
     let decoded = URL_SAFE_NO_PAD
         .decode(value)
@@ -155,6 +616,10 @@ fn atomic_write(path: &Path, bytes: &[u8]) -> anyhow::Result<()> {
         }
     }
 
+    persist(dir, path, bytes)
+}
+
+fn persist(dir: &Path, path: &Path, bytes: &[u8]) -> anyhow::Result<()> {
     let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
     tmp.write_all(bytes)?;
     tmp.as_file().sync_all()?;
@@ -174,3 +639,74 @@ fn atomic_write(path: &Path, bytes: &[u8]) -> anyhow::Result<()> {
 fn io_other<E: std::fmt::Display>(e: E) -> std::io::Error {
     std::io::Error::other(e.to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Small parameters keep the tests fast; production uses Params::default()
+    fn test_params() -> Params {
+        Params::new(64, 1, 1, None).unwrap()
+    }
+
+    #[test]
+    fn profile_names_are_file_safe() {
+        assert_eq!(
+            profile_file_name("api.phirepass.com").unwrap(),
+            "api.phirepass.com"
+        );
+        assert_eq!(
+            profile_file_name("localhost:8080").unwrap(),
+            "localhost_8080"
+        );
+        assert_eq!(profile_file_name("../etc").unwrap(), ".._etc");
+        assert!(profile_file_name("..").is_err());
+        assert!(profile_file_name(" ").is_err());
+    }
+
+    #[test]
+    fn encrypted_key_round_trips() {
+        let passphrase = SecretString::from("correct horse");
+        let aad = Uuid::new_v4();
+
+        let encrypted =
+            encrypt_private_key("private-key", &passphrase, aad.as_bytes(), &test_params())
+                .unwrap();
+
+        assert_ne!(encrypted.ciphertext, "private-key");
+        assert_eq!(
+            decrypt_private_key(&encrypted, &passphrase, aad.as_bytes()).unwrap(),
+            "private-key"
+        );
+    }
+
+    #[test]
+    fn wrong_passphrase_is_rejected() {
+        let aad = Uuid::new_v4();
+        let encrypted = encrypt_private_key(
+            "private-key",
+            &SecretString::from("correct horse"),
+            aad.as_bytes(),
+            &test_params(),
+        )
+        .unwrap();
+
+        let result = decrypt_private_key(&encrypted, &SecretString::from("wrong"), aad.as_bytes());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn encrypted_key_is_bound_to_node_id() {
+        let passphrase = SecretString::from("correct horse");
+        let encrypted = encrypt_private_key(
+            "private-key",
+            &passphrase,
+            Uuid::new_v4().as_bytes(),
+            &test_params(),
+        )
+        .unwrap();
+
+        let result = decrypt_private_key(&encrypted, &passphrase, Uuid::new_v4().as_bytes());
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,410 @@
+use crate::agent::{
+    connection_targets, fetch_session_jwt, generate_http_endpoint, open_token_store,
+};
+use crate::creds::{KeySettings, StoredState};
+use crate::env::{self, Env};
+use crate::error::RequestError;
+use crate::ssh::auth::SSHAuthMethod;
+use crate::ssh::connection::{SSHConfig, SSHConfigAuth, SSHConnection};
+use crate::{proxy, tls, ws};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use ed25519_dalek::SigningKey;
+use phirepass_common::env::Mode;
+use rustls::pki_types::ServerName;
+use serde::Deserialize;
+use std::future::Future;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+const STEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Collects the outcome of each step and prints it as it goes.
+#[derive(Default)]
+struct Report {
+    failed: usize,
+}
+
+impl Report {
+    fn pass(&mut self, step: &str, detail: impl AsRef<str>) {
+        println!("[PASS] {step:<10} {}", detail.as_ref());
+    }
+
+    fn fail(&mut self, step: &str, error: impl std::fmt::Display, hint: impl AsRef<str>) {
+        self.failed += 1;
+        println!("[FAIL] {step:<10} {error:#}");
+        println!("       {:<10} hint: {}", "", hint.as_ref());
+    }
+
+    fn skip(&mut self, step: &str, reason: impl AsRef<str>) {
+        println!("[SKIP] {step:<10} {}", reason.as_ref());
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct VersionResponse {
+    version: String,
+}
+
+/// Walks through everything the agent needs to come online and prints a
+/// report. Returns `false` when any step failed.
+pub(crate) async fn run(
+    config: Env,
+    profile: Option<String>,
+    ssh_user: Option<String>,
+) -> anyhow::Result<bool> {
+    let profiles: Vec<_> = profile.iter().cloned().collect();
+    let mut report = Report::default();
+
+    let env = match connection_targets(&config, &profiles) {
+        Ok(mut targets) => targets.remove(0).1,
+        Err(err) => {
+            report.fail(
+                "identity",
+                err,
+                "run `phirepass-agent login --profile <name>` first",
+            );
+            return Ok(false);
+        }
+    };
+
+    let server_host = env
+        .server_host
+        .split_once("://")
+        .map(|(_, rest)| rest)
+        .unwrap_or(env.server_host.as_str());
+    let server_port = env.server_port;
+
+    println!(
+        "phirepass agent {} checking {server_host}:{server_port} ({} mode)",
+        env::version(),
+        env.mode
+    );
+
+    let network = check_network(&mut report, &env, server_host, server_port).await;
+
+    // A named profile may belong to any server, as in `start`
+    let expected_host = if profile.is_some() { "" } else { server_host };
+    let identity = check_identity(
+        &mut report,
+        &env.key_settings(),
+        profile.as_deref(),
+        expected_host,
+    );
+
+    match (network, identity) {
+        (true, Some(identity)) => check_auth(&mut report, &env, &identity).await,
+        (false, _) => {
+            report.skip("auth", "server is not reachable");
+            report.skip("websocket", "server is not reachable");
+        }
+        (true, None) => {
+            report.skip("auth", "no usable identity");
+            report.skip("websocket", "no usable identity");
+        }
+    }
+
+    check_ssh(&mut report, &env, ssh_user).await;
+
+    println!();
+    if report.failed == 0 {
+        println!("all checks passed");
+    } else {
+        println!("{} check(s) failed", report.failed);
+    }
+
+    Ok(report.failed == 0)
+}
+
+/// DNS, TCP, TLS and `/version`. Returns whether the server answered.
+async fn check_network(report: &mut Report, env: &Env, host: &str, port: u16) -> bool {
+    let proxied = proxy::proxied(host);
+
+    match tokio::net::lookup_host((host, port)).await {
+        Ok(addrs) => {
+            let addrs: Vec<_> = addrs.map(|addr| addr.ip().to_string()).collect();
+            report.pass("dns", format!("{host} resolves to {}", addrs.join(", ")));
+        }
+        Err(_) if proxied => report.skip("dns", format!("{host} is resolved by the proxy")),
+        Err(err) => report.fail(
+            "dns",
+            err,
+            "check SERVER_HOST and the resolver in /etc/resolv.conf",
+        ),
+    }
+
+    let tcp = match with_timeout(proxy::connect(host, port)).await {
+        Ok(tcp) => {
+            let via = if proxied { " through the proxy" } else { "" };
+            report.pass("tcp", format!("connected to {host}:{port}{via}"));
+            tcp
+        }
+        Err(err) => {
+            let hint = if proxied {
+                "check PROXY_URL and that the proxy allows CONNECT to this port"
+            } else {
+                "check SERVER_PORT and that a firewall allows outbound traffic; set PROXY_URL if egress needs a proxy"
+            };
+            report.fail("tcp", err, hint);
+            report.skip("tls", "no tcp connection");
+            report.skip("version", "no tcp connection");
+            return false;
+        }
+    };
+
+    match env.mode {
+        Mode::Production => {
+            if let Err(err) = check_tls(report, host, tcp).await {
+                report.fail(
+                    "tls",
+                    err,
+                    "set TLS_CA_FILE for a private or intercepting CA and update TLS_PINS after a key change",
+                );
+                report.skip("version", "tls handshake failed");
+                return false;
+            }
+        }
+        Mode::Development => report.skip("tls", "development mode connects without tls"),
+    }
+
+    check_version(report, host, port).await
+}
+
+async fn check_tls(report: &mut Report, host: &str, tcp: TcpStream) -> anyhow::Result<()> {
+    let server_name = ServerName::try_from(host.to_string())?;
+    let connector = TlsConnector::from(tls::client_config());
+    let stream = with_timeout(async { Ok(connector.connect(server_name, tcp).await?) }).await?;
+
+    let (_, session) = stream.get_ref();
+    let protocol = session
+        .protocol_version()
+        .map(|version| format!("{version:?}"))
+        .unwrap_or_default();
+    let cipher = session
+        .negotiated_cipher_suite()
+        .map(|suite| format!("{:?}", suite.suite()))
+        .unwrap_or_default();
+    let chain = session.peer_certificates().map_or(0, <[_]>::len);
+
+    report.pass(
+        "tls",
+        format!("{protocol} {cipher}, chain of {chain} certificate(s) verified"),
+    );
+    Ok(())
+}
+
+async fn check_version(report: &mut Report, host: &str, port: u16) -> bool {
+    let url = format!("{}/version", generate_http_endpoint(host, port));
+    let result = with_timeout(async {
+        let response = proxy::http_client()?.get(&url).send().await?;
+        let response = response.error_for_status()?;
+        Ok(response.json::<VersionResponse>().await?)
+    })
+    .await;
+
+    match result {
+        Ok(server) if compatible(env::version(), &server.version) => {
+            report.pass(
+                "version",
+                format!("server {} is compatible", server.version),
+            );
+            true
+        }
+        Ok(server) => {
+            report.fail(
+                "version",
+                format!(
+                    "server {} is not compatible with agent {}",
+                    server.version,
+                    env::version()
+                ),
+                "install the agent release that matches the server",
+            );
+            true
+        }
+        Err(err) => {
+            report.fail(
+                "version",
+                err,
+                format!(
+                    "{url} should answer with the server version; check SERVER_HOST and SERVER_PORT"
+                ),
+            );
+            false
+        }
+    }
+}
+
+/// Loads the stored identity and checks that its keypair belongs together.
+fn check_identity(
+    report: &mut Report,
+    keys: &KeySettings,
+    profile: Option<&str>,
+    expected_host: &str,
+) -> Option<StoredState> {
+    let loaded = open_token_store(keys, profile, expected_host)
+        .map_err(anyhow::Error::from)
+        .and_then(|store| store.load());
+
+    let stored = match loaded {
+        Ok(stored) => stored,
+        Err(err) => {
+            report.fail(
+                "identity",
+                err,
+                "run `phirepass-agent login`; an encrypted key also needs its passphrase or key file",
+            );
+            return None;
+        }
+    };
+
+    if let Err(err) = validate_keypair(&stored) {
+        report.fail(
+            "identity",
+            err,
+            "the stored key is damaged; run `phirepass-agent logout --local-only` and login again",
+        );
+        return None;
+    }
+
+    report.pass("identity", format!("node {}", stored.node_id));
+    Some(stored)
+}
+
+fn validate_keypair(stored: &StoredState) -> anyhow::Result<()> {
+    let private_key: [u8; 32] = URL_SAFE_NO_PAD
+        .decode(&stored.private_key)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("private key must decode to 32 bytes"))?;
+
+    let public_key = URL_SAFE_NO_PAD.decode(&stored.public_key)?;
+    let derived = SigningKey::from_bytes(&private_key).verifying_key();
+
+    if derived.as_bytes().as_slice() != public_key.as_slice() {
+        anyhow::bail!("private key does not match the stored public key");
+    }
+
+    Ok(())
+}
+
+/// Challenge/verify round trip and the websocket auth frame.
+async fn check_auth(report: &mut Report, env: &Env, identity: &StoredState) {
+    let token = match with_timeout(fetch_session_jwt(
+        &env.server_host,
+        env.server_port,
+        identity,
+    ))
+    .await
+    {
+        Ok(token) => {
+            report.pass("auth", "challenge signed and verified");
+            token
+        }
+        Err(err) => {
+            let rejected = err
+                .chain()
+                .filter_map(|cause| cause.downcast_ref::<RequestError>())
+                .any(RequestError::is_auth_rejected);
+            let hint = if rejected {
+                "the server rejected the node; it may have been revoked or its key rotated elsewhere, login again"
+            } else {
+                "the auth endpoints did not answer; check the server logs"
+            };
+            report.fail("auth", err, hint);
+            report.skip("websocket", "no session token");
+            return;
+        }
+    };
+
+    match with_timeout(ws::probe(env, identity.node_id, &token)).await {
+        Ok(version) => report.pass(
+            "websocket",
+            format!("authenticated with server version {version}"),
+        ),
+        Err(err) => report.fail(
+            "websocket",
+            err,
+            "a proxy or load balancer in front of the server must allow websocket upgrades",
+        ),
+    }
+}
+
+async fn check_ssh(report: &mut Report, env: &Env, ssh_user: Option<String>) {
+    let Some(username) = ssh_user else {
+        report.skip("ssh", "pass --ssh-user to test a login to the local sshd");
+        return;
+    };
+
+    let credentials = match env.ssh_auth_mode {
+        SSHAuthMethod::Password => {
+            let prompt = format!("Password for {username}@{}: ", env.ssh_host);
+            match rpassword::prompt_password(prompt) {
+                Ok(password) => SSHConfigAuth::UsernamePassword(username, password),
+                Err(err) => {
+                    report.fail("ssh", err, "run doctor from an interactive terminal");
+                    return;
+                }
+            }
+        }
+        SSHAuthMethod::None => SSHConfigAuth::Username(username),
+    };
+
+    let conn = SSHConnection::new(SSHConfig {
+        host: env.ssh_host.clone(),
+        port: env.ssh_port,
+        credentials,
+        inactivity_timeout: Some(STEP_TIMEOUT),
+    });
+
+    match with_timeout(async { Ok(conn.check_login().await?) }).await {
+        Ok(()) => report.pass(
+            "ssh",
+            format!("logged in to {}:{}", env.ssh_host, env.ssh_port),
+        ),
+        Err(err) => report.fail(
+            "ssh",
+            err,
+            format!(
+                "check SSH_HOST, SSH_PORT and that sshd accepts {} authentication",
+                env.ssh_auth_mode.to_string().to_lowercase()
+            ),
+        ),
+    }
+}
+
+async fn with_timeout<T>(future: impl Future<Output = anyhow::Result<T>>) -> anyhow::Result<T> {
+    tokio::time::timeout(STEP_TIMEOUT, future)
+        .await
+        .map_err(|_| anyhow::anyhow!("timed out after {}s", STEP_TIMEOUT.as_secs()))?
+}
+
+/// Same major version, and for 0.x the same minor as well.
+fn compatible(agent: &str, server: &str) -> bool {
+    let parse = |version: &str| -> Option<(u64, u64)> {
+        let mut parts = version.trim().trim_start_matches('v').split('.');
+        Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+    };
+
+    match (parse(agent), parse(server)) {
+        (Some((0, agent_minor)), Some((0, server_minor))) => agent_minor == server_minor,
+        (Some((agent_major, _)), Some((server_major, _))) => agent_major == server_major,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_version_compatibility() {
+        assert!(compatible("0.1.180", "0.1.175"));
+        assert!(compatible("1.2.0", "1.4.3"));
+        assert!(compatible("0.1.0", "v0.1.2"));
+
+        assert!(!compatible("0.1.180", "0.2.0"));
+        assert!(!compatible("1.0.0", "2.0.0"));
+        assert!(!compatible("0.1.0", "unknown"));
+    }
+}
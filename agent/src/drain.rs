@@ -0,0 +1,213 @@
+use crate::admin::Connections;
+use crate::common::send_frame_data;
+use crate::session::TunnelSessions;
+use crate::sftp::SFTPActiveUploads;
+use log::{info, warn};
+use phirepass_common::protocol::common::Frame;
+use phirepass_common::protocol::node::{NodeFrameData, WebFrameId};
+use phirepass_common::protocol::web::WebFrameData;
+use std::collections::BTreeSet;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::watch;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// how long closed tunnels get to report `TunnelClosed` before the
+// connection goes away
+pub(crate) const CLOSE_WAIT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub(crate) struct DrainRequest {
+    pub reason: String,
+    /// how long users are warned before their sessions close
+    pub notice: Duration,
+    /// from the start of the drain; uploads still in flight after the
+    /// notice keep the sessions open until they finish or this passes
+    pub timeout: Duration,
+    /// the agent is stopping, so the drain outlives the connection
+    pub stopping: bool,
+}
+
+/// Drain state of one server link. While a drain is requested new tunnels
+/// are refused and closed tunnels report `CloseReason::Drain`.
+pub(crate) struct Drain {
+    request: watch::Sender<Option<DrainRequest>>,
+}
+
+impl Default for Drain {
+    fn default() -> Self {
+        Self {
+            request: watch::Sender::new(None),
+        }
+    }
+}
+
+impl Drain {
+    pub(crate) fn request(&self, request: DrainRequest) {
+        // a shutdown is never downgraded to a server drain
+        self.request.send_if_modified(|current| {
+            if current.as_ref().is_some_and(|current| current.stopping) {
+                return false;
+            }
+            *current = Some(request);
+            true
+        });
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.request.borrow().is_some()
+    }
+
+    pub(crate) fn is_stopping(&self) -> bool {
+        self.request
+            .borrow()
+            .as_ref()
+            .is_some_and(|request| request.stopping)
+    }
+
+    /// A server drain ends with the connection it was sent on.
+    pub(crate) fn reset(&self) {
+        self.request.send_if_modified(|current| {
+            if current.as_ref().is_some_and(|current| !current.stopping) {
+                *current = None;
+                return true;
+            }
+            false
+        });
+    }
+
+    pub(crate) async fn requested(&self) -> DrainRequest {
+        let mut rx = self.request.subscribe();
+        let request = rx
+            .wait_for(Option::is_some)
+            .await
+            .ok()
+            .and_then(|request| request.clone());
+
+        match request {
+            Some(request) => request,
+            // the sender lives as long as self
+            None => std::future::pending().await,
+        }
+    }
+}
+
+/// Warns every web client with a session, waits out the notice and any
+/// uploads still in flight, then closes the sessions.
+pub(crate) async fn run(
+    request: &DrainRequest,
+    sender: &Sender<Frame>,
+    sessions: &TunnelSessions,
+    uploads: &SFTPActiveUploads,
+) {
+    let started = Instant::now();
+    let cids = connection_ids(sessions);
+    info!(
+        "draining {} session(s) of {} connection(s): {}",
+        sessions.len(),
+        cids.len(),
+        request.reason
+    );
+
+    for cid in cids {
+        send_frame_data(
+            sender,
+            NodeFrameData::WebFrame {
+                id: WebFrameId::ConnectionId(cid),
+                frame: WebFrameData::NodeDraining {
+                    reason: request.reason.clone(),
+                    closes_in_secs: request.notice.as_secs() as u32,
+                },
+            },
+        );
+    }
+
+    let notice_end = started + request.notice;
+    let deadline = started + request.timeout.max(request.notice);
+    while !sessions.is_empty() && Instant::now() < deadline {
+        if Instant::now() >= notice_end && uploads_in_flight(sessions, uploads) == 0 {
+            break;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+
+    let pending = uploads_in_flight(sessions, uploads);
+    if pending > 0 {
+        warn!("drain deadline passed with {pending} upload(s) in flight");
+    }
+
+    // Stopped in place, so every tunnel task reports `TunnelClosed` before
+    // it removes itself
+    info!("closing {} drained session(s)", sessions.len());
+    for mut entry in sessions.iter_mut() {
+        entry.value_mut().stop();
+    }
+
+    let closing = Instant::now() + CLOSE_WAIT;
+    while !sessions.is_empty() && Instant::now() < closing {
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Drains every link for a shutdown and returns once their sessions are
+/// closed or the deadline has passed.
+pub(crate) async fn all(connections: &Connections, request: DrainRequest) {
+    let deadline = Instant::now() + request.timeout.max(request.notice) + CLOSE_WAIT;
+    for link in connections.iter() {
+        link.drain.request(request.clone());
+    }
+
+    while connections.iter().any(|link| !link.sessions.is_empty()) {
+        if Instant::now() >= deadline {
+            warn!("drain did not finish in time");
+            return;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+fn connection_ids(sessions: &TunnelSessions) -> BTreeSet<Uuid> {
+    sessions.iter().map(|entry| entry.key().0).collect()
+}
+
+// Uploads of connections without a session left are abandoned and only
+// wait for the cleanup task
+fn uploads_in_flight(sessions: &TunnelSessions, uploads: &SFTPActiveUploads) -> usize {
+    let cids = connection_ids(sessions);
+    uploads
+        .iter()
+        .filter(|entry| cids.contains(&entry.key().0))
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(stopping: bool) -> DrainRequest {
+        DrainRequest {
+            reason: "test".to_string(),
+            notice: Duration::from_secs(1),
+            timeout: Duration::from_secs(2),
+            stopping,
+        }
+    }
+
+    #[test]
+    fn a_shutdown_outlives_server_drains() {
+        let drain = Drain::default();
+        drain.request(request(false));
+        assert!(drain.is_active());
+        drain.reset();
+        assert!(!drain.is_active());
+
+        drain.request(request(true));
+        drain.request(request(false));
+        assert!(drain.is_stopping());
+        drain.reset();
+        assert!(drain.is_stopping());
+    }
+}
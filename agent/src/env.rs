@@ -1,10 +1,18 @@
+use crate::accounts::LocalAccounts;
+use crate::creds::KeySettings;
+use crate::drain::DrainRequest;
+use crate::session::SessionLimits;
 use crate::ssh::auth::SSHAuthMethod;
 use envconfig::Envconfig;
 use phirepass_common::env::Mode;
+use phirepass_common::labels::{self, Labels};
+use secrecy::SecretString;
+use std::collections::HashMap;
 use std::env;
+use std::path::PathBuf;
 use std::time::Duration;
 
-#[derive(Envconfig, Debug)]
+#[derive(Envconfig, Debug, Clone)]
 pub(crate) struct Env {
     #[envconfig(from = "APP_MODE", default = "production")]
     pub mode: Mode,
@@ -35,6 +43,105 @@ pub(crate) struct Env {
 
     #[envconfig(from = "SSH_INACTIVITY_PERIOD", default = "3600")] // 1 hour
     pub ssh_inactivity_secs: u64,
+
+    #[envconfig(from = "SFTP_WATCH_INTERVAL", default = "3")]
+    pub sftp_watch_interval_secs: u64,
+
+    // how long an ssh connection shared by a browser connection's tunnels
+    // stays up after its last channel closes
+    #[envconfig(from = "SSH_POOL_IDLE_TIMEOUT", default = "30")]
+    pub ssh_pool_idle_secs: u64,
+
+    // http://, socks5:// or socks5h:// with optional user:password
+    #[envconfig(from = "PROXY_URL")]
+    pub proxy_url: Option<String>,
+
+    // PEM bundle trusted in addition to the webpki roots
+    #[envconfig(from = "TLS_CA_FILE")]
+    pub tls_ca_file: Option<String>,
+
+    // comma separated base64 sha256 hashes of the server public key
+    #[envconfig(from = "TLS_PINS")]
+    pub tls_pins: Option<String>,
+
+    #[envconfig(from = "RECONNECT_INITIAL_DELAY_MS", default = "1000")]
+    pub reconnect_initial_delay_ms: u64,
+
+    #[envconfig(from = "RECONNECT_MAX_DELAY_MS", default = "60000")]
+    pub reconnect_max_delay_ms: u64,
+
+    #[envconfig(from = "RECONNECT_MULTIPLIER", default = "2.0")]
+    pub reconnect_multiplier: f64,
+
+    // draw each delay uniformly from zero up to the exponential ceiling
+    #[envconfig(from = "RECONNECT_JITTER", default = "true")]
+    pub reconnect_jitter: bool,
+
+    // a connection that stayed up this long resets the backoff
+    #[envconfig(from = "RECONNECT_RESET_AFTER", default = "60")]
+    pub reconnect_reset_after_secs: u64,
+
+    // unix:/path/to/socket or a loopback host:port; unset disables the admin api
+    #[envconfig(from = "ADMIN_LISTEN")]
+    pub admin_listen: Option<String>,
+
+    // key=value,key=value or a [labels] table in the config file
+    #[envconfig(from = "LABELS")]
+    pub labels: Option<String>,
+
+    // JSON object or a [metadata] table in the config file
+    #[envconfig(from = "METADATA")]
+    pub metadata: Option<String>,
+
+    // concurrent tunnel limits; 0 leaves a limit off
+    #[envconfig(from = "MAX_SESSIONS", default = "0")]
+    pub max_sessions: usize,
+
+    #[envconfig(from = "MAX_SSH_SESSIONS", default = "0")]
+    pub max_ssh_sessions: usize,
+
+    #[envconfig(from = "MAX_SFTP_SESSIONS", default = "0")]
+    pub max_sftp_sessions: usize,
+
+    // per browser connection (cid)
+    #[envconfig(from = "MAX_SESSIONS_PER_CONNECTION", default = "0")]
+    pub max_sessions_per_connection: usize,
+
+    // JSON lines record of tunnels and file operations; unset disables it
+    #[envconfig(from = "AUDIT_LOG")]
+    pub audit_log: Option<String>,
+
+    #[envconfig(from = "AUDIT_LOG_MAX_BYTES", default = "10485760")] // 10 MiB
+    pub audit_log_max_bytes: u64,
+
+    // rotated files kept next to the active one
+    #[envconfig(from = "AUDIT_LOG_MAX_FILES", default = "5")]
+    pub audit_log_max_files: u32,
+
+    // warning users get before a drain closes their sessions
+    #[envconfig(from = "DRAIN_NOTICE", default = "10")]
+    pub drain_notice_secs: u64,
+
+    // uploads in flight may hold a drain open this long
+    #[envconfig(from = "DRAIN_TIMEOUT", default = "60")]
+    pub drain_timeout_secs: u64,
+
+    // JSON object or a [local_accounts] table mapping user:<uuid>, role:<name>
+    // or * to local accounts; unset allows any account
+    #[envconfig(from = "LOCAL_ACCOUNTS")]
+    pub local_accounts: Option<String>,
+
+    // encrypts the stored private key; IDENTITY_PASSPHRASE_FILE reads it
+    // from a file instead
+    #[envconfig(from = "IDENTITY_PASSPHRASE")]
+    pub identity_passphrase: Option<String>,
+
+    #[envconfig(from = "IDENTITY_PASSPHRASE_FILE")]
+    pub identity_passphrase_file: Option<String>,
+
+    // keeps the private key in this file instead of the identity store
+    #[envconfig(from = "IDENTITY_KEY_FILE")]
+    pub identity_key_file: Option<String>,
 }
 
 impl Env {
@@ -44,10 +151,188 @@ impl Env {
             o => Some(Duration::from_secs(o)),
         }
     }
+
+    pub fn get_sftp_watch_interval(&self) -> Duration {
+        Duration::from_secs(self.sftp_watch_interval_secs.max(1))
+    }
+
+    pub fn get_ssh_pool_idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.ssh_pool_idle_secs)
+    }
+
+    /// Drain settings; a server drain may ask for a shorter timeout.
+    pub(crate) fn drain_request(
+        &self,
+        reason: &str,
+        stopping: bool,
+        server_timeout: Option<Duration>,
+    ) -> DrainRequest {
+        let mut timeout = Duration::from_secs(self.drain_timeout_secs);
+        if let Some(server_timeout) = server_timeout {
+            timeout = timeout.min(server_timeout);
+        }
+
+        DrainRequest {
+            reason: reason.to_string(),
+            notice: Duration::from_secs(self.drain_notice_secs).min(timeout),
+            timeout,
+            stopping,
+        }
+    }
+
+    pub fn session_limits(&self) -> SessionLimits {
+        SessionLimits {
+            total: self.max_sessions,
+            ssh: self.max_ssh_sessions,
+            sftp: self.max_sftp_sessions,
+            per_connection: self.max_sessions_per_connection,
+        }
+    }
+
+    /// Labels sent to the server on every connect.
+    pub fn get_labels(&self) -> anyhow::Result<Labels> {
+        let Some(value) = self.labels.as_deref().map(str::trim) else {
+            return Ok(Labels::new());
+        };
+
+        // A config file table arrives as a JSON object
+        if value.starts_with('{') {
+            let labels: Labels = serde_json::from_str(value)
+                .map_err(|err| anyhow::anyhow!("LABELS must map names to strings: {err}"))?;
+            labels::validate(&labels).map_err(anyhow::Error::msg)?;
+            return Ok(labels);
+        }
+
+        labels::parse(value).map_err(anyhow::Error::msg)
+    }
+
+    /// Accounts each phirepass user may log in as; `None` when unrestricted.
+    pub(crate) fn get_local_accounts(&self) -> anyhow::Result<Option<LocalAccounts>> {
+        match self
+            .local_accounts
+            .as_deref()
+            .filter(|value| !value.trim().is_empty())
+        {
+            Some(value) => LocalAccounts::parse(value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// How the node private key is kept at rest; empty values are unset.
+    pub(crate) fn key_settings(&self) -> KeySettings {
+        let set = |value: &Option<String>| value.clone().filter(|value| !value.trim().is_empty());
+
+        KeySettings {
+            passphrase: set(&self.identity_passphrase).map(SecretString::from),
+            passphrase_file: set(&self.identity_passphrase_file).map(PathBuf::from),
+            key_file: set(&self.identity_key_file).map(PathBuf::from),
+        }
+    }
+
+    pub fn get_metadata(&self) -> anyhow::Result<serde_json::Value> {
+        let Some(value) = self
+            .metadata
+            .as_deref()
+            .filter(|value| !value.trim().is_empty())
+        else {
+            return Ok(serde_json::Value::Object(Default::default()));
+        };
+
+        match serde_json::from_str(value) {
+            Ok(metadata @ serde_json::Value::Object(_)) => Ok(metadata),
+            Ok(_) => anyhow::bail!("METADATA must be a JSON object"),
+            Err(err) => anyhow::bail!("invalid METADATA: {err}"),
+        }
+    }
+
+    /// Effective settings keyed by their environment variable name, in
+    /// declaration order. Used by `config check` and to spot unknown keys
+    /// in the config file.
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("APP_MODE", self.mode.to_string()),
+            ("HOST", self.host.clone()),
+            ("PORT", self.port.to_string()),
+            (
+                "STATS_REFRESH_INTERVAL",
+                self.stats_refresh_interval.to_string(),
+            ),
+            ("SERVER_HOST", self.server_host.clone()),
+            ("SERVER_PORT", self.server_port.to_string()),
+            ("SSH_HOST", self.ssh_host.clone()),
+            ("SSH_PORT", self.ssh_port.to_string()),
+            ("SSH_AUTH_METHOD", self.ssh_auth_mode.to_string()),
+            (
+                "SSH_INACTIVITY_PERIOD",
+                self.ssh_inactivity_secs.to_string(),
+            ),
+            (
+                "SFTP_WATCH_INTERVAL",
+                self.sftp_watch_interval_secs.to_string(),
+            ),
+            ("SSH_POOL_IDLE_TIMEOUT", self.ssh_pool_idle_secs.to_string()),
+            ("PROXY_URL", self.proxy_url.clone().unwrap_or_default()),
+            ("TLS_CA_FILE", self.tls_ca_file.clone().unwrap_or_default()),
+            ("TLS_PINS", self.tls_pins.clone().unwrap_or_default()),
+            (
+                "RECONNECT_INITIAL_DELAY_MS",
+                self.reconnect_initial_delay_ms.to_string(),
+            ),
+            (
+                "RECONNECT_MAX_DELAY_MS",
+                self.reconnect_max_delay_ms.to_string(),
+            ),
+            (
+                "RECONNECT_MULTIPLIER",
+                self.reconnect_multiplier.to_string(),
+            ),
+            ("RECONNECT_JITTER", self.reconnect_jitter.to_string()),
+            (
+                "RECONNECT_RESET_AFTER",
+                self.reconnect_reset_after_secs.to_string(),
+            ),
+            (
+                "ADMIN_LISTEN",
+                self.admin_listen.clone().unwrap_or_default(),
+            ),
+            ("LABELS", self.labels.clone().unwrap_or_default()),
+            ("METADATA", self.metadata.clone().unwrap_or_default()),
+            ("MAX_SESSIONS", self.max_sessions.to_string()),
+            ("MAX_SSH_SESSIONS", self.max_ssh_sessions.to_string()),
+            ("MAX_SFTP_SESSIONS", self.max_sftp_sessions.to_string()),
+            (
+                "MAX_SESSIONS_PER_CONNECTION",
+                self.max_sessions_per_connection.to_string(),
+            ),
+            ("AUDIT_LOG", self.audit_log.clone().unwrap_or_default()),
+            ("AUDIT_LOG_MAX_BYTES", self.audit_log_max_bytes.to_string()),
+            ("AUDIT_LOG_MAX_FILES", self.audit_log_max_files.to_string()),
+            ("DRAIN_NOTICE", self.drain_notice_secs.to_string()),
+            ("DRAIN_TIMEOUT", self.drain_timeout_secs.to_string()),
+            (
+                "LOCAL_ACCOUNTS",
+                self.local_accounts.clone().unwrap_or_default(),
+            ),
+            (
+                "IDENTITY_PASSPHRASE",
+                self.identity_passphrase.clone().unwrap_or_default(),
+            ),
+            (
+                "IDENTITY_PASSPHRASE_FILE",
+                self.identity_passphrase_file.clone().unwrap_or_default(),
+            ),
+            (
+                "IDENTITY_KEY_FILE",
+                self.identity_key_file.clone().unwrap_or_default(),
+            ),
+        ]
+    }
 }
 
-pub(crate) fn init() -> anyhow::Result<Env> {
-    let config = Env::init_from_env()?;
+/// Builds the configuration from already layered variables (config file
+/// values overlaid by the process environment).
+pub(crate) fn init(vars: &HashMap<String, String>) -> anyhow::Result<Env> {
+    let config = Env::init_from_hashmap(vars)?;
     Ok(config)
 }
 
@@ -1,27 +1,12 @@
-use std::fmt::{Debug, Display, Formatter};
 use thiserror::Error;
 
-#[derive(Debug, Error)]
-struct AgentMessageError(pub &'static str);
-
-impl Display for AgentMessageError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{0}", self.0)
-    }
-}
-
-impl From<AgentMessageError> for AgentError {
-    fn from(message: AgentMessageError) -> Self {
-        AgentError::Other(Box::new(message))
-    }
-}
-
-pub fn message_error<T>(msg: &'static str) -> Result<T, AgentError> {
-    Err(AgentMessageError(msg).into())
-}
-
 #[derive(Debug, Error)]
 pub enum AgentError {
+    /// sshd refused the login. Its own variant, not a message error, so the
+    /// audit log can record it as `auth_failed`; the text is unchanged.
+    #[error("{0} authentication failed")]
+    AuthFailed(&'static str),
+
     #[error("russh error: {0}")]
     Russh(#[from] russh::Error),
 
@@ -34,3 +19,22 @@ pub enum AgentError {
     #[error(transparent)]
     Other(#[from] Box<dyn std::error::Error + Send + Sync>),
 }
+
+/// Non-success response from one of the server's REST endpoints.
+#[derive(Debug, Error)]
+#[error("request failed ({status}): {message}")]
+pub struct RequestError {
+    pub status: reqwest::StatusCode,
+    pub message: String,
+}
+
+impl RequestError {
+    /// Whether the server rejected the node itself (unknown, revoked or bad
+    /// signature) rather than failing to answer.
+    pub fn is_auth_rejected(&self) -> bool {
+        matches!(
+            self.status,
+            reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN
+        )
+    }
+}
@@ -0,0 +1,161 @@
+use log::{debug, info, warn};
+use phirepass_common::protocol::node::{TargetHealth, TunnelError};
+use phirepass_common::time::now_millis;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast;
+use tokio::time::timeout;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Servers may send other lines before the identification string; give up
+// after this many bytes
+const MAX_GREETING: usize = 1024;
+
+// Identifying ourselves lets sshd log a plain disconnect instead of a
+// missing identification string
+const PROBE_IDENT: &[u8] = b"SSH-2.0-phirepass_health\r\n";
+
+static LAST_TUNNEL_ERROR: Mutex<Option<TunnelError>> = Mutex::new(None);
+
+// Shared by the heartbeats of every server link, so the target sees one
+// probe per interval however many profiles the agent connects
+static LATEST: Mutex<Option<TargetHealth>> = Mutex::new(None);
+
+/// Remembers why the last ssh or sftp tunnel failed to open, for the next
+/// heartbeat.
+pub(crate) fn tunnel_failed(error: impl ToString) {
+    let mut last = LAST_TUNNEL_ERROR
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    *last = Some(TunnelError {
+        message: error.to_string(),
+        at: now_millis(),
+    });
+}
+
+/// Probes the ssh target every `interval` until shutdown.
+pub(crate) fn spawn_prober(
+    host: String,
+    port: u16,
+    interval: Duration,
+    mut shutdown: broadcast::Receiver<()>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    let health = probe(&host, port).await;
+                    if !health.reachable {
+                        warn!("ssh target {host}:{port} is not reachable");
+                    }
+                    *LATEST.lock().unwrap_or_else(|err| err.into_inner()) = Some(health);
+                }
+                _ = shutdown.recv() => {
+                    info!("ssh target probe shutting down");
+                    break;
+                }
+            }
+        }
+    })
+}
+
+/// The last probe result, with the latest tunnel error; `None` until the
+/// first probe finishes.
+pub(crate) fn latest() -> Option<TargetHealth> {
+    let mut health = LATEST
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()?;
+    health.last_tunnel_error = LAST_TUNNEL_ERROR
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone();
+    Some(health)
+}
+
+/// Connects to the ssh target and reads its banner.
+async fn probe(host: &str, port: u16) -> TargetHealth {
+    let (reachable, banner) = match timeout(PROBE_TIMEOUT, TcpStream::connect((host, port))).await {
+        Ok(Ok(mut stream)) => (true, read_banner(&mut stream).await),
+        Ok(Err(err)) => {
+            debug!("ssh target {host}:{port} is not reachable: {err}");
+            (false, None)
+        }
+        Err(_) => {
+            debug!("ssh target {host}:{port} timed out");
+            (false, None)
+        }
+    };
+
+    let last_tunnel_error = LAST_TUNNEL_ERROR
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone();
+
+    TargetHealth {
+        host: host.to_string(),
+        port,
+        reachable,
+        banner,
+        checked_at: now_millis(),
+        last_tunnel_error,
+    }
+}
+
+async fn read_banner(stream: &mut TcpStream) -> Option<String> {
+    let mut greeting = Vec::with_capacity(256);
+    let mut buf = [0u8; 256];
+
+    let banner = timeout(PROBE_TIMEOUT, async {
+        while greeting.len() < MAX_GREETING {
+            let n = stream.read(&mut buf).await.ok()?;
+            if n == 0 {
+                return None;
+            }
+            greeting.extend_from_slice(&buf[..n]);
+            if let Some(banner) = parse_banner(&greeting) {
+                return Some(banner);
+            }
+        }
+        None
+    })
+    .await
+    .ok()
+    .flatten();
+
+    let _ = stream.write_all(PROBE_IDENT).await;
+    let _ = stream.shutdown().await;
+    banner
+}
+
+/// The first complete `SSH-` line of the server greeting (RFC 4253 4.2).
+fn parse_banner(greeting: &[u8]) -> Option<String> {
+    greeting
+        .split_inclusive(|byte| *byte == b'\n')
+        .filter(|line| line.ends_with(b"\n"))
+        .map(|line| String::from_utf8_lossy(line).trim_end().to_string())
+        .find(|line| line.starts_with("SSH-"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_banner_after_other_lines() {
+        assert_eq!(
+            parse_banner(b"SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13\r\n"),
+            Some("SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13".to_string())
+        );
+        assert_eq!(
+            parse_banner(b"welcome\r\nSSH-2.0-dropbear\r\n"),
+            Some("SSH-2.0-dropbear".to_string())
+        );
+        assert_eq!(parse_banner(b"SSH-2.0-partial"), None);
+        assert_eq!(parse_banner(b"HTTP/1.1 400 Bad Request\r\n"), None);
+    }
+}
@@ -1,5 +1,9 @@
+use crate::admin::Connections;
 use crate::env::Env;
+use crate::metrics::METRICS;
 use axum::Json;
+use axum::extract::State;
+use axum::http::header;
 use axum::response::IntoResponse;
 use serde_json::json;
 use std::sync::Arc;
@@ -7,16 +11,34 @@ use std::sync::Arc;
 #[derive(Clone)]
 pub(crate) struct AppState {
     pub(crate) env: Arc<Env>,
+    pub(crate) connections: Connections,
 }
 
 impl AppState {
-    pub fn new(config: Arc<Env>) -> Self {
-        Self { env: config }
+    pub fn new(config: Arc<Env>, connections: Connections) -> Self {
+        Self {
+            env: config,
+            connections,
+        }
     }
 }
 
-pub async fn get_version() -> impl IntoResponse {
+pub(crate) async fn get_version(State(state): State<AppState>) -> impl IntoResponse {
+    let connections: Vec<_> = state
+        .connections
+        .iter()
+        .map(|conn| conn.summary())
+        .collect();
+
     Json(json!({
         "version": crate::env::version(),
+        "connections": connections,
     }))
 }
+
+pub(crate) async fn get_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        METRICS.render(&state.connections),
+    )
+}
@@ -1,16 +1,31 @@
+use anyhow::Context;
 use phirepass_common::runtime::RuntimeBuilder;
 use std::fs;
+use std::path::Path;
 
+mod accounts;
+mod admin;
 mod agent;
+mod audit;
+mod backoff;
 mod cli;
 mod common;
+mod config;
 mod creds;
+mod doctor;
+mod drain;
 mod env;
 mod error;
+mod health;
 mod http;
+mod metrics;
+mod proxy;
 mod session;
 mod sftp;
 mod ssh;
+mod systemd;
+mod tls;
+mod usage;
 mod ws;
 
 fn main() -> anyhow::Result<()> {
@@ -25,32 +40,104 @@ fn main() -> anyhow::Result<()> {
         phirepass_common::logger::init("phirepass:agent");
         match cli.command {
             None => {
-                let config = env::init()?;
-                agent::start(config).await
+                let config = load_config(cli.config.as_deref())?;
+                agent::start(config.env, config.local_accounts, vec![]).await
             }
             Some(cli::Commands::Start(args)) => {
-                let config = env::init()?;
+                let mut config = load_config(cli.config.as_deref())?;
+                config.override_server(args.server_host, args.server_port);
 
                 if let Some(path_to_token) = args.token_from_file {
-                    let server_host = args.server_host.unwrap_or(config.server_host.to_owned());
-                    let server_port = args.server_port.unwrap_or(config.server_port);
+                    if args.profiles.len() > 1 {
+                        anyhow::bail!("--token-from-file accepts at most one --profile");
+                    }
+
                     let token = fs::read_to_string(path_to_token)?;
-                    agent::save_token(server_host.as_str(), server_port, &token).await?;
+                    agent::save_token(
+                        &config.env.key_settings(),
+                        args.profiles.first().map(String::as_str),
+                        config.env.server_host.as_str(),
+                        config.env.server_port,
+                        &token,
+                    )
+                    .await?;
                 }
 
-                agent::start(config).await
+                agent::start(config.env, config.local_accounts, args.profiles).await
             }
             Some(cli::Commands::Login(args)) => {
+                let mut config = load_config(cli.config.as_deref())?;
+                config.override_server(args.server_host, args.server_port);
                 agent::login(
-                    args.server_host,
-                    args.server_port,
+                    config.env.key_settings(),
+                    args.profile,
+                    config.env.server_host,
+                    config.env.server_port,
                     args.from_file,
                     args.from_stdin,
                 )
                 .await
             }
             Some(cli::Commands::Logout(args)) => {
-                agent::logout(args.server_host, args.server_port).await
+                let mut config = load_config(cli.config.as_deref())?;
+                config.override_server(args.server_host, args.server_port);
+                agent::logout(
+                    config.env.key_settings(),
+                    args.profile,
+                    config.env.server_host,
+                    config.env.server_port,
+                    args.local_only,
+                )
+                .await
+            }
+            Some(cli::Commands::RotateKey(args)) => {
+                let mut config = load_config(cli.config.as_deref())?;
+                config.override_server(args.server_host, args.server_port);
+                agent::rotate_key(
+                    config.env.key_settings(),
+                    args.profile,
+                    config.env.server_host,
+                    config.env.server_port,
+                )
+                .await
+            }
+            Some(cli::Commands::Doctor(args)) => {
+                let mut config = load_config(cli.config.as_deref())?;
+                config.override_server(args.server_host, args.server_port);
+
+                if !doctor::run(config.env, args.profile, args.ssh_user).await? {
+                    std::process::exit(1);
+                }
+                Ok(())
+            }
+            Some(cli::Commands::Config(cli::ConfigCommands::Check)) => {
+                let config = config::load(cli.config.as_deref())?;
+                config.print();
+                Ok(())
+            }
+            Some(cli::Commands::Service(cli::ServiceCommands::Install(args))) => {
+                let config = config::load(cli.config.as_deref())?;
+                let user = match args.user {
+                    Some(user) => user,
+                    None => whoami::username().context("failed to resolve current user")?,
+                };
+
+                let options = systemd::UnitOptions {
+                    name: args.name,
+                    data_dir: systemd::data_dir_for(&user),
+                    audit_dir: systemd::audit_dir(&config.env),
+                    user,
+                    exec: std::env::current_exe()?,
+                    config: cli.config.map(std::path::absolute).transpose()?,
+                    profiles: args.profiles,
+                    watchdog_secs: systemd::watchdog_secs(&config.env),
+                    stop_timeout_secs: systemd::stop_timeout_secs(&config.env),
+                };
+
+                systemd::install(&options, &args.unit_dir, args.enable)
+            }
+            Some(cli::Commands::Service(cli::ServiceCommands::Uninstall(args))) => {
+                systemd::uninstall(&args.name, &args.unit_dir)
             }
             Some(cli::Commands::Version) => {
                 println!("{}", env::version());
@@ -59,3 +146,18 @@ fn main() -> anyhow::Result<()> {
         }
     })
 }
+
+/// Loads the layered configuration and applies the process-wide settings
+/// every command that talks to the server needs.
+fn load_config(path: Option<&Path>) -> anyhow::Result<config::LoadedConfig> {
+    let mut config = config::load(path)?;
+    config.env.get_labels()?;
+    config.env.get_metadata()?;
+    config.local_accounts = config.env.get_local_accounts()?;
+    proxy::init(config.env.proxy_url.as_deref())?;
+    tls::init(
+        config.env.tls_ca_file.as_deref(),
+        config.env.tls_pins.as_deref(),
+    )?;
+    Ok(config)
+}
@@ -0,0 +1,354 @@
+use crate::admin::{Connection, ConnectionState};
+use phirepass_common::protocol::Protocol;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Process-wide counters exposed on `/metrics`. Gauges that mirror live
+/// state (connections, sessions, transfers) are read from the connection
+/// registry at scrape time instead.
+pub(crate) struct Metrics {
+    reconnect_attempts: AtomicU64,
+    ssh_bytes_in: AtomicU64,
+    ssh_bytes_out: AtomicU64,
+    sftp_bytes_in: AtomicU64,
+    sftp_bytes_out: AtomicU64,
+    uploads_completed: AtomicU64,
+    uploads_failed: AtomicU64,
+    downloads_completed: AtomicU64,
+    downloads_failed: AtomicU64,
+    ssh_auth_failures: AtomicU64,
+    sftp_auth_failures: AtomicU64,
+    sessions_closed: AtomicU64,
+}
+
+pub(crate) static METRICS: Metrics = Metrics {
+    reconnect_attempts: AtomicU64::new(0),
+    ssh_bytes_in: AtomicU64::new(0),
+    ssh_bytes_out: AtomicU64::new(0),
+    sftp_bytes_in: AtomicU64::new(0),
+    sftp_bytes_out: AtomicU64::new(0),
+    uploads_completed: AtomicU64::new(0),
+    uploads_failed: AtomicU64::new(0),
+    downloads_completed: AtomicU64::new(0),
+    downloads_failed: AtomicU64::new(0),
+    ssh_auth_failures: AtomicU64::new(0),
+    sftp_auth_failures: AtomicU64::new(0),
+    sessions_closed: AtomicU64::new(0),
+};
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Transfer {
+    Upload,
+    Download,
+}
+
+impl Metrics {
+    pub(crate) fn reconnect_attempt(&self) {
+        self.reconnect_attempts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Bytes received from the server for the target.
+    pub(crate) fn bytes_in(&self, protocol: Protocol, bytes: usize) {
+        let counter = match protocol {
+            Protocol::SSH => &self.ssh_bytes_in,
+            Protocol::SFTP => &self.sftp_bytes_in,
+        };
+        counter.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Bytes read from the target and sent to the server.
+    pub(crate) fn bytes_out(&self, protocol: Protocol, bytes: usize) {
+        let counter = match protocol {
+            Protocol::SSH => &self.ssh_bytes_out,
+            Protocol::SFTP => &self.sftp_bytes_out,
+        };
+        counter.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn transfer_completed(&self, transfer: Transfer) {
+        match transfer {
+            Transfer::Upload => &self.uploads_completed,
+            Transfer::Download => &self.downloads_completed,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn transfer_failed(&self, transfer: Transfer) {
+        match transfer {
+            Transfer::Upload => &self.uploads_failed,
+            Transfer::Download => &self.downloads_failed,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn auth_failure(&self, protocol: Protocol) {
+        match protocol {
+            Protocol::SSH => &self.ssh_auth_failures,
+            Protocol::SFTP => &self.sftp_auth_failures,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn session_closed(&self) {
+        self.sessions_closed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Renders the Prometheus text exposition format.
+    pub(crate) fn render(&self, connections: &[Arc<Connection>]) -> String {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "phirepass_agent_reconnect_attempts_total",
+            "counter",
+            "Websocket reconnect attempts after a failed or closed connection.",
+        );
+        sample(
+            &mut out,
+            "phirepass_agent_reconnect_attempts_total",
+            &[],
+            load(&self.reconnect_attempts),
+        );
+
+        header(
+            &mut out,
+            "phirepass_agent_websocket_connected",
+            "gauge",
+            "Whether the websocket to the server is authenticated and up.",
+        );
+        for conn in connections {
+            let connected = conn.state() == ConnectionState::Connected;
+            sample(
+                &mut out,
+                "phirepass_agent_websocket_connected",
+                &[
+                    ("server", &conn.server_host),
+                    ("profile", conn.profile.as_deref().unwrap_or_default()),
+                ],
+                connected as u64,
+            );
+        }
+
+        header(
+            &mut out,
+            "phirepass_agent_heartbeat_rtt_seconds",
+            "gauge",
+            "Round trip time of the last acknowledged heartbeat.",
+        );
+        for conn in connections {
+            let labels = [
+                ("server", conn.server_host.as_str()),
+                ("profile", conn.profile.as_deref().unwrap_or_default()),
+            ];
+            let _ = writeln!(
+                out,
+                "phirepass_agent_heartbeat_rtt_seconds{} {}",
+                label_set(&labels),
+                conn.heartbeat_rtt_ms() as f64 / 1000.0
+            );
+        }
+
+        header(
+            &mut out,
+            "phirepass_agent_active_sessions",
+            "gauge",
+            "Open tunnel sessions by protocol.",
+        );
+        let (mut ssh, mut sftp) = (0, 0);
+        for conn in connections {
+            for entry in conn.sessions.iter() {
+                match entry.value().protocol() {
+                    "ssh" => ssh += 1,
+                    _ => sftp += 1,
+                }
+            }
+        }
+        sample(
+            &mut out,
+            "phirepass_agent_active_sessions",
+            &[("protocol", "ssh")],
+            ssh,
+        );
+        sample(
+            &mut out,
+            "phirepass_agent_active_sessions",
+            &[("protocol", "sftp")],
+            sftp,
+        );
+
+        header(
+            &mut out,
+            "phirepass_agent_sessions_closed_total",
+            "counter",
+            "Tunnel sessions that ended after opening.",
+        );
+        sample(
+            &mut out,
+            "phirepass_agent_sessions_closed_total",
+            &[],
+            load(&self.sessions_closed),
+        );
+
+        header(
+            &mut out,
+            "phirepass_agent_tunnel_bytes_total",
+            "counter",
+            "Tunnel payload bytes; in is from the server to the target.",
+        );
+        for (protocol, direction, counter) in [
+            ("ssh", "in", &self.ssh_bytes_in),
+            ("ssh", "out", &self.ssh_bytes_out),
+            ("sftp", "in", &self.sftp_bytes_in),
+            ("sftp", "out", &self.sftp_bytes_out),
+        ] {
+            sample(
+                &mut out,
+                "phirepass_agent_tunnel_bytes_total",
+                &[("protocol", protocol), ("direction", direction)],
+                load(counter),
+            );
+        }
+
+        header(
+            &mut out,
+            "phirepass_agent_sftp_active_transfers",
+            "gauge",
+            "SFTP uploads and downloads in progress.",
+        );
+        let uploads = connections
+            .iter()
+            .map(|conn| conn.uploads.len())
+            .sum::<usize>();
+        let downloads = connections
+            .iter()
+            .map(|conn| conn.downloads.len())
+            .sum::<usize>();
+        sample(
+            &mut out,
+            "phirepass_agent_sftp_active_transfers",
+            &[("direction", "upload")],
+            uploads as u64,
+        );
+        sample(
+            &mut out,
+            "phirepass_agent_sftp_active_transfers",
+            &[("direction", "download")],
+            downloads as u64,
+        );
+
+        header(
+            &mut out,
+            "phirepass_agent_sftp_transfers_total",
+            "counter",
+            "Finished SFTP transfers by direction and result.",
+        );
+        for (direction, result, counter) in [
+            ("upload", "completed", &self.uploads_completed),
+            ("upload", "failed", &self.uploads_failed),
+            ("download", "completed", &self.downloads_completed),
+            ("download", "failed", &self.downloads_failed),
+        ] {
+            sample(
+                &mut out,
+                "phirepass_agent_sftp_transfers_total",
+                &[("direction", direction), ("result", result)],
+                load(counter),
+            );
+        }
+
+        header(
+            &mut out,
+            "phirepass_agent_ssh_auth_failures_total",
+            "counter",
+            "Rejected authentications against the target SSH server.",
+        );
+        for (protocol, counter) in [
+            ("ssh", &self.ssh_auth_failures),
+            ("sftp", &self.sftp_auth_failures),
+        ] {
+            sample(
+                &mut out,
+                "phirepass_agent_ssh_auth_failures_total",
+                &[("protocol", protocol)],
+                load(counter),
+            );
+        }
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: u64) {
+    if labels.is_empty() {
+        let _ = writeln!(out, "{name} {value}");
+        return;
+    }
+
+    let _ = writeln!(out, "{name}{} {value}", label_set(labels));
+}
+
+fn label_set(labels: &[(&str, &str)]) -> String {
+    let labels = labels
+        .iter()
+        .map(|(key, value)| format!("{key}=\"{}\"", escape(value)))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("{{{labels}}}")
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_labels_and_escapes_values() {
+        let mut out = String::new();
+        sample(&mut out, "metric", &[], 3);
+        sample(
+            &mut out,
+            "metric",
+            &[("server", "a\"b"), ("profile", "c\\d")],
+            1,
+        );
+
+        assert_eq!(
+            out,
+            "metric 3\nmetric{server=\"a\\\"b\",profile=\"c\\\\d\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn renders_heartbeat_rtt_per_connection() {
+        let staging = Arc::new(Connection::new(
+            Some("staging".into()),
+            "staging.example.com".into(),
+            443,
+        ));
+        let production = Arc::new(Connection::new(None, "api.phirepass.com".into(), 443));
+        staging.set_heartbeat_rtt(1500);
+        production.set_heartbeat_rtt(20);
+
+        let out = METRICS.render(&[staging, production]);
+
+        assert!(out.contains(
+            "phirepass_agent_heartbeat_rtt_seconds{server=\"staging.example.com\",profile=\"staging\"} 1.5\n"
+        ));
+        assert!(out.contains(
+            "phirepass_agent_heartbeat_rtt_seconds{server=\"api.phirepass.com\",profile=\"\"} 0.02\n"
+        ));
+    }
+}
@@ -1,7 +1,9 @@
+use crate::admin::Connection;
 use crate::sftp::session::{SFTPCommand, SFTPSessionHandle};
 use crate::ssh::session::{SSHCommand, SSHSessionHandle};
 use dashmap::DashMap;
 use log::info;
+use phirepass_common::protocol::Protocol;
 use std::fmt::{Display, Formatter};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU32, Ordering};
@@ -16,6 +18,87 @@ pub fn generate_session_id() -> u32 {
     SESSION_ID.fetch_add(1, Ordering::Relaxed)
 }
 
+/// Caps on concurrent tunnels; a zero leaves that limit off.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SessionLimits {
+    pub total: usize,
+    pub ssh: usize,
+    pub sftp: usize,
+    pub per_connection: usize,
+}
+
+#[derive(Debug, Default)]
+struct SessionCounts {
+    total: usize,
+    protocol: usize,
+    connection: usize,
+}
+
+impl SessionLimits {
+    /// Fails with a message for the web client when one more `protocol`
+    /// session for `cid` would go over a limit. Sessions of every server
+    /// link count, since they all share the node.
+    pub(crate) fn check(
+        &self,
+        links: &[Arc<Connection>],
+        protocol: Protocol,
+        cid: Uuid,
+    ) -> Result<(), String> {
+        let name = protocol_name(protocol);
+        let counts = links.iter().flat_map(|link| link.sessions.iter()).fold(
+            SessionCounts::default(),
+            |mut counts, entry| {
+                counts.total += 1;
+                if entry.value().protocol() == name {
+                    counts.protocol += 1;
+                }
+                if entry.key().0 == cid {
+                    counts.connection += 1;
+                }
+                counts
+            },
+        );
+
+        self.check_counts(protocol, &counts)
+    }
+
+    fn check_counts(&self, protocol: Protocol, counts: &SessionCounts) -> Result<(), String> {
+        let reached = |limit: usize, count: usize| limit > 0 && count >= limit;
+        let protocol_limit = match protocol {
+            Protocol::SSH => self.ssh,
+            Protocol::SFTP => self.sftp,
+        };
+
+        if reached(self.per_connection, counts.connection) {
+            return Err(format!(
+                "Session limit reached: this connection already has {} open session(s) on the node",
+                counts.connection
+            ));
+        }
+        if reached(protocol_limit, counts.protocol) {
+            return Err(format!(
+                "Session limit reached: the node allows {protocol_limit} {} session(s) at a time",
+                protocol_name(protocol).to_uppercase()
+            ));
+        }
+        if reached(self.total, counts.total) {
+            return Err(format!(
+                "Session limit reached: the node allows {} session(s) at a time",
+                self.total
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+fn protocol_name(protocol: Protocol) -> &'static str {
+    match protocol {
+        Protocol::SSH => "ssh",
+        Protocol::SFTP => "sftp",
+    }
+}
+
 #[derive(Debug)]
 pub enum SessionHandle {
     Ssh(SSHSessionHandle),
@@ -44,6 +127,36 @@ impl SessionHandle {
         }
     }
 
+    pub fn protocol(&self) -> &'static str {
+        match self {
+            SessionHandle::Ssh(_) => "ssh",
+            SessionHandle::Sftp(_) => "sftp",
+        }
+    }
+
+    pub fn username(&self) -> &str {
+        match self {
+            SessionHandle::Ssh(ssh_handle) => &ssh_handle.username,
+            SessionHandle::Sftp(sftp_handle) => &sftp_handle.username,
+        }
+    }
+
+    pub fn started_at(&self) -> u64 {
+        match self {
+            SessionHandle::Ssh(ssh_handle) => ssh_handle.started_at,
+            SessionHandle::Sftp(sftp_handle) => sftp_handle.started_at,
+        }
+    }
+
+    /// Signals the tunnel task to end but leaves the handle in place; the
+    /// task removes it once it has reported `TunnelClosed`.
+    pub fn stop(&mut self) {
+        match self {
+            SessionHandle::Ssh(ssh_handle) => ssh_handle.stop(),
+            SessionHandle::Sftp(sftp_handle) => sftp_handle.stop(),
+        }
+    }
+
     pub async fn shutdown(self) {
         match self {
             SessionHandle::Ssh(ssh_handle) => {
@@ -57,3 +170,51 @@ impl SessionHandle {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_each_limit() {
+        let limits = SessionLimits {
+            total: 10,
+            ssh: 4,
+            sftp: 0,
+            per_connection: 3,
+        };
+        let counts = |total, protocol, connection| SessionCounts {
+            total,
+            protocol,
+            connection,
+        };
+
+        assert!(limits.check_counts(Protocol::SSH, &counts(5, 3, 2)).is_ok());
+        assert!(
+            limits
+                .check_counts(Protocol::SFTP, &counts(9, 8, 2))
+                .is_ok()
+        );
+
+        let err = limits
+            .check_counts(Protocol::SSH, &counts(5, 3, 3))
+            .unwrap_err();
+        assert!(err.contains("this connection"));
+
+        let err = limits
+            .check_counts(Protocol::SSH, &counts(5, 4, 0))
+            .unwrap_err();
+        assert!(err.contains("4 SSH session(s)"));
+
+        let err = limits
+            .check_counts(Protocol::SFTP, &counts(10, 6, 0))
+            .unwrap_err();
+        assert!(err.contains("allows 10 session(s)"));
+
+        assert!(
+            SessionLimits::default()
+                .check_counts(Protocol::SSH, &counts(100, 100, 100))
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use crate::audit::Access;
+use crate::sftp::actions::join_path;
+use crate::sftp::actions::progress::OperationProgress;
+use log::{debug, info, warn};
+use phirepass_common::protocol::common::{Frame, FrameError};
+use phirepass_common::protocol::node::{NodeFrameData, WebFrameId};
+use phirepass_common::protocol::sftp::{SFTPCopy, SFTPOperationKind};
+use phirepass_common::protocol::web::WebFrameData;
+use russh_sftp::client::SftpSession;
+use russh_sftp::protocol::FileAttributes;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc::Sender;
+
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+pub async fn copy(
+    tx: &Sender<Frame>,
+    sftp_session: &SftpSession,
+    data: &SFTPCopy,
+    sid: u32,
+    msg_id: Option<u32>,
+    access: &Access,
+) {
+    info!("copying {} to {}", data.source, data.destination);
+
+    let mut progress = OperationProgress::new(tx, sid, msg_id, SFTPOperationKind::Copy);
+
+    match copy_path(sftp_session, data, &mut progress).await {
+        Ok(()) => {
+            info!("copied {} to {}", data.source, data.destination);
+            access.sftp_to("sftp_copy", &data.source, &data.destination);
+            progress.finish().await;
+        }
+        Err(err) => {
+            warn!(
+                "failed to copy {} to {}: {err}",
+                data.source, data.destination
+            );
+            if let Err(send_err) = tx
+                .send(
+                    NodeFrameData::WebFrame {
+                        frame: WebFrameData::Error {
+                            kind: FrameError::Generic,
+                            message: format!("Failed to copy: {}", err),
+                            msg_id,
+                        },
+                        id: WebFrameId::SessionId(sid),
+                    }
+                    .into(),
+                )
+                .await
+            {
+                warn!("failed to send error frame for copy: {send_err}");
+            }
+        }
+    }
+}
+
+async fn copy_path(
+    sftp_session: &SftpSession,
+    data: &SFTPCopy,
+    progress: &mut OperationProgress<'_>,
+) -> anyhow::Result<()> {
+    let source = sftp_session.canonicalize(data.source.as_str()).await?;
+    let destination = resolve_destination(sftp_session, &data.destination).await?;
+
+    let source_prefix = if source.ends_with('/') {
+        source.clone()
+    } else {
+        format!("{source}/")
+    };
+
+    if destination == source || destination.starts_with(&source_prefix) {
+        anyhow::bail!("cannot copy {source} into itself");
+    }
+
+    if !data.overwrite && sftp_session.try_exists(destination.as_str()).await? {
+        anyhow::bail!("{destination} already exists");
+    }
+
+    let metadata = sftp_session.metadata(source.as_str()).await?;
+    if metadata.is_dir() {
+        copy_dir(sftp_session, &source, &destination, progress).await
+    } else {
+        progress.set_total_bytes(metadata.len());
+        copy_file(
+            sftp_session,
+            &source,
+            &destination,
+            metadata.permissions,
+            progress,
+        )
+        .await
+    }
+}
+
+/// Copies a directory tree. Symlinks and special files are skipped, since
+/// recreating them over SFTP is not portable across servers.
+async fn copy_dir(
+    sftp_session: &SftpSession,
+    source: &str,
+    destination: &str,
+    progress: &mut OperationProgress<'_>,
+) -> anyhow::Result<()> {
+    let mut pending = vec![(source.to_string(), destination.to_string())];
+
+    while let Some((src_dir, dst_dir)) = pending.pop() {
+        if !sftp_session.try_exists(dst_dir.as_str()).await? {
+            sftp_session.create_dir(dst_dir.as_str()).await?;
+        }
+        progress.entry(&dst_dir).await;
+
+        for entry in sftp_session.read_dir(src_dir.as_str()).await? {
+            let name = entry.file_name();
+            let src = join_path(&src_dir, &name);
+            let dst = join_path(&dst_dir, &name);
+            let file_type = entry.file_type();
+
+            if file_type.is_dir() {
+                pending.push((src, dst));
+            } else if file_type.is_file() {
+                let permissions = entry.metadata().permissions;
+                copy_file(sftp_session, &src, &dst, permissions, progress).await?;
+            } else {
+                debug!("skipping {src}, only files and directories are copied");
+                progress.skip(&src).await;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn copy_file(
+    sftp_session: &SftpSession,
+    source: &str,
+    destination: &str,
+    permissions: Option<u32>,
+    progress: &mut OperationProgress<'_>,
+) -> anyhow::Result<()> {
+    let mut reader = sftp_session.open(source).await?;
+    let mut writer = sftp_session.create(destination).await?;
+    let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+
+    loop {
+        let read = reader.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+
+        writer.write_all(&buffer[..read]).await?;
+        progress.bytes(read as u64).await;
+    }
+
+    writer.shutdown().await?;
+
+    if let Some(permissions) = permissions {
+        let attributes = FileAttributes {
+            permissions: Some(permissions & 0o777),
+            ..FileAttributes::empty()
+        };
+        if let Err(err) = sftp_session.set_metadata(destination, attributes).await {
+            debug!("failed to copy permissions to {destination}: {err}");
+        }
+    }
+
+    progress.entry(destination).await;
+
+    Ok(())
+}
+
+/// The destination may not exist yet, so only its parent is canonicalized.
+pub(crate) async fn resolve_destination(
+    sftp_session: &SftpSession,
+    destination: &str,
+) -> anyhow::Result<String> {
+    let destination = destination.trim_end_matches('/');
+
+    let (parent, name) = match destination.rsplit_once('/') {
+        Some(("", name)) => ("/", name),
+        Some((parent, name)) => (parent, name),
+        None => (".", destination),
+    };
+
+    if name.is_empty() || name == "." || name == ".." {
+        anyhow::bail!("invalid destination path");
+    }
+
+    let parent = sftp_session.canonicalize(parent).await?;
+    Ok(join_path(&parent, name))
+}
@@ -1,3 +1,4 @@
+use crate::audit::Access;
 use crate::sftp::SFTPActiveUploads;
 use log::{info, warn};
 use phirepass_common::protocol::common::{Frame, FrameError};
@@ -8,6 +9,7 @@ use russh_sftp::client::SftpSession;
 use tokio::sync::mpsc::Sender;
 use uuid::Uuid;
 
+#[allow(clippy::too_many_arguments)]
 pub async fn delete_file(
     tx: &Sender<Frame>,
     sftp_session: &SftpSession,
@@ -15,6 +17,7 @@ pub async fn delete_file(
     cid: Uuid,
     sid: u32,
     msg_id: Option<u32>,
+    access: &Access,
     uploads: &SFTPActiveUploads,
 ) {
     let file_path = format!(
@@ -40,6 +43,7 @@ pub async fn delete_file(
     match sftp_session.remove_file(&file_path).await {
         Ok(_) => {
             info!("file deleted successfully: {file_path}");
+            access.sftp("sftp_delete", &file_path);
             // No need to send response, UI will refresh the directory listing
         }
         Err(err) => {
@@ -0,0 +1,209 @@
+use crate::sftp::actions::join_path;
+use log::{debug, info, warn};
+use phirepass_common::protocol::common::{Frame, FrameError};
+use phirepass_common::protocol::node::{NodeFrameData, WebFrameId};
+use phirepass_common::protocol::sftp::{
+    SFTPDiskUsage, SFTPDiskUsageEntry, SFTPDiskUsageResult, SFTPFilesystemInfo, SFTPListItemKind,
+};
+use phirepass_common::protocol::web::WebFrameData;
+use russh_sftp::client::SftpSession;
+use russh_sftp::extensions::Statvfs;
+use tokio::sync::mpsc::Sender;
+
+// Walk limits, so a request for "/" cannot keep the sftp session busy forever
+const DEFAULT_MAX_ENTRIES: u32 = 10_000;
+const MAX_ENTRIES_LIMIT: u32 = 100_000;
+const MAX_DEPTH_LIMIT: u32 = 32;
+
+struct WalkBudget {
+    scanned: u64,
+    max_entries: u64,
+    truncated: bool,
+}
+
+impl WalkBudget {
+    fn take(&mut self) -> bool {
+        if self.scanned >= self.max_entries {
+            self.truncated = true;
+            return false;
+        }
+
+        self.scanned += 1;
+        true
+    }
+}
+
+pub async fn send_disk_usage(
+    tx: &Sender<Frame>,
+    sftp_session: &SftpSession,
+    data: &SFTPDiskUsage,
+    sid: u32,
+    msg_id: Option<u32>,
+) {
+    info!(
+        "computing disk usage for {} (depth: {}, entries: {})",
+        data.path, data.max_depth, data.max_entries
+    );
+
+    let frame = match disk_usage(sftp_session, data).await {
+        Ok(result) => WebFrameData::SFTPDiskUsageResult {
+            sid,
+            msg_id,
+            result,
+        },
+        Err(err) => {
+            warn!("failed to compute disk usage for {}: {err}", data.path);
+            WebFrameData::Error {
+                kind: FrameError::Generic,
+                message: format!("Failed to get disk usage: {}", err),
+                msg_id,
+            }
+        }
+    };
+
+    if let Err(err) = tx
+        .send(
+            NodeFrameData::WebFrame {
+                frame,
+                id: WebFrameId::SessionId(sid),
+            }
+            .into(),
+        )
+        .await
+    {
+        warn!("sftp failed to send disk usage for {}: {err}", data.path);
+    }
+}
+
+async fn disk_usage(
+    sftp_session: &SftpSession,
+    data: &SFTPDiskUsage,
+) -> anyhow::Result<SFTPDiskUsageResult> {
+    let abs_path = sftp_session.canonicalize(data.path.as_str()).await?;
+
+    // statvfs@openssh.com is optional, servers without it just don't report capacity
+    let filesystem = sftp_session
+        .fs_info(abs_path.as_str())
+        .await?
+        .map(|statvfs| filesystem_info(&statvfs));
+
+    let mut result = SFTPDiskUsageResult {
+        path: abs_path.clone(),
+        filesystem,
+        total_size: 0,
+        scanned_entries: 0,
+        truncated: false,
+        children: vec![],
+    };
+
+    if data.max_depth == 0 {
+        return Ok(result);
+    }
+
+    let max_depth = data.max_depth.min(MAX_DEPTH_LIMIT);
+    let max_entries = match data.max_entries {
+        0 => DEFAULT_MAX_ENTRIES,
+        n => n.min(MAX_ENTRIES_LIMIT),
+    };
+
+    let mut budget = WalkBudget {
+        scanned: 0,
+        max_entries: max_entries as u64,
+        truncated: false,
+    };
+
+    for entry in sftp_session.read_dir(abs_path.as_str()).await? {
+        if !budget.take() {
+            break;
+        }
+
+        let name = entry.file_name();
+        let path = join_path(&abs_path, &name);
+
+        let child = if entry.file_type().is_dir() {
+            let (size, entries) = dir_size(sftp_session, &path, max_depth, &mut budget).await;
+            SFTPDiskUsageEntry {
+                name,
+                path,
+                kind: SFTPListItemKind::Folder,
+                size,
+                entries,
+            }
+        } else {
+            SFTPDiskUsageEntry {
+                name,
+                path,
+                kind: SFTPListItemKind::File,
+                size: entry.metadata().size.unwrap_or(0),
+                entries: 0,
+            }
+        };
+
+        result.total_size += child.size;
+        result.children.push(child);
+    }
+
+    result
+        .children
+        .sort_by_key(|child| std::cmp::Reverse(child.size));
+    result.scanned_entries = budget.scanned;
+    result.truncated = budget.truncated;
+
+    Ok(result)
+}
+
+/// Sums file sizes below `path`, reading at most `depth` levels of it: 1
+/// only reads `path` itself. Unreadable directories are skipped rather than
+/// failing the whole summary.
+async fn dir_size(
+    sftp_session: &SftpSession,
+    path: &str,
+    depth: u32,
+    budget: &mut WalkBudget,
+) -> (u64, u64) {
+    let mut size = 0u64;
+    let mut entries = 0u64;
+    let mut pending = vec![(path.to_string(), depth)];
+
+    while let Some((dir, depth)) = pending.pop() {
+        if depth == 0 {
+            budget.truncated = true;
+            continue;
+        }
+
+        let read_dir = match sftp_session.read_dir(dir.as_str()).await {
+            Ok(read_dir) => read_dir,
+            Err(err) => {
+                debug!("skipping unreadable directory {dir}: {err}");
+                continue;
+            }
+        };
+
+        for entry in read_dir {
+            if !budget.take() {
+                return (size, entries);
+            }
+
+            entries += 1;
+
+            if entry.file_type().is_dir() {
+                pending.push((join_path(&dir, &entry.file_name()), depth - 1));
+            } else {
+                size += entry.metadata().size.unwrap_or(0);
+            }
+        }
+    }
+
+    (size, entries)
+}
+
+fn filesystem_info(statvfs: &Statvfs) -> SFTPFilesystemInfo {
+    SFTPFilesystemInfo {
+        total_bytes: statvfs.blocks.saturating_mul(statvfs.fragment_size),
+        free_bytes: statvfs.blocks_free.saturating_mul(statvfs.fragment_size),
+        available_bytes: statvfs.blocks_avail.saturating_mul(statvfs.fragment_size),
+        total_inodes: statvfs.inodes,
+        free_inodes: statvfs.inodes_free,
+        available_inodes: statvfs.inodes_avail,
+    }
+}
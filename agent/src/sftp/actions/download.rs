@@ -1,8 +1,11 @@
+use crate::audit::Access;
+use crate::metrics::{METRICS, Transfer};
 use crate::sftp::{
     CHUNK_SIZE, FileDownload, SFTPActiveDownloads, cleanup_abandoned_downloads, generate_id,
 };
 use bytes::Bytes;
 use log::{debug, info, warn};
+use phirepass_common::protocol::Protocol;
 use phirepass_common::protocol::common::{Frame, FrameError};
 use phirepass_common::protocol::node::{NodeFrameData, WebFrameId};
 use phirepass_common::protocol::sftp::{
@@ -26,6 +29,7 @@ use uuid::Uuid;
 //   100ms = ~640 KB/s max
 const DOWNLOAD_CHUNK_DELAY_MS: u64 = 0;
 
+#[allow(clippy::too_many_arguments)]
 pub async fn start_download(
     tx: &Sender<Frame>,
     sftp_session: &SftpSession,
@@ -33,6 +37,7 @@ pub async fn start_download(
     cid: Uuid,
     sid: u32,
     msg_id: Option<u32>,
+    access: &Access,
     downloads: &SFTPActiveDownloads,
 ) {
     cleanup_abandoned_downloads(downloads).await;
@@ -50,6 +55,7 @@ pub async fn start_download(
         Ok(meta) => meta,
         Err(err) => {
             warn!("failed to get file metadata for {file_path}: {err}");
+            METRICS.transfer_failed(Transfer::Download);
             let _ = tx
                 .send(
                     NodeFrameData::WebFrame {
@@ -77,6 +83,7 @@ pub async fn start_download(
         Ok(f) => f,
         Err(err) => {
             warn!("failed to open file {file_path}: {err}");
+            METRICS.transfer_failed(Transfer::Download);
             let _ = tx
                 .send(
                     NodeFrameData::WebFrame {
@@ -108,6 +115,7 @@ pub async fn start_download(
             sftp_file: file,
             started_at: now,
             last_updated: now,
+            meter: access.transfer(false, &file_path),
         },
     );
     info!(
@@ -162,6 +170,7 @@ pub async fn download_file_chunk(
                     "error seeking to position {} for download_id {download_id} at chunk {chunk_index}: {err}",
                     chunk_position
                 );
+                METRICS.transfer_failed(Transfer::Download);
                 let _ = tx
                     .send(
                         NodeFrameData::WebFrame {
@@ -184,10 +193,14 @@ pub async fn download_file_chunk(
                             "file download complete: {} (download_id: {}), sent {} chunks",
                             download.filename, download_id, chunk_index
                         );
+                        METRICS.transfer_completed(Transfer::Download);
+                        download.meter.complete();
                         // Mark for removal
                         should_remove = true;
                     }
                     Ok(bytes_read) => {
+                        METRICS.bytes_out(Protocol::SFTP, bytes_read);
+                        download.meter.add(bytes_read);
                         let chunk_data = Bytes::copy_from_slice(&buffer[..bytes_read]);
                         let chunk = SFTPDownloadChunk {
                             download_id,
@@ -231,6 +244,7 @@ pub async fn download_file_chunk(
                         warn!(
                             "error reading file for download_id {download_id} at chunk {chunk_index}: {err}"
                         );
+                        METRICS.transfer_failed(Transfer::Download);
                         let _ = tx
                             .send(
                                 NodeFrameData::WebFrame {
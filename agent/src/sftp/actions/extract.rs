@@ -0,0 +1,408 @@
+use crate::audit::Access;
+use crate::sftp::actions::join_path;
+use crate::sftp::actions::progress::OperationProgress;
+use flate2::read::GzDecoder;
+use log::{debug, info, warn};
+use phirepass_common::protocol::common::{Frame, FrameError};
+use phirepass_common::protocol::node::{NodeFrameData, WebFrameId};
+use phirepass_common::protocol::sftp::{SFTPExtract, SFTPOperationKind};
+use phirepass_common::protocol::web::WebFrameData;
+use russh_sftp::client::SftpSession;
+use russh_sftp::client::fs::File;
+use russh_sftp::protocol::FileAttributes;
+use std::collections::HashSet;
+use std::io::{Read, SeekFrom};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::Sender;
+
+const ENTRY_CHUNK_SIZE: usize = 64 * 1024;
+const ENTRY_CHANNEL_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArchiveFormat {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+/// Entries are read on a blocking thread and written over SFTP as they arrive,
+/// so large archives are never held in memory.
+enum ArchiveEntry {
+    Dir(String),
+    File { path: String, mode: Option<u32> },
+    Data(Vec<u8>),
+    Skipped(String),
+}
+
+struct OpenEntry {
+    file: File,
+    path: String,
+    mode: Option<u32>,
+}
+
+pub async fn extract(
+    tx: &Sender<Frame>,
+    sftp_session: &SftpSession,
+    data: &SFTPExtract,
+    sid: u32,
+    msg_id: Option<u32>,
+    access: &Access,
+) {
+    info!("extracting {}", data.path);
+
+    let mut progress = OperationProgress::new(tx, sid, msg_id, SFTPOperationKind::Extract);
+
+    match extract_archive(sftp_session, data, &mut progress).await {
+        Ok(destination) => {
+            info!("extracted {} into {destination}", data.path);
+            access.sftp_to("sftp_extract", &data.path, &destination);
+            progress.finish().await;
+        }
+        Err(err) => {
+            warn!("failed to extract {}: {err}", data.path);
+            if let Err(send_err) = tx
+                .send(
+                    NodeFrameData::WebFrame {
+                        frame: WebFrameData::Error {
+                            kind: FrameError::Generic,
+                            message: format!("Failed to extract archive: {}", err),
+                            msg_id,
+                        },
+                        id: WebFrameId::SessionId(sid),
+                    }
+                    .into(),
+                )
+                .await
+            {
+                warn!("failed to send error frame for extract: {send_err}");
+            }
+        }
+    }
+}
+
+async fn extract_archive(
+    sftp_session: &SftpSession,
+    data: &SFTPExtract,
+    progress: &mut OperationProgress<'_>,
+) -> anyhow::Result<String> {
+    let archive_path = sftp_session.canonicalize(data.path.as_str()).await?;
+
+    let Some(format) = archive_format(&archive_path) else {
+        anyhow::bail!("unsupported archive format, expected .tar, .tar.gz, .tgz or .zip");
+    };
+
+    let destination = match data.destination.trim_end_matches('/') {
+        "" if data.destination.is_empty() => parent_dir(&archive_path).to_string(),
+        "" => "/".to_string(),
+        destination => destination.to_string(),
+    };
+
+    if !sftp_session.try_exists(destination.as_str()).await? {
+        sftp_session.create_dir(destination.as_str()).await?;
+    }
+    let destination = sftp_session.canonicalize(destination).await?;
+
+    // zip needs random access, so the archive is staged in a local temp file
+    let local = download_to_tempfile(sftp_session, &archive_path).await?;
+
+    let (entries_tx, mut entries_rx) = mpsc::channel(ENTRY_CHANNEL_SIZE);
+    let reader = tokio::task::spawn_blocking(move || read_archive(local, format, &entries_tx));
+
+    let mut created_dirs = HashSet::from([destination.clone()]);
+    let mut current: Option<OpenEntry> = None;
+
+    while let Some(entry) = entries_rx.recv().await {
+        match entry {
+            ArchiveEntry::Dir(path) => {
+                close_entry(sftp_session, current.take()).await?;
+                create_dirs(sftp_session, &destination, &path, &mut created_dirs).await?;
+                progress.entry(&join_path(&destination, &path)).await;
+            }
+            ArchiveEntry::File { path, mode } => {
+                close_entry(sftp_session, current.take()).await?;
+
+                if let Some((parent, _)) = path.rsplit_once('/') {
+                    create_dirs(sftp_session, &destination, parent, &mut created_dirs).await?;
+                }
+
+                let target = join_path(&destination, &path);
+                if !data.overwrite && sftp_session.try_exists(target.as_str()).await? {
+                    anyhow::bail!("{target} already exists");
+                }
+
+                let file = sftp_session.create(target.as_str()).await?;
+                progress.entry(&target).await;
+                current = Some(OpenEntry {
+                    file,
+                    path: target,
+                    mode,
+                });
+            }
+            ArchiveEntry::Data(bytes) => {
+                if let Some(open) = current.as_mut() {
+                    open.file.write_all(&bytes).await?;
+                    progress.bytes(bytes.len() as u64).await;
+                }
+            }
+            ArchiveEntry::Skipped(path) => {
+                close_entry(sftp_session, current.take()).await?;
+                debug!("skipped archive entry {path}");
+                progress.skip(&path).await;
+            }
+        }
+    }
+
+    close_entry(sftp_session, current.take()).await?;
+    reader.await??;
+
+    Ok(destination)
+}
+
+async fn download_to_tempfile(
+    sftp_session: &SftpSession,
+    path: &str,
+) -> anyhow::Result<std::fs::File> {
+    let mut remote = sftp_session.open(path).await?;
+    let mut local = tokio::fs::File::from_std(tempfile::tempfile()?);
+
+    let size = tokio::io::copy(&mut remote, &mut local).await?;
+    debug!("staged {size} bytes of {path} for extraction");
+
+    local.flush().await?;
+    local.seek(SeekFrom::Start(0)).await?;
+
+    Ok(local.into_std().await)
+}
+
+async fn close_entry(sftp_session: &SftpSession, entry: Option<OpenEntry>) -> anyhow::Result<()> {
+    let Some(mut entry) = entry else {
+        return Ok(());
+    };
+
+    entry.file.shutdown().await?;
+
+    if let Some(mode) = entry.mode {
+        // never restore setuid/setgid bits from an uploaded archive
+        let attributes = FileAttributes {
+            permissions: Some(mode & 0o777),
+            ..FileAttributes::empty()
+        };
+        if let Err(err) = sftp_session
+            .set_metadata(entry.path.as_str(), attributes)
+            .await
+        {
+            debug!("failed to set permissions on {}: {err}", entry.path);
+        }
+    }
+
+    Ok(())
+}
+
+async fn create_dirs(
+    sftp_session: &SftpSession,
+    destination: &str,
+    relative: &str,
+    created: &mut HashSet<String>,
+) -> anyhow::Result<()> {
+    let mut current = destination.to_string();
+
+    for part in relative.split('/').filter(|part| !part.is_empty()) {
+        current = join_path(&current, part);
+
+        if created.contains(&current) {
+            continue;
+        }
+
+        if !sftp_session.try_exists(current.as_str()).await? {
+            sftp_session.create_dir(current.as_str()).await?;
+        }
+
+        created.insert(current.clone());
+    }
+
+    Ok(())
+}
+
+fn read_archive(
+    file: std::fs::File,
+    format: ArchiveFormat,
+    tx: &mpsc::Sender<ArchiveEntry>,
+) -> anyhow::Result<()> {
+    match format {
+        ArchiveFormat::Tar => read_tar(tar::Archive::new(file), tx),
+        ArchiveFormat::TarGz => read_tar(tar::Archive::new(GzDecoder::new(file)), tx),
+        ArchiveFormat::Zip => read_zip(file, tx),
+    }
+}
+
+fn read_tar<R: Read>(
+    mut archive: tar::Archive<R>,
+    tx: &mpsc::Sender<ArchiveEntry>,
+) -> anyhow::Result<()> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let raw = entry.path()?.to_string_lossy().into_owned();
+        let entry_type = entry.header().entry_type();
+
+        let path = match sanitize_entry_path(&raw) {
+            Some(path) if path.is_empty() => continue,
+            Some(path) => path,
+            None => {
+                send_entry(tx, ArchiveEntry::Skipped(raw))?;
+                continue;
+            }
+        };
+
+        match entry_type {
+            tar::EntryType::Directory => send_entry(tx, ArchiveEntry::Dir(path))?,
+            tar::EntryType::Regular | tar::EntryType::Continuous => {
+                let mode = entry.header().mode().ok();
+                send_entry(tx, ArchiveEntry::File { path, mode })?;
+                stream_entry(&mut entry, tx)?;
+            }
+            // links could point outside the destination, so they are never created
+            _ => send_entry(tx, ArchiveEntry::Skipped(path))?,
+        }
+    }
+
+    Ok(())
+}
+
+fn read_zip(file: std::fs::File, tx: &mpsc::Sender<ArchiveEntry>) -> anyhow::Result<()> {
+    let mut archive = zip::ZipArchive::new(file)?;
+
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        let raw = entry.name().to_string();
+
+        let path = match sanitize_entry_path(&raw) {
+            Some(path) if path.is_empty() => continue,
+            Some(path) => path,
+            None => {
+                send_entry(tx, ArchiveEntry::Skipped(raw))?;
+                continue;
+            }
+        };
+
+        if entry.is_dir() {
+            send_entry(tx, ArchiveEntry::Dir(path))?;
+        } else if entry.is_symlink() {
+            send_entry(tx, ArchiveEntry::Skipped(path))?;
+        } else {
+            let mode = entry.unix_mode();
+            send_entry(tx, ArchiveEntry::File { path, mode })?;
+            stream_entry(&mut entry, tx)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn stream_entry(reader: &mut impl Read, tx: &mpsc::Sender<ArchiveEntry>) -> anyhow::Result<()> {
+    let mut buffer = vec![0u8; ENTRY_CHUNK_SIZE];
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(());
+        }
+
+        send_entry(tx, ArchiveEntry::Data(buffer[..read].to_vec()))?;
+    }
+}
+
+fn send_entry(tx: &mpsc::Sender<ArchiveEntry>, entry: ArchiveEntry) -> anyhow::Result<()> {
+    // the receiver is only dropped when writing over sftp failed
+    tx.blocking_send(entry)
+        .map_err(|_| anyhow::anyhow!("extraction aborted"))
+}
+
+fn archive_format(path: &str) -> Option<ArchiveFormat> {
+    let path = path.to_lowercase();
+
+    if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+        Some(ArchiveFormat::TarGz)
+    } else if path.ends_with(".tar") {
+        Some(ArchiveFormat::Tar)
+    } else if path.ends_with(".zip") {
+        Some(ArchiveFormat::Zip)
+    } else {
+        None
+    }
+}
+
+fn parent_dir(path: &str) -> &str {
+    match path.rsplit_once('/') {
+        Some(("", _)) => "/",
+        Some((parent, _)) => parent,
+        None => ".",
+    }
+}
+
+/// Makes an archive entry path relative to the extraction directory. Leading
+/// slashes and `.` components are dropped, any `..` rejects the entry.
+fn sanitize_entry_path(raw: &str) -> Option<String> {
+    let mut parts = vec![];
+
+    for part in raw.split(['/', '\\']) {
+        match part {
+            "" | "." => continue,
+            ".." => return None,
+            part => parts.push(part),
+        }
+    }
+
+    Some(parts.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_archive_format_from_extension() {
+        assert_eq!(
+            archive_format("/srv/app.tar.gz"),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(archive_format("/srv/app.TGZ"), Some(ArchiveFormat::TarGz));
+        assert_eq!(archive_format("/srv/app.tar"), Some(ArchiveFormat::Tar));
+        assert_eq!(archive_format("/srv/app.zip"), Some(ArchiveFormat::Zip));
+        assert_eq!(archive_format("/srv/app.rar"), None);
+    }
+
+    #[test]
+    fn sanitize_keeps_relative_paths() {
+        assert_eq!(
+            sanitize_entry_path("config/app.toml"),
+            Some("config/app.toml".to_string())
+        );
+        assert_eq!(
+            sanitize_entry_path("./config//app.toml"),
+            Some("config/app.toml".to_string())
+        );
+        assert_eq!(sanitize_entry_path("./"), Some(String::new()));
+    }
+
+    #[test]
+    fn sanitize_strips_leading_slashes() {
+        assert_eq!(
+            sanitize_entry_path("/etc/passwd"),
+            Some("etc/passwd".to_string())
+        );
+    }
+
+    #[test]
+    fn sanitize_rejects_parent_components() {
+        assert_eq!(sanitize_entry_path("../outside"), None);
+        assert_eq!(sanitize_entry_path("config/../../outside"), None);
+        assert_eq!(sanitize_entry_path("config\\..\\..\\outside"), None);
+    }
+
+    #[test]
+    fn parent_dir_of_archive() {
+        assert_eq!(parent_dir("/srv/bundle.tar.gz"), "/srv");
+        assert_eq!(parent_dir("/bundle.zip"), "/");
+    }
+}
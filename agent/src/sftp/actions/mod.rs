@@ -1,4 +1,18 @@
+pub mod copy;
 pub mod delete;
+pub mod disk_usage;
 pub mod download;
+pub mod extract;
 pub mod list_dir;
+pub mod progress;
+pub mod search;
 pub mod upload;
+pub mod watch;
+
+pub(crate) fn join_path(dir: &str, name: &str) -> String {
+    if dir.ends_with('/') {
+        format!("{}{}", dir, name)
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
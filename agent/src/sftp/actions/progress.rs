@@ -0,0 +1,97 @@
+use log::warn;
+use phirepass_common::protocol::common::Frame;
+use phirepass_common::protocol::node::{NodeFrameData, WebFrameId};
+use phirepass_common::protocol::sftp::{SFTPOperationKind, SFTPOperationProgress};
+use phirepass_common::protocol::web::WebFrameData;
+use tokio::sync::mpsc::Sender;
+use tokio::time::{Duration, Instant};
+
+// How often progress frames are sent while an operation is running
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+pub(crate) struct OperationProgress<'a> {
+    tx: &'a Sender<Frame>,
+    sid: u32,
+    msg_id: Option<u32>,
+    progress: SFTPOperationProgress,
+    last_sent: Instant,
+}
+
+impl<'a> OperationProgress<'a> {
+    pub fn new(
+        tx: &'a Sender<Frame>,
+        sid: u32,
+        msg_id: Option<u32>,
+        kind: SFTPOperationKind,
+    ) -> Self {
+        Self {
+            tx,
+            sid,
+            msg_id,
+            progress: SFTPOperationProgress {
+                kind,
+                path: String::new(),
+                entries_done: 0,
+                skipped_entries: 0,
+                bytes_done: 0,
+                total_bytes: None,
+                done: false,
+            },
+            last_sent: Instant::now(),
+        }
+    }
+
+    pub fn set_total_bytes(&mut self, total_bytes: u64) {
+        self.progress.total_bytes = Some(total_bytes);
+    }
+
+    pub async fn entry(&mut self, path: &str) {
+        self.progress.entries_done += 1;
+        self.progress.path = path.to_string();
+        self.maybe_send().await;
+    }
+
+    pub async fn skip(&mut self, path: &str) {
+        self.progress.skipped_entries += 1;
+        self.progress.path = path.to_string();
+        self.maybe_send().await;
+    }
+
+    pub async fn bytes(&mut self, bytes: u64) {
+        self.progress.bytes_done += bytes;
+        self.maybe_send().await;
+    }
+
+    pub async fn finish(mut self) {
+        self.progress.done = true;
+        self.send().await;
+    }
+
+    async fn maybe_send(&mut self) {
+        if self.last_sent.elapsed() >= PROGRESS_INTERVAL {
+            self.send().await;
+        }
+    }
+
+    async fn send(&mut self) {
+        self.last_sent = Instant::now();
+
+        if let Err(err) = self
+            .tx
+            .send(
+                NodeFrameData::WebFrame {
+                    frame: WebFrameData::SFTPOperationProgress {
+                        sid: self.sid,
+                        msg_id: self.msg_id,
+                        progress: self.progress.clone(),
+                    },
+                    id: WebFrameId::SessionId(self.sid),
+                }
+                .into(),
+            )
+            .await
+        {
+            warn!("failed to send sftp operation progress: {err}");
+        }
+    }
+}
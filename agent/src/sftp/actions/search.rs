@@ -0,0 +1,272 @@
+use crate::sftp::actions::join_path;
+use log::{debug, info, warn};
+use phirepass_common::protocol::common::{Frame, FrameError};
+use phirepass_common::protocol::node::{NodeFrameData, WebFrameId};
+use phirepass_common::protocol::sftp::{
+    SFTPListItem, SFTPListItemAttributes, SFTPListItemKind, SFTPSearch, SFTPSearchResults,
+};
+use phirepass_common::protocol::web::WebFrameData;
+use russh_sftp::client::SftpSession;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::time::Instant;
+
+const DEFAULT_MAX_DEPTH: u32 = 16;
+const MAX_DEPTH_LIMIT: u32 = 64;
+const DEFAULT_MAX_RESULTS: u32 = 1_000;
+const MAX_RESULTS_LIMIT: u32 = 10_000;
+const DEFAULT_TIMEOUT_SECS: u32 = 30;
+const MAX_TIMEOUT_SECS: u32 = 300;
+
+// Matches are flushed to the browser in batches of this size, or sooner
+// once the oldest unsent match has waited this long
+const BATCH_SIZE: usize = 100;
+const FLUSH_INTERVAL: Duration = Duration::from_millis(250);
+
+pub async fn send_search_results(
+    tx: &Sender<Frame>,
+    sftp_session: &SftpSession,
+    data: &SFTPSearch,
+    sid: u32,
+    msg_id: Option<u32>,
+) {
+    info!("searching {} for {}", data.path, data.pattern);
+
+    if let Err(err) = search(tx, sftp_session, data, sid, msg_id).await {
+        warn!("failed to search {} for {}: {err}", data.path, data.pattern);
+        if let Err(send_err) = tx
+            .send(
+                NodeFrameData::WebFrame {
+                    frame: WebFrameData::Error {
+                        kind: FrameError::Generic,
+                        message: format!("Failed to search: {}", err),
+                        msg_id,
+                    },
+                    id: WebFrameId::SessionId(sid),
+                }
+                .into(),
+            )
+            .await
+        {
+            warn!("failed to send error frame for search: {send_err}");
+        }
+    }
+}
+
+async fn search(
+    tx: &Sender<Frame>,
+    sftp_session: &SftpSession,
+    data: &SFTPSearch,
+    sid: u32,
+    msg_id: Option<u32>,
+) -> anyhow::Result<()> {
+    if data.pattern.is_empty() {
+        anyhow::bail!("search pattern is empty");
+    }
+
+    let abs_path = sftp_session.canonicalize(data.path.as_str()).await?;
+
+    let max_depth = limit(data.max_depth, DEFAULT_MAX_DEPTH, MAX_DEPTH_LIMIT);
+    let max_results = limit(data.max_results, DEFAULT_MAX_RESULTS, MAX_RESULTS_LIMIT) as usize;
+    let timeout_secs = limit(data.timeout_secs, DEFAULT_TIMEOUT_SECS, MAX_TIMEOUT_SECS);
+    let deadline = Instant::now() + Duration::from_secs(timeout_secs as u64);
+
+    let mut results = SFTPSearchResults {
+        path: abs_path.clone(),
+        items: vec![],
+        done: false,
+        truncated: false,
+        timed_out: false,
+    };
+
+    let mut found = 0usize;
+    let mut pending = vec![(abs_path.clone(), 1u32)];
+    let mut last_flush = Instant::now();
+
+    'walk: while let Some((dir, depth)) = pending.pop() {
+        if !results.items.is_empty() && last_flush.elapsed() >= FLUSH_INTERVAL {
+            send_batch(tx, &mut results, sid, msg_id).await?;
+            last_flush = Instant::now();
+        }
+
+        let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+            results.timed_out = true;
+            break;
+        };
+
+        let read_dir =
+            match tokio::time::timeout(remaining, sftp_session.read_dir(dir.as_str())).await {
+                Ok(Ok(read_dir)) => read_dir,
+                Ok(Err(err)) if dir == abs_path => return Err(err.into()),
+                Ok(Err(err)) => {
+                    debug!("skipping unreadable directory {dir}: {err}");
+                    continue;
+                }
+                Err(_) => {
+                    results.timed_out = true;
+                    break;
+                }
+            };
+
+        for entry in read_dir {
+            let name = entry.file_name();
+            let is_dir = entry.file_type().is_dir();
+
+            if is_dir {
+                if depth < max_depth {
+                    pending.push((join_path(&dir, &name), depth + 1));
+                } else {
+                    results.truncated = true;
+                }
+            }
+
+            if !matches(&data.pattern, &name) {
+                continue;
+            }
+
+            if found >= max_results {
+                results.truncated = true;
+                break 'walk;
+            }
+
+            found += 1;
+            results.items.push(SFTPListItem {
+                name,
+                path: dir.clone(),
+                kind: if is_dir {
+                    SFTPListItemKind::Folder
+                } else {
+                    SFTPListItemKind::File
+                },
+                items: vec![],
+                attributes: SFTPListItemAttributes {
+                    size: entry.metadata().size.unwrap_or(0),
+                },
+            });
+
+            if results.items.len() >= BATCH_SIZE || last_flush.elapsed() >= FLUSH_INTERVAL {
+                send_batch(tx, &mut results, sid, msg_id).await?;
+                last_flush = Instant::now();
+            }
+        }
+    }
+
+    debug!("search in {abs_path} found {found} matches");
+
+    results.done = true;
+    send_batch(tx, &mut results, sid, msg_id).await
+}
+
+async fn send_batch(
+    tx: &Sender<Frame>,
+    results: &mut SFTPSearchResults,
+    sid: u32,
+    msg_id: Option<u32>,
+) -> anyhow::Result<()> {
+    let batch = SFTPSearchResults {
+        path: results.path.clone(),
+        items: std::mem::take(&mut results.items),
+        done: results.done,
+        truncated: results.truncated,
+        timed_out: results.timed_out,
+    };
+
+    tx.send(
+        NodeFrameData::WebFrame {
+            frame: WebFrameData::SFTPSearchResults {
+                sid,
+                msg_id,
+                results: batch,
+            },
+            id: WebFrameId::SessionId(sid),
+        }
+        .into(),
+    )
+    .await
+    .map_err(|err| anyhow::anyhow!("failed to send search results: {err}"))
+}
+
+fn limit(value: u32, default: u32, max: u32) -> u32 {
+    match value {
+        0 => default,
+        n => n.min(max),
+    }
+}
+
+/// Matches a file name against a `*`/`?` glob. Patterns without wildcards
+/// are treated as a case-insensitive substring search.
+fn matches(pattern: &str, name: &str) -> bool {
+    if !pattern.contains(['*', '?']) {
+        return name.to_lowercase().contains(&pattern.to_lowercase());
+    }
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            // let the last `*` swallow one more character and retry
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_text_matches_as_case_insensitive_substring() {
+        assert!(matches("report", "Q3-Report.pdf"));
+        assert!(!matches("report", "summary.pdf"));
+    }
+
+    #[test]
+    fn star_matches_any_sequence() {
+        assert!(matches("*.log", "app.log"));
+        assert!(matches("*.log", ".log"));
+        assert!(matches("app*.log", "app-2024-01-01.log"));
+        assert!(!matches("*.log", "app.log.gz"));
+        assert!(matches("*", "anything"));
+    }
+
+    #[test]
+    fn question_mark_matches_single_character() {
+        assert!(matches("file?.txt", "file1.txt"));
+        assert!(!matches("file?.txt", "file10.txt"));
+        assert!(!matches("file?.txt", "file.txt"));
+    }
+
+    #[test]
+    fn glob_is_case_sensitive() {
+        assert!(!matches("*.LOG", "app.log"));
+    }
+
+    #[test]
+    fn backtracks_across_multiple_stars() {
+        assert!(matches("*a*b*c", "xxaxxbxxc"));
+        assert!(!matches("*a*b*c", "xxaxxcxxb"));
+    }
+
+    #[test]
+    fn limit_uses_default_for_zero_and_caps_at_max() {
+        assert_eq!(limit(0, 10, 100), 10);
+        assert_eq!(limit(50, 10, 100), 50);
+        assert_eq!(limit(500, 10, 100), 100);
+    }
+}
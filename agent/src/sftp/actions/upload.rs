@@ -1,5 +1,8 @@
+use crate::audit::Access;
+use crate::metrics::{METRICS, Transfer};
 use crate::sftp::{FileUpload, SFTPActiveUploads, cleanup_abandoned_uploads, generate_id};
 use log::{debug, info, warn};
+use phirepass_common::protocol::Protocol;
 use phirepass_common::protocol::common::{Frame, FrameError};
 use phirepass_common::protocol::node::{NodeFrameData, WebFrameId};
 use phirepass_common::protocol::sftp::{SFTPUploadChunk, SFTPUploadStart, SFTPUploadStartResponse};
@@ -21,6 +24,7 @@ use uuid::Uuid;
 //   100ms = ~640 KB/s max
 const UPLOAD_CHUNK_ACK_DELAY_MS: u64 = 0;
 
+#[allow(clippy::too_many_arguments)]
 pub async fn start_upload(
     tx: &Sender<Frame>,
     sftp_session: &SftpSession,
@@ -28,6 +32,7 @@ pub async fn start_upload(
     cid: Uuid,
     sid: u32,
     msg_id: Option<u32>,
+    access: &Access,
     uploads: &SFTPActiveUploads,
 ) {
     cleanup_abandoned_uploads(uploads).await;
@@ -71,6 +76,7 @@ pub async fn start_upload(
                     temp_path: temp_path.clone(),
                     started_at: now,
                     last_updated: now,
+                    meter: access.transfer(true, &file_path),
                 },
             );
             info!(
@@ -95,6 +101,7 @@ pub async fn start_upload(
         }
         Err(err) => {
             warn!("failed to open file on SFTP: {err}");
+            METRICS.transfer_failed(Transfer::Upload);
             let _ = tx
                 .send(
                     NodeFrameData::WebFrame {
@@ -160,6 +167,7 @@ pub async fn upload_file_chunk(
         if let Some(ref mut upload) = file_upload {
             if let Err(err) = upload.sftp_file.write_all(chunk.data.as_ref()).await {
                 warn!("failed to write final chunk to SFTP file: {err}");
+                METRICS.transfer_failed(Transfer::Upload);
                 let _ = tx
                     .send(
                         NodeFrameData::WebFrame {
@@ -176,6 +184,9 @@ pub async fn upload_file_chunk(
                 return;
             }
 
+            METRICS.bytes_in(Protocol::SFTP, chunk.data.len());
+            upload.meter.add(chunk.data.len());
+
             // Close the file by dropping the whole FileUpload struct
             // (the sftp_file will be closed when dropped)
             debug!("closed file on SFTP after final chunk");
@@ -191,6 +202,8 @@ pub async fn upload_file_chunk(
             match sftp_session.rename(&upload.temp_path, &file_path).await {
                 Ok(_) => {
                     info!("file upload complete: {}", file_path);
+                    METRICS.transfer_completed(Transfer::Upload);
+                    upload.meter.complete();
 
                     // Send acknowledgment for the final chunk
                     let _ = tx
@@ -209,6 +222,7 @@ pub async fn upload_file_chunk(
                 }
                 Err(err) => {
                     warn!("failed to rename file on SFTP: {}", err);
+                    METRICS.transfer_failed(Transfer::Upload);
                     let _ = tx
                         .send(
                             NodeFrameData::WebFrame {
@@ -251,6 +265,7 @@ pub async fn upload_file_chunk(
                     "failed to write chunk {} to SFTP file: {err}",
                     chunk.chunk_index
                 );
+                METRICS.transfer_failed(Transfer::Upload);
                 if let Some((_, file_upload)) = uploads.remove(&key) {
                     debug!(
                         "closed sftp file for upload due to write error: {}",
@@ -273,6 +288,9 @@ pub async fn upload_file_chunk(
                     .await;
                 return;
             }
+            METRICS.bytes_in(Protocol::SFTP, chunk.data.len());
+            file_upload.meter.add(chunk.data.len());
+
             // Update last_updated timestamp after successful write
             file_upload.last_updated = std::time::SystemTime::now();
             debug!(
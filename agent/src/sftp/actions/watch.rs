@@ -0,0 +1,367 @@
+use log::{debug, info, warn};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use phirepass_common::protocol::common::{Frame, FrameError};
+use phirepass_common::protocol::node::{NodeFrameData, WebFrameId};
+use phirepass_common::protocol::sftp::{
+    SFTPListItem, SFTPListItemAttributes, SFTPListItemKind, SFTPWatch, SFTPWatchChange,
+    SFTPWatchChangeKind, SFTPWatchEvent,
+};
+use phirepass_common::protocol::web::WebFrameData;
+use russh_sftp::client::SftpSession;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::Sender;
+use tokio::time::{Duration, Instant, Interval, MissedTickBehavior, interval_at, sleep};
+
+// Bursts of inotify events (e.g. an editor saving a file) trigger a single rescan
+const NOTIFY_DEBOUNCE: Duration = Duration::from_millis(250);
+
+// With inotify the poll only guards against paths that differ from what the
+// sftp server sees (chroots), so it runs much less often
+const NOTIFY_FALLBACK_FACTOR: u32 = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+struct EntryState {
+    is_dir: bool,
+    size: u64,
+    mtime: Option<u32>,
+}
+
+enum WatchTrigger {
+    Poll(Interval),
+    Notify {
+        _watcher: RecommendedWatcher,
+        events: mpsc::Receiver<()>,
+        fallback: Interval,
+        dirty: bool, // survives the select! in the sftp loop cancelling a debounce
+    },
+}
+
+pub(crate) struct DirectoryWatch {
+    path: String,
+    msg_id: Option<u32>,
+    entries: HashMap<String, EntryState>,
+    trigger: WatchTrigger,
+}
+
+impl DirectoryWatch {
+    /// Resolves once the directory may have changed and should be rescanned.
+    pub async fn changed(&mut self) {
+        match &mut self.trigger {
+            WatchTrigger::Poll(interval) => {
+                interval.tick().await;
+            }
+            WatchTrigger::Notify {
+                events,
+                fallback,
+                dirty,
+                ..
+            } => {
+                if !*dirty {
+                    tokio::select! {
+                        Some(_) = events.recv() => *dirty = true,
+                        _ = fallback.tick() => return,
+                    }
+                }
+
+                sleep(NOTIFY_DEBOUNCE).await;
+                while events.try_recv().is_ok() {}
+                *dirty = false;
+            }
+        }
+    }
+
+    async fn rescan(&mut self, sftp_session: &SftpSession) -> anyhow::Result<Vec<SFTPWatchChange>> {
+        let entries = scan(sftp_session, &self.path).await?;
+        let changes = diff(&self.entries, &entries)
+            .into_iter()
+            .map(|(kind, name, state)| SFTPWatchChange {
+                kind,
+                item: list_item(&self.path, name, state),
+            })
+            .collect();
+
+        self.entries = entries;
+        Ok(changes)
+    }
+}
+
+pub(crate) async fn start_watch(
+    tx: &Sender<Frame>,
+    sftp_session: &SftpSession,
+    data: &SFTPWatch,
+    host: &str,
+    poll_interval: Duration,
+    sid: u32,
+    msg_id: Option<u32>,
+) -> Option<DirectoryWatch> {
+    match create_watch(sftp_session, data, host, poll_interval, msg_id).await {
+        Ok(watch) => {
+            info!("watching {} for changes", watch.path);
+            Some(watch)
+        }
+        Err(err) => {
+            warn!("failed to watch {}: {err}", data.path);
+            send_error(
+                tx,
+                sid,
+                msg_id,
+                format!("Failed to watch directory: {}", err),
+            )
+            .await;
+            None
+        }
+    }
+}
+
+/// Rescans the watched directory and pushes any differences to the browser.
+/// Returns false when the directory can no longer be read and the watch
+/// should be dropped.
+pub(crate) async fn send_watch_changes(
+    tx: &Sender<Frame>,
+    sftp_session: &SftpSession,
+    watch: &mut DirectoryWatch,
+    sid: u32,
+) -> bool {
+    let changes = match watch.rescan(sftp_session).await {
+        Ok(changes) => changes,
+        Err(err) => {
+            warn!("stopped watching {}: {err}", watch.path);
+            send_error(
+                tx,
+                sid,
+                watch.msg_id,
+                format!("Stopped watching {}: {}", watch.path, err),
+            )
+            .await;
+            return false;
+        }
+    };
+
+    if changes.is_empty() {
+        return true;
+    }
+
+    debug!(
+        "{} changes in watched directory {}",
+        changes.len(),
+        watch.path
+    );
+
+    if let Err(err) = tx
+        .send(
+            NodeFrameData::WebFrame {
+                frame: WebFrameData::SFTPWatchEvent {
+                    sid,
+                    msg_id: watch.msg_id,
+                    event: SFTPWatchEvent {
+                        path: watch.path.clone(),
+                        changes,
+                    },
+                },
+                id: WebFrameId::SessionId(sid),
+            }
+            .into(),
+        )
+        .await
+    {
+        warn!("failed to send watch event for {}: {err}", watch.path);
+    }
+
+    true
+}
+
+async fn create_watch(
+    sftp_session: &SftpSession,
+    data: &SFTPWatch,
+    host: &str,
+    poll_interval: Duration,
+    msg_id: Option<u32>,
+) -> anyhow::Result<DirectoryWatch> {
+    let path = sftp_session.canonicalize(data.path.as_str()).await?;
+    let entries = scan(sftp_session, &path).await?;
+
+    let trigger = if is_local_target(host) {
+        match notify_trigger(&path, poll_interval * NOTIFY_FALLBACK_FACTOR) {
+            Ok(trigger) => trigger,
+            Err(err) => {
+                debug!("inotify unavailable for {path}, polling instead: {err}");
+                WatchTrigger::Poll(poll(poll_interval))
+            }
+        }
+    } else {
+        WatchTrigger::Poll(poll(poll_interval))
+    };
+
+    Ok(DirectoryWatch {
+        path,
+        msg_id,
+        entries,
+        trigger,
+    })
+}
+
+fn notify_trigger(path: &str, fallback: Duration) -> notify::Result<WatchTrigger> {
+    let (events_tx, events) = mpsc::channel(1);
+
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if event.is_ok() {
+            // a full channel already means a rescan is pending
+            let _ = events_tx.try_send(());
+        }
+    })?;
+    watcher.watch(Path::new(path), RecursiveMode::NonRecursive)?;
+
+    Ok(WatchTrigger::Notify {
+        _watcher: watcher,
+        events,
+        fallback: poll(fallback),
+        dirty: false,
+    })
+}
+
+fn poll(period: Duration) -> Interval {
+    let mut interval = interval_at(Instant::now() + period, period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    interval
+}
+
+async fn scan(
+    sftp_session: &SftpSession,
+    path: &str,
+) -> anyhow::Result<HashMap<String, EntryState>> {
+    let mut entries = HashMap::new();
+
+    for entry in sftp_session.read_dir(path).await? {
+        let metadata = entry.metadata();
+        entries.insert(
+            entry.file_name(),
+            EntryState {
+                is_dir: entry.file_type().is_dir(),
+                size: metadata.size.unwrap_or(0),
+                mtime: metadata.mtime,
+            },
+        );
+    }
+
+    Ok(entries)
+}
+
+fn diff<'a>(
+    old: &'a HashMap<String, EntryState>,
+    new: &'a HashMap<String, EntryState>,
+) -> Vec<(SFTPWatchChangeKind, &'a str, &'a EntryState)> {
+    let mut changes = vec![];
+
+    for (name, state) in new {
+        match old.get(name) {
+            None => changes.push((SFTPWatchChangeKind::Created, name.as_str(), state)),
+            Some(previous) if previous != state => {
+                changes.push((SFTPWatchChangeKind::Modified, name.as_str(), state))
+            }
+            Some(_) => {}
+        }
+    }
+
+    for (name, state) in old {
+        if !new.contains_key(name) {
+            changes.push((SFTPWatchChangeKind::Deleted, name.as_str(), state));
+        }
+    }
+
+    changes.sort_by(|a, b| a.1.cmp(b.1));
+    changes
+}
+
+fn list_item(path: &str, name: &str, state: &EntryState) -> SFTPListItem {
+    SFTPListItem {
+        name: name.to_string(),
+        path: path.to_string(),
+        kind: if state.is_dir {
+            SFTPListItemKind::Folder
+        } else {
+            SFTPListItemKind::File
+        },
+        items: vec![],
+        attributes: SFTPListItemAttributes { size: state.size },
+    }
+}
+
+/// inotify only helps when the sftp server runs on this machine.
+fn is_local_target(host: &str) -> bool {
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .trim_matches(['[', ']'])
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+async fn send_error(tx: &Sender<Frame>, sid: u32, msg_id: Option<u32>, message: String) {
+    if let Err(err) = tx
+        .send(
+            NodeFrameData::WebFrame {
+                frame: WebFrameData::Error {
+                    kind: FrameError::Generic,
+                    message,
+                    msg_id,
+                },
+                id: WebFrameId::SessionId(sid),
+            }
+            .into(),
+        )
+        .await
+    {
+        warn!("failed to send error frame for watch: {err}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(size: u64, mtime: u32) -> EntryState {
+        EntryState {
+            is_dir: false,
+            size,
+            mtime: Some(mtime),
+        }
+    }
+
+    #[test]
+    fn diff_reports_created_modified_and_deleted() {
+        let old = HashMap::from([
+            ("kept.txt".to_string(), state(1, 1)),
+            ("changed.txt".to_string(), state(1, 1)),
+            ("removed.txt".to_string(), state(1, 1)),
+        ]);
+        let new = HashMap::from([
+            ("kept.txt".to_string(), state(1, 1)),
+            ("changed.txt".to_string(), state(2, 5)),
+            ("added.txt".to_string(), state(3, 1)),
+        ]);
+
+        let changes = diff(&old, &new);
+        let names: Vec<&str> = changes.iter().map(|(_, name, _)| *name).collect();
+        assert_eq!(names, vec!["added.txt", "changed.txt", "removed.txt"]);
+        assert!(matches!(changes[0].0, SFTPWatchChangeKind::Created));
+        assert!(matches!(changes[1].0, SFTPWatchChangeKind::Modified));
+        assert!(matches!(changes[2].0, SFTPWatchChangeKind::Deleted));
+    }
+
+    #[test]
+    fn diff_is_empty_without_changes() {
+        let entries = HashMap::from([("a".to_string(), state(1, 1))]);
+        assert!(diff(&entries, &entries.clone()).is_empty());
+    }
+
+    #[test]
+    fn detects_local_targets() {
+        assert!(is_local_target("localhost"));
+        assert!(is_local_target("127.0.0.1"));
+        assert!(is_local_target("[::1]"));
+        assert!(!is_local_target("10.0.0.5"));
+        assert!(!is_local_target("example.com"));
+    }
+}
@@ -1,43 +1,60 @@
+use crate::audit::Access;
 use crate::common::send_frame_data;
-use crate::error::{AgentError, message_error};
+use crate::error::AgentError;
 use crate::session::generate_session_id;
+use crate::sftp::actions::copy::copy;
 use crate::sftp::actions::delete::delete_file;
+use crate::sftp::actions::disk_usage::send_disk_usage;
 use crate::sftp::actions::download;
+use crate::sftp::actions::extract::extract;
 use crate::sftp::actions::list_dir::send_directory_listing;
+use crate::sftp::actions::search::send_search_results;
 use crate::sftp::actions::upload::{start_upload, upload_file_chunk};
-use crate::sftp::client::SFTPClient;
+use crate::sftp::actions::watch::{DirectoryWatch, send_watch_changes, start_watch};
 use crate::sftp::session::SFTPCommand;
 use crate::sftp::{SFTPActiveDownloads, SFTPActiveUploads};
-use log::{debug, info};
+use crate::ssh::pool::{self, Target};
+use log::{debug, info, warn};
 use phirepass_common::protocol::Protocol;
-use phirepass_common::protocol::common::Frame;
+use phirepass_common::protocol::common::{Frame, FrameError};
 use phirepass_common::protocol::node::{NodeFrameData, WebFrameId};
-use russh::client::Handle;
-use russh::{Disconnect, Preferred, client, kex};
+use phirepass_common::protocol::web::WebFrameData;
 use russh_sftp::client::SftpSession;
-use std::borrow::Cow;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::sync::oneshot;
+use tokio::task::JoinSet;
 use uuid::Uuid;
 
+// Walks, searches, copies and extractions one session may run at once
+const MAX_OPERATIONS: usize = 4;
+
 #[derive(Clone)]
 pub(crate) enum SFTPConfigAuth {
     UsernamePassword(String, String),
     Username(String),
 }
 
+impl SFTPConfigAuth {
+    pub fn username(&self) -> &str {
+        match self {
+            SFTPConfigAuth::UsernamePassword(username, _) | SFTPConfigAuth::Username(username) => {
+                username
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct SFTPConfig {
     pub host: String,
     pub port: u16,
     pub credentials: SFTPConfigAuth,
     pub inactivity_timeout: Option<Duration>,
+    pub watch_interval: Duration, // polling interval when the target is not local
 }
 
-type HandleType = Handle<SFTPClient>;
-
 pub(crate) struct SFTPConnection {
     session_id: u32,
     config: SFTPConfig,
@@ -53,40 +70,21 @@ impl SFTPConnection {
         self.session_id
     }
 
-    async fn create_client(&self) -> Result<HandleType, AgentError> {
-        let sftp_config: SFTPConfig = self.config.clone();
-
-        let config = Arc::new(client::Config {
-            inactivity_timeout: self.config.inactivity_timeout,
-            preferred: Preferred {
-                kex: Cow::Owned(vec![
-                    kex::CURVE25519_PRE_RFC_8731,
-                    kex::EXTENSION_SUPPORT_AS_CLIENT,
-                ]),
-                ..Default::default()
-            },
-            ..<_>::default()
-        });
-
-        let sh = SFTPClient {};
-
-        let mut client_handler =
-            client::connect(config, (sftp_config.host, sftp_config.port), sh).await?;
-
-        let auth_res = match sftp_config.credentials {
+    fn target(&self) -> Target {
+        let (username, password) = match &self.config.credentials {
             SFTPConfigAuth::UsernamePassword(username, password) => {
-                client_handler
-                    .authenticate_password(username, password)
-                    .await
+                (username.clone(), Some(password.clone()))
             }
-            SFTPConfigAuth::Username(username) => client_handler.authenticate_none(username).await,
-        }?;
+            SFTPConfigAuth::Username(username) => (username.clone(), None),
+        };
 
-        if !auth_res.success() {
-            return message_error::<HandleType>("SFTP authentication failed");
+        Target {
+            host: self.config.host.clone(),
+            port: self.config.port,
+            username,
+            password,
+            inactivity_timeout: self.config.inactivity_timeout,
         }
-
-        Ok(client_handler)
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -95,6 +93,7 @@ impl SFTPConnection {
         cid: Uuid,
         tx: &Sender<Frame>,
         msg_id: Option<u32>,
+        access: &Access,
         uploads: &SFTPActiveUploads,
         downloads: &SFTPActiveDownloads,
         mut cmd_rx: Receiver<SFTPCommand>,
@@ -113,8 +112,7 @@ impl SFTPConnection {
             },
         );
 
-        let client = self
-            .create_client()
+        let client = pool::acquire(cid, &self.target(), Protocol::SFTP)
             .await
             .map_err(|e| (WebFrameId::SessionId(sid), e))?;
 
@@ -135,6 +133,15 @@ impl SFTPConnection {
             .map_err(|e| (WebFrameId::SessionId(sid), AgentError::RusshSFTP(e)))?;
 
         info!("sftp[id={sid}] tunnel opened");
+        access.opened();
+
+        let sftp = Arc::new(sftp);
+        let mut watch: Option<DirectoryWatch> = None;
+
+        // Walks, copies and extractions can take minutes; they run beside the
+        // loop so other commands and shutdown are not held up, and are
+        // aborted with the session
+        let mut operations = JoinSet::new();
 
         loop {
             tokio::select! {
@@ -144,14 +151,31 @@ impl SFTPConnection {
                     break;
                 }
                 Some(cmd) = cmd_rx.recv() => {
+                    if cmd.runs_in_background() && operations.len() >= MAX_OPERATIONS {
+                        warn!("sftp[id={sid}] already runs {MAX_OPERATIONS} operations, refusing another");
+                        send_frame_data(
+                            tx,
+                            NodeFrameData::WebFrame {
+                                frame: WebFrameData::Error {
+                                    kind: FrameError::Generic,
+                                    message: "Too many operations in progress; wait for one to finish".to_string(),
+                                    msg_id: cmd.msg_id(),
+                                },
+                                id: WebFrameId::SessionId(sid),
+                            },
+                        );
+                        continue;
+                    }
+
                     match cmd {
                         SFTPCommand::List(folder, msg_id) => {
                             debug!("sftp list command received for folder {folder}: {msg_id:?}");
+                            access.sftp("sftp_list", &folder);
                             send_directory_listing(tx, &sftp, &folder, sid, msg_id).await;
                         }
                         SFTPCommand::DownloadStart { download, msg_id } => {
                             debug!("sftp download start command received for {}/{}: {msg_id:?}", download.path, download.filename);
-                            download::start_download(tx, &sftp, &download, cid, sid, msg_id, downloads).await;
+                            download::start_download(tx, &sftp, &download, cid, sid, msg_id, access, downloads).await;
                         }
                         SFTPCommand::DownloadChunk { chunk, msg_id } => {
                             debug!("sftp download chunk command received for download_id {}: {msg_id:?}", chunk.download_id);
@@ -159,7 +183,7 @@ impl SFTPConnection {
                         }
                         SFTPCommand::UploadStart { upload, msg_id } => {
                             debug!("sftp upload start command received for {}/{}: {msg_id:?}", upload.remote_path, upload.filename);
-                            start_upload(tx, &sftp, &upload, cid, sid, msg_id, uploads).await;
+                            start_upload(tx, &sftp, &upload, cid, sid, msg_id, access, uploads).await;
                         }
                         SFTPCommand::Upload { chunk, msg_id } => {
                             debug!("sftp upload chunk command received for upload_id {}: {msg_id:?}", chunk.upload_id);
@@ -167,18 +191,68 @@ impl SFTPConnection {
                         }
                         SFTPCommand::Delete { data, msg_id } => {
                             debug!("sftp delete command received for {}/{}: {msg_id:?}", data.path, data.filename);
-                            delete_file(tx, &sftp, &data, cid, sid, msg_id, uploads).await;
+                            delete_file(tx, &sftp, &data, cid, sid, msg_id, access, uploads).await;
+                        }
+                        SFTPCommand::DiskUsage { data, msg_id } => {
+                            debug!("sftp disk usage command received for {}: {msg_id:?}", data.path);
+                            let (tx, sftp) = (tx.clone(), Arc::clone(&sftp));
+                            operations.spawn(async move { send_disk_usage(&tx, &sftp, &data, sid, msg_id).await });
+                        }
+                        SFTPCommand::Search { data, msg_id } => {
+                            debug!("sftp search command received for {} in {}: {msg_id:?}", data.pattern, data.path);
+                            let (tx, sftp) = (tx.clone(), Arc::clone(&sftp));
+                            operations.spawn(async move { send_search_results(&tx, &sftp, &data, sid, msg_id).await });
+                        }
+                        SFTPCommand::Copy { data, msg_id } => {
+                            debug!("sftp copy command received for {} to {}: {msg_id:?}", data.source, data.destination);
+                            let (tx, sftp, access) = (tx.clone(), Arc::clone(&sftp), access.clone());
+                            operations.spawn(async move { copy(&tx, &sftp, &data, sid, msg_id, &access).await });
+                        }
+                        SFTPCommand::Extract { data, msg_id } => {
+                            debug!("sftp extract command received for {}: {msg_id:?}", data.path);
+                            let (tx, sftp, access) = (tx.clone(), Arc::clone(&sftp), access.clone());
+                            operations.spawn(async move { extract(&tx, &sftp, &data, sid, msg_id, &access).await });
                         }
+                        SFTPCommand::Watch { data, msg_id } => {
+                            debug!("sftp watch command received for {}: {msg_id:?}", data.path);
+                            watch = start_watch(tx, &sftp, &data, &self.config.host, self.config.watch_interval, sid, msg_id).await;
+                        }
+                        SFTPCommand::Unwatch { msg_id } => {
+                            debug!("sftp unwatch command received: {msg_id:?}");
+                            watch = None;
+                        }
+                    }
+                }
+                Some(res) = operations.join_next(), if !operations.is_empty() => {
+                    if let Err(err) = res {
+                        warn!("sftp operation for {cid} failed: {err}");
+                    }
+                }
+                _ = watch_changed(&mut watch) => {
+                    if let Some(active) = watch.as_mut()
+                        && !send_watch_changes(tx, &sftp, active, sid).await
+                    {
+                        watch = None;
                     }
                 }
             }
         }
 
-        client
-            .disconnect(Disconnect::ByApplication, "", "English")
-            .await
-            .map_err(|e| (WebFrameId::SessionId(sid), AgentError::Russh(e)))?;
+        operations.shutdown().await;
+
+        // Only the sftp channel goes; the pool disconnects the shared client
+        // once it has been idle for a while
+        if let Err(err) = sftp.close().await {
+            warn!("failed to close sftp channel for {cid}: {err}");
+        }
 
         Ok(sid)
     }
 }
+
+async fn watch_changed(watch: &mut Option<DirectoryWatch>) {
+    match watch {
+        Some(watch) => watch.changed().await,
+        None => std::future::pending().await,
+    }
+}
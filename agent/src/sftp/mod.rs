@@ -1,3 +1,4 @@
+use crate::usage::TransferMeter;
 use dashmap::DashMap;
 use log::debug;
 use russh_sftp::client::fs::File;
@@ -12,24 +13,22 @@ pub struct FileUpload {
     pub filename: String,
     pub remote_path: String,
     pub total_chunks: u32,
-    #[allow(dead_code)]
     pub total_size: u64,
     pub sftp_file: File,
     pub temp_path: String,
-    #[allow(dead_code)]
     pub started_at: SystemTime,
     pub last_updated: SystemTime,
+    pub meter: TransferMeter,
 }
 
 pub struct FileDownload {
     pub filename: String,
-    #[allow(dead_code)]
     pub total_size: u64,
     pub total_chunks: u32,
     pub sftp_file: File,
-    #[allow(dead_code)]
     pub started_at: SystemTime,
     pub last_updated: SystemTime,
+    pub meter: TransferMeter,
 }
 
 pub type SFTPActiveUploads = Arc<DashMap<(Uuid, u32), FileUpload>>;
@@ -100,6 +99,5 @@ pub async fn cleanup_abandoned_downloads(downloads: &SFTPActiveDownloads) {
 }
 
 pub mod actions;
-pub mod client;
 pub mod connection;
 pub mod session;
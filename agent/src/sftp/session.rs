@@ -1,6 +1,7 @@
 use log::{debug, info};
 use phirepass_common::protocol::sftp::{
-    SFTPDelete, SFTPDownloadChunk, SFTPDownloadStart, SFTPUploadChunk, SFTPUploadStart,
+    SFTPCopy, SFTPDelete, SFTPDiskUsage, SFTPDownloadChunk, SFTPDownloadStart, SFTPExtract,
+    SFTPSearch, SFTPUploadChunk, SFTPUploadStart, SFTPWatch,
 };
 use tokio::sync::mpsc::Sender;
 use tokio::sync::oneshot;
@@ -28,20 +29,80 @@ pub(crate) enum SFTPCommand {
         data: SFTPDelete,
         msg_id: Option<u32>,
     },
+    DiskUsage {
+        data: SFTPDiskUsage,
+        msg_id: Option<u32>,
+    },
+    Search {
+        data: SFTPSearch,
+        msg_id: Option<u32>,
+    },
+    Copy {
+        data: SFTPCopy,
+        msg_id: Option<u32>,
+    },
+    Extract {
+        data: SFTPExtract,
+        msg_id: Option<u32>,
+    },
+    Watch {
+        data: SFTPWatch,
+        msg_id: Option<u32>,
+    },
+    Unwatch {
+        msg_id: Option<u32>,
+    },
+}
+
+impl SFTPCommand {
+    /// Disk usage walks, searches, copies and extractions, which run beside
+    /// the command loop.
+    pub fn runs_in_background(&self) -> bool {
+        matches!(
+            self,
+            SFTPCommand::DiskUsage { .. }
+                | SFTPCommand::Search { .. }
+                | SFTPCommand::Copy { .. }
+                | SFTPCommand::Extract { .. }
+        )
+    }
+
+    pub fn msg_id(&self) -> Option<u32> {
+        match self {
+            SFTPCommand::List(_, msg_id)
+            | SFTPCommand::DownloadStart { msg_id, .. }
+            | SFTPCommand::DownloadChunk { msg_id, .. }
+            | SFTPCommand::UploadStart { msg_id, .. }
+            | SFTPCommand::Upload { msg_id, .. }
+            | SFTPCommand::Delete { msg_id, .. }
+            | SFTPCommand::DiskUsage { msg_id, .. }
+            | SFTPCommand::Search { msg_id, .. }
+            | SFTPCommand::Copy { msg_id, .. }
+            | SFTPCommand::Extract { msg_id, .. }
+            | SFTPCommand::Watch { msg_id, .. }
+            | SFTPCommand::Unwatch { msg_id } => *msg_id,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub(crate) struct SFTPSessionHandle {
     pub stdin: Sender<SFTPCommand>,
     pub stop: Option<oneshot::Sender<()>>,
+    pub username: String,
+    pub started_at: u64,
 }
 
 impl SFTPSessionHandle {
-    pub async fn shutdown(mut self) {
-        info!("shutting down sftp session");
+    pub fn stop(&mut self) {
         if let Some(stop) = self.stop.take() {
             let _ = stop.send(());
             debug!("sftp self stopped sent");
         }
     }
+
+    pub async fn shutdown(mut self) {
+        info!("shutting down sftp session");
+        self.stop();
+    }
 }
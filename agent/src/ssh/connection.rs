@@ -1,18 +1,17 @@
+use crate::audit::Access;
 use crate::common::{send_frame_data, send_tunnel_data};
-use crate::error::{AgentError, message_error};
+use crate::error::AgentError;
+use crate::metrics::METRICS;
 use crate::session::generate_session_id;
-use crate::ssh::client::SSHClient;
+use crate::ssh::pool::{self, Target};
 use crate::ssh::session::SSHCommand;
 use bytes::Bytes;
 use log::{debug, info, warn};
 use phirepass_common::protocol::Protocol;
 use phirepass_common::protocol::common::Frame;
 use phirepass_common::protocol::node::{NodeFrameData, WebFrameId};
-use russh::client::Handle;
-use russh::{ChannelMsg, Disconnect, Preferred, client, kex};
-use std::borrow::Cow;
+use russh::{ChannelMsg, Disconnect};
 use std::io::Cursor;
-use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::sync::oneshot;
@@ -24,6 +23,16 @@ pub(crate) enum SSHConfigAuth {
     Username(String),
 }
 
+impl SSHConfigAuth {
+    pub fn username(&self) -> &str {
+        match self {
+            SSHConfigAuth::UsernamePassword(username, _) | SSHConfigAuth::Username(username) => {
+                username
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct SSHConfig {
     pub host: String,
@@ -32,8 +41,6 @@ pub(crate) struct SSHConfig {
     pub inactivity_timeout: Option<Duration>,
 }
 
-type HandleType = Handle<SSHClient>;
-
 pub(crate) struct SSHConnection {
     session_id: u32,
     config: SSHConfig,
@@ -49,48 +56,40 @@ impl SSHConnection {
         self.session_id
     }
 
-    async fn create_client(&self) -> Result<HandleType, AgentError> {
-        let ssh_config: SSHConfig = self.config.clone();
-
-        let config = Arc::new(client::Config {
-            inactivity_timeout: self.config.inactivity_timeout,
-            preferred: Preferred {
-                kex: Cow::Owned(vec![
-                    kex::CURVE25519_PRE_RFC_8731,
-                    kex::EXTENSION_SUPPORT_AS_CLIENT,
-                ]),
-                ..Default::default()
-            },
-            ..<_>::default()
-        });
-
-        let sh = SSHClient {};
-
-        let mut client_handler =
-            client::connect(config, (ssh_config.host, ssh_config.port), sh).await?;
-
-        let auth_res = match ssh_config.credentials {
+    fn target(&self) -> Target {
+        let (username, password) = match &self.config.credentials {
             SSHConfigAuth::UsernamePassword(username, password) => {
-                client_handler
-                    .authenticate_password(username, password)
-                    .await
+                (username.clone(), Some(password.clone()))
             }
-            SSHConfigAuth::Username(username) => client_handler.authenticate_none(username).await,
-        }?;
-
-        if !auth_res.success() {
-            return message_error::<HandleType>("SSH authentication failed");
+            SSHConfigAuth::Username(username) => (username.clone(), None),
+        };
+
+        Target {
+            host: self.config.host.clone(),
+            port: self.config.port,
+            username,
+            password,
+            inactivity_timeout: self.config.inactivity_timeout,
         }
+    }
 
-        Ok(client_handler)
+    /// Authenticates against the target and disconnects right away.
+    pub async fn check_login(&self) -> Result<(), AgentError> {
+        let client = pool::connect(&self.target(), Protocol::SSH).await?;
+        client
+            .disconnect(Disconnect::ByApplication, "", "English")
+            .await?;
+        Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn connect(
         &self,
         node_id: Uuid,
         cid: Uuid,
         tx: &Sender<Frame>,
         msg_id: Option<u32>,
+        access: &Access,
         mut cmd_rx: Receiver<SSHCommand>,
         mut shutdown_rx: oneshot::Receiver<()>,
     ) -> Result<u32, (WebFrameId, AgentError)> {
@@ -98,8 +97,7 @@ impl SSHConnection {
 
         let sid = self.get_session_id();
 
-        let client = self
-            .create_client()
+        let client = pool::acquire(cid, &self.target(), Protocol::SSH)
             .await
             .map_err(|e| (WebFrameId::SessionId(sid), e))?;
 
@@ -131,6 +129,7 @@ impl SSHConnection {
         );
 
         info!("ssh[id={sid}] tunnel opened");
+        access.opened();
 
         loop {
             tokio::select! {
@@ -142,6 +141,8 @@ impl SSHConnection {
                 Some(cmd) = cmd_rx.recv() => {
                     match cmd {
                         SSHCommand::Data(buf) => {
+                            METRICS.bytes_in(Protocol::SSH, buf.len());
+                            access.usage().received(buf.len());
                             let bytes = Cursor::new(buf);
                             if let Err(err) = channel.data(bytes).await {
                                 warn!("failed to send data to ssh channel {cid}: {err}");
@@ -164,6 +165,8 @@ impl SSHConnection {
 
                     match msg {
                         ChannelMsg::Data { ref data } => {
+                            METRICS.bytes_out(Protocol::SSH, data.len());
+                            access.usage().sent(data.len());
                             send_tunnel_data(
                                 tx,
                                 sid,
@@ -194,15 +197,12 @@ impl SSHConnection {
             }
         }
 
+        // The client may carry other channels of this connection; the pool
+        // disconnects it once it has been idle for a while
         if let Err(err) = channel.close().await {
             warn!("failed to close ssh channel for {cid}: {err}");
         }
 
-        client
-            .disconnect(Disconnect::ByApplication, "", "English")
-            .await
-            .map_err(|e| (WebFrameId::SessionId(sid), AgentError::Russh(e)))?;
-
         Ok(sid)
     }
 }
@@ -1,4 +1,5 @@
 pub mod auth;
 pub mod client;
 pub mod connection;
+pub mod pool;
 pub mod session;
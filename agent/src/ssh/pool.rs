@@ -0,0 +1,287 @@
+use crate::error::AgentError;
+use crate::metrics::METRICS;
+use crate::ssh::client::SSHClient;
+use dashmap::DashMap;
+use log::{debug, info, warn};
+use phirepass_common::protocol::Protocol;
+use russh::client::{self, Handle};
+use russh::{Disconnect, Preferred, kex};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::ops::Deref;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+type SharedHandle = Arc<Handle<SSHClient>>;
+
+static POOL: OnceLock<ClientPool> = OnceLock::new();
+
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What a tunnel needs to log in to the ssh target.
+#[derive(Clone)]
+pub(crate) struct Target {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: Option<String>,
+    pub inactivity_timeout: Option<Duration>,
+}
+
+/// Opens a client to the target and authenticates it.
+pub(crate) async fn connect(
+    target: &Target,
+    protocol: Protocol,
+) -> Result<Handle<SSHClient>, AgentError> {
+    let config = Arc::new(client::Config {
+        inactivity_timeout: target.inactivity_timeout,
+        preferred: Preferred {
+            kex: Cow::Owned(vec![
+                kex::CURVE25519_PRE_RFC_8731,
+                kex::EXTENSION_SUPPORT_AS_CLIENT,
+            ]),
+            ..Default::default()
+        },
+        ..<_>::default()
+    });
+
+    let mut handle =
+        client::connect(config, (target.host.as_str(), target.port), SSHClient {}).await?;
+
+    let username = target.username.clone();
+    let auth_res = match &target.password {
+        Some(password) => handle.authenticate_password(username, password).await,
+        None => handle.authenticate_none(username).await,
+    }?;
+
+    if !auth_res.success() {
+        METRICS.auth_failure(protocol);
+        return Err(AgentError::AuthFailed(match protocol {
+            Protocol::SSH => "SSH",
+            Protocol::SFTP => "SFTP",
+        }));
+    }
+
+    Ok(handle)
+}
+
+/// Sets how long an authenticated client outlives its last channel.
+pub(crate) fn init(idle_timeout: Duration) {
+    let _ = POOL.set(ClientPool::new(idle_timeout));
+}
+
+/// Borrows an authenticated client for the tunnels of one browser
+/// connection. A terminal and a file browser opened with the same
+/// credentials share one ssh connection as separate channels.
+pub(crate) async fn acquire(
+    cid: Uuid,
+    target: &Target,
+    protocol: Protocol,
+) -> Result<Lease, AgentError> {
+    pool().acquire(cid, target, protocol).await
+}
+
+fn pool() -> &'static ClientPool {
+    POOL.get_or_init(|| ClientPool::new(DEFAULT_IDLE_TIMEOUT))
+}
+
+// Only identical credentials may share a client, so the password is part
+// of the key; it is hashed to keep it out of the map
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct PoolKey {
+    cid: Uuid,
+    host: String,
+    port: u16,
+    username: String,
+    secret: [u8; 32],
+}
+
+impl PoolKey {
+    fn new(cid: Uuid, target: &Target) -> Self {
+        let secret = match &target.password {
+            Some(password) => Sha256::digest(password.as_bytes()).into(),
+            None => [0; 32],
+        };
+
+        Self {
+            cid,
+            host: target.host.clone(),
+            port: target.port,
+            username: target.username.clone(),
+            secret,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Slot {
+    handle: Option<SharedHandle>,
+    refs: usize,
+    // bumped whenever the last lease goes, so only the latest idle timer
+    // may tear the client down
+    idle_generation: u64,
+}
+
+struct ClientPool {
+    slots: DashMap<PoolKey, Arc<Mutex<Slot>>>,
+    idle_timeout: Duration,
+}
+
+impl ClientPool {
+    fn new(idle_timeout: Duration) -> Self {
+        Self {
+            slots: DashMap::new(),
+            idle_timeout,
+        }
+    }
+
+    async fn acquire(
+        &self,
+        cid: Uuid,
+        target: &Target,
+        protocol: Protocol,
+    ) -> Result<Lease, AgentError> {
+        let key = PoolKey::new(cid, target);
+        let slot = Arc::clone(self.slots.entry(key.clone()).or_default().value());
+
+        // Held across the handshake so concurrent tunnels wait for one client
+        let mut guard = slot.lock().await;
+
+        let handle = match &guard.handle {
+            Some(handle) if !handle.is_closed() => {
+                debug!(
+                    "reusing ssh connection to {}:{} for {cid}",
+                    target.host, target.port
+                );
+                Arc::clone(handle)
+            }
+            _ => match connect(target, protocol).await {
+                Ok(handle) => {
+                    let handle = Arc::new(handle);
+                    guard.handle = Some(Arc::clone(&handle));
+                    handle
+                }
+                Err(err) => {
+                    let unused = guard.refs == 0;
+                    drop(guard);
+                    if unused {
+                        self.slots
+                            .remove_if(&key, |_, current| Arc::ptr_eq(current, &slot));
+                    }
+                    return Err(err);
+                }
+            },
+        };
+
+        guard.refs += 1;
+        drop(guard);
+
+        Ok(Lease {
+            key,
+            slot,
+            handle: Some(handle),
+        })
+    }
+
+    async fn release(&self, key: PoolKey, slot: Arc<Mutex<Slot>>, handle: SharedHandle) {
+        let generation = {
+            let mut guard = slot.lock().await;
+            guard.refs = guard.refs.saturating_sub(1);
+            if guard.refs > 0 {
+                return;
+            }
+            guard.idle_generation += 1;
+            guard.idle_generation
+        };
+
+        if !handle.is_closed() {
+            tokio::time::sleep(self.idle_timeout).await;
+        }
+
+        {
+            let mut guard = slot.lock().await;
+            let current = guard
+                .handle
+                .as_ref()
+                .is_some_and(|held| Arc::ptr_eq(held, &handle));
+            if guard.refs > 0 || guard.idle_generation != generation || !current {
+                return;
+            }
+            guard.handle = None;
+        }
+
+        self.slots
+            .remove_if(&key, |_, current| Arc::ptr_eq(current, &slot));
+
+        info!(
+            "closing idle ssh connection to {}:{} for {}",
+            key.host, key.port, key.cid
+        );
+        if let Err(err) = handle
+            .disconnect(Disconnect::ByApplication, "", "English")
+            .await
+        {
+            warn!("failed to disconnect idle ssh connection: {err}");
+        }
+    }
+}
+
+/// A share of a pooled client; the last lease dropped starts its idle timer.
+pub(crate) struct Lease {
+    key: PoolKey,
+    slot: Arc<Mutex<Slot>>,
+    handle: Option<SharedHandle>,
+}
+
+impl Deref for Lease {
+    type Target = Handle<SSHClient>;
+
+    fn deref(&self) -> &Self::Target {
+        self.handle
+            .as_ref()
+            .expect("lease holds a handle until dropped")
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        let Some(handle) = self.handle.take() else {
+            return;
+        };
+
+        // Without a runtime the process is exiting and the client goes with it
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let key = self.key.clone();
+            let slot = Arc::clone(&self.slot);
+            runtime.spawn(async move { pool().release(key, slot, handle).await });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(password: Option<&str>) -> Target {
+        Target {
+            host: "localhost".into(),
+            port: 22,
+            username: "deploy".into(),
+            password: password.map(Into::into),
+            inactivity_timeout: None,
+        }
+    }
+
+    #[test]
+    fn shares_only_identical_credentials() {
+        let cid = Uuid::new_v4();
+        let key = PoolKey::new(cid, &target(Some("secret")));
+
+        assert!(key == PoolKey::new(cid, &target(Some("secret"))));
+        assert!(key != PoolKey::new(cid, &target(Some("other"))));
+        assert!(key != PoolKey::new(cid, &target(None)));
+        assert!(key != PoolKey::new(Uuid::new_v4(), &target(Some("secret"))));
+    }
+}
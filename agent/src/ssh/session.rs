@@ -18,13 +18,19 @@ pub(crate) enum SSHCommand {
 pub(crate) struct SSHSessionHandle {
     pub stdin: Sender<SSHCommand>,
     pub stop: Option<oneshot::Sender<()>>,
+    pub username: String,
+    pub started_at: u64,
 }
 
 impl SSHSessionHandle {
-    pub async fn shutdown(mut self) {
+    pub fn stop(&mut self) {
         if let Some(stop) = self.stop.take() {
             let _ = stop.send(());
             debug!("ssh self stopped sent");
         }
     }
+
+    pub async fn shutdown(mut self) {
+        self.stop();
+    }
 }
@@ -0,0 +1,306 @@
+use crate::drain::CLOSE_WAIT;
+use crate::env::Env;
+use anyhow::Context;
+use directories::{BaseDirs, ProjectDirs};
+use log::{debug, info, warn};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Once;
+
+static READY: Once = Once::new();
+
+/// Sends a state update to the service manager when the agent runs under
+/// systemd with `Type=notify`; a no-op otherwise.
+pub(crate) fn notify(state: &str) {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    if let Err(err) = send(Path::new(&path), state) {
+        debug!("failed to notify systemd: {err}");
+    }
+}
+
+/// Reports readiness once, after the first websocket authenticates.
+pub(crate) fn ready() {
+    READY.call_once(|| notify("READY=1\nSTATUS=connected"));
+}
+
+pub(crate) fn watchdog() {
+    notify("WATCHDOG=1");
+}
+
+fn send(path: &Path, state: &str) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    let socket = UnixDatagram::unbound()?;
+
+    match path.as_os_str().as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => return Err(std::io::Error::other("abstract sockets need linux")),
+        None => {
+            socket.send_to(state.as_bytes(), path)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) struct UnitOptions {
+    pub(crate) name: String,
+    pub(crate) user: String,
+    pub(crate) exec: PathBuf,
+    pub(crate) config: Option<PathBuf>,
+    pub(crate) profiles: Vec<String>,
+    pub(crate) data_dir: Option<PathBuf>,
+    pub(crate) audit_dir: Option<PathBuf>,
+    pub(crate) watchdog_secs: u64,
+    pub(crate) stop_timeout_secs: u64,
+}
+
+/// Watchdog period for the unit: the agent feeds it on every heartbeat and
+/// before every reconnect delay, so allow three of the longer of the two.
+pub(crate) fn watchdog_secs(env: &Env) -> u64 {
+    let max_delay_secs = env.reconnect_max_delay_ms.div_ceil(1000);
+    (env.stats_refresh_interval as u64)
+        .max(max_delay_secs)
+        .saturating_mul(3)
+        .max(60)
+}
+
+/// Stop timeout for the unit: a drain may run for the longer of
+/// `DRAIN_TIMEOUT` and `DRAIN_NOTICE` plus the close wait, and shutting down
+/// takes a little more, so systemd must not kill the agent before that.
+pub(crate) fn stop_timeout_secs(env: &Env) -> u64 {
+    env.drain_timeout_secs
+        .max(env.drain_notice_secs)
+        .saturating_add(CLOSE_WAIT.as_secs())
+        .saturating_add(10)
+        .max(30)
+}
+
+pub(crate) fn render_unit(options: &UnitOptions) -> String {
+    let mut exec = quote(&options.exec.to_string_lossy());
+    if let Some(config) = &options.config {
+        let _ = write!(exec, " --config {}", quote(&config.to_string_lossy()));
+    }
+    exec.push_str(" start");
+    for profile in &options.profiles {
+        let _ = write!(exec, " --profile {}", quote(profile));
+    }
+
+    let mut unit = format!(
+        "[Unit]
+Description=Phirepass agent
+Documentation=https://github.com/dimitrmo/phirepass-rs
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=notify
+NotifyAccess=main
+ExecStart={exec}
+User={user}
+Restart=always
+RestartSec=5
+# Ready is reported once the websocket authenticates, which may take a
+# while when the server is down
+TimeoutStartSec=infinity
+WatchdogSec={watchdog}
+KillSignal=SIGTERM
+TimeoutStopSec={stop_timeout}
+
+NoNewPrivileges=yes
+ProtectSystem=strict
+ProtectHome=read-only
+PrivateTmp=yes
+PrivateDevices=yes
+ProtectKernelTunables=yes
+ProtectKernelModules=yes
+ProtectKernelLogs=yes
+ProtectControlGroups=yes
+ProtectClock=yes
+ProtectHostname=yes
+RestrictAddressFamilies=AF_UNIX AF_INET AF_INET6
+RestrictNamespaces=yes
+RestrictRealtime=yes
+RestrictSUIDSGID=yes
+LockPersonality=yes
+MemoryDenyWriteExecute=yes
+SystemCallArchitectures=native
+CapabilityBoundingSet=
+RuntimeDirectory=phirepass
+",
+        user = options.user,
+        watchdog = options.watchdog_secs,
+        stop_timeout = options.stop_timeout_secs,
+    );
+
+    // The identity store must stay writable for key rotation and profile
+    // migration; `-` tolerates a user who has not logged in yet
+    if let Some(data_dir) = &options.data_dir {
+        let _ = writeln!(
+            unit,
+            "ReadWritePaths=-{}",
+            quote(&data_dir.to_string_lossy())
+        );
+    }
+
+    // AUDIT_LOG rotates by renaming, so its whole directory must be writable
+    if let Some(audit_dir) = &options.audit_dir {
+        let _ = writeln!(
+            unit,
+            "ReadWritePaths=-{}",
+            quote(&audit_dir.to_string_lossy())
+        );
+    }
+
+    unit.push_str(
+        "
+[Install]
+WantedBy=multi-user.target
+",
+    );
+
+    unit
+}
+
+pub(crate) fn install(options: &UnitOptions, unit_dir: &Path, enable: bool) -> anyhow::Result<()> {
+    let path = unit_path(unit_dir, &options.name);
+
+    std::fs::write(&path, render_unit(options))
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    info!("wrote {}", path.display());
+    println!("installed {}", path.display());
+
+    systemctl(&["daemon-reload"])?;
+    if enable {
+        systemctl(&["enable", "--now", &options.name])?;
+        println!("enabled and started {}", options.name);
+    } else {
+        println!("start it with: systemctl enable --now {}", options.name);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn uninstall(name: &str, unit_dir: &Path) -> anyhow::Result<()> {
+    let path = unit_path(unit_dir, name);
+    if !path.exists() {
+        anyhow::bail!("{} does not exist", path.display());
+    }
+
+    if let Err(err) = systemctl(&["disable", "--now", name]) {
+        warn!("failed to stop {name}: {err}");
+    }
+
+    std::fs::remove_file(&path).with_context(|| format!("failed to remove {}", path.display()))?;
+    systemctl(&["daemon-reload"])?;
+    println!("removed {}", path.display());
+
+    Ok(())
+}
+
+/// The agent's data directory as seen by `user`, so the unit can keep the
+/// identity store writable under `ProtectHome=read-only`.
+pub(crate) fn data_dir_for(user: &str) -> Option<PathBuf> {
+    let dirs = ProjectDirs::from("com", "phirepass", "agent")?;
+    let base = BaseDirs::new()?;
+    let relative = dirs.data_local_dir().strip_prefix(base.home_dir()).ok()?;
+
+    if whoami::username().ok().as_deref() == Some(user) {
+        return Some(base.home_dir().join(relative));
+    }
+
+    let passwd = std::fs::read_to_string("/etc/passwd").ok()?;
+    passwd
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.len() > 5 && fields[0] == user)
+        .map(|fields| Path::new(fields[5]).join(relative))
+}
+
+/// Directory holding the audit log, when one is configured.
+pub(crate) fn audit_dir(env: &Env) -> Option<PathBuf> {
+    let path = env
+        .audit_log
+        .as_deref()
+        .filter(|path| !path.trim().is_empty())?;
+    let path = std::path::absolute(path).ok()?;
+    path.parent().map(Path::to_path_buf)
+}
+
+fn unit_path(unit_dir: &Path, name: &str) -> PathBuf {
+    unit_dir.join(format!("{name}.service"))
+}
+
+fn systemctl(args: &[&str]) -> anyhow::Result<()> {
+    let status = Command::new("systemctl")
+        .args(args)
+        .status()
+        .context("failed to run systemctl")?;
+
+    if !status.success() {
+        anyhow::bail!("systemctl {} failed with {status}", args.join(" "));
+    }
+
+    Ok(())
+}
+
+/// Quotes a value for an `ExecStart=` or path line when it needs it.
+fn quote(value: &str) -> String {
+    if !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:@=+".contains(c))
+    {
+        return value.to_string();
+    }
+
+    format!(
+        "\"{}\"",
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('%', "%%")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_exec_start_with_config_and_profiles() {
+        let unit = render_unit(&UnitOptions {
+            name: "phirepass-agent".into(),
+            user: "phirepass".into(),
+            exec: "/usr/local/bin/phirepass-agent".into(),
+            config: Some("/etc/phirepass/agent.toml".into()),
+            profiles: vec!["staging".into(), "my profile".into()],
+            data_dir: Some("/home/phirepass/.local/share/agent".into()),
+            audit_dir: Some("/var/log/phirepass".into()),
+            watchdog_secs: 180,
+            stop_timeout_secs: 75,
+        });
+
+        assert!(unit.contains(
+            "ExecStart=/usr/local/bin/phirepass-agent --config /etc/phirepass/agent.toml start --profile staging --profile \"my profile\"\n"
+        ));
+        assert!(unit.contains("User=phirepass\n"));
+        assert!(unit.contains("Type=notify\n"));
+        assert!(unit.contains("WatchdogSec=180\n"));
+        assert!(unit.contains("TimeoutStopSec=75\n"));
+        assert!(unit.contains("ReadWritePaths=-/home/phirepass/.local/share/agent\n"));
+        assert!(unit.contains("ReadWritePaths=-/var/log/phirepass\n"));
+        assert!(unit.ends_with("WantedBy=multi-user.target\n"));
+    }
+}
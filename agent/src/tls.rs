@@ -0,0 +1,191 @@
+use anyhow::Context;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use log::info;
+use rustls::client::WebPkiServerVerifier;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, OnceLock};
+
+static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+
+type Pin = [u8; 32];
+
+/// Builds the TLS client config shared by the websocket and the REST calls:
+/// webpki roots plus the certificates in `ca_file`, and, when `pins` is set,
+/// a requirement that the server chain contains one of the pinned keys.
+pub(crate) fn init(ca_file: Option<&str>, pins: Option<&str>) -> anyhow::Result<()> {
+    let mut roots = RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+    if let Some(path) = ca_file.filter(|path| !path.trim().is_empty()) {
+        let certs = CertificateDer::pem_file_iter(path)
+            .with_context(|| format!("failed to read CA bundle {path}"))?
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("invalid CA bundle {path}"))?;
+
+        let (added, _) = roots.add_parsable_certificates(certs);
+        if added == 0 {
+            anyhow::bail!("CA bundle {path} contains no usable certificates");
+        }
+
+        info!("trusting {added} extra CA certificate(s) from {path}");
+    }
+
+    let pins = pins.map(parse_pins).transpose()?.unwrap_or_default();
+    let verifier = WebPkiServerVerifier::builder(Arc::new(roots))
+        .build()
+        .context("failed to build certificate verifier")?;
+
+    let config = if pins.is_empty() {
+        ClientConfig::builder()
+            .with_webpki_verifier(verifier)
+            .with_no_client_auth()
+    } else {
+        info!("pinning server certificates to {} key(s)", pins.len());
+        ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedVerifier {
+                inner: verifier,
+                pins,
+            }))
+            .with_no_client_auth()
+    };
+
+    let _ = CONFIG.set(Arc::new(config));
+    Ok(())
+}
+
+pub(crate) fn client_config() -> Arc<ClientConfig> {
+    CONFIG
+        .get_or_init(|| {
+            let roots = RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+            Arc::new(
+                ClientConfig::builder()
+                    .with_root_certificates(roots)
+                    .with_no_client_auth(),
+            )
+        })
+        .clone()
+}
+
+/// Runs the regular chain validation first, then requires the SPKI hash of
+/// the leaf or one of the intermediates to match a pin.
+#[derive(Debug)]
+struct PinnedVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<Pin>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        let pinned = std::iter::once(end_entity)
+            .chain(intermediates)
+            .filter_map(spki_sha256)
+            .any(|hash| self.pins.contains(&hash));
+
+        if !pinned {
+            return Err(rustls::Error::General(
+                "server certificate does not match any pinned key".into(),
+            ));
+        }
+
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+fn spki_sha256(cert: &CertificateDer<'_>) -> Option<Pin> {
+    let cert = webpki::EndEntityCert::try_from(cert).ok()?;
+    Some(Sha256::digest(cert.subject_public_key_info().as_ref()).into())
+}
+
+/// Comma separated base64 SHA-256 hashes of the server public key, with an
+/// optional `sha256/` prefix (the format `curl --pinnedpubkey` prints).
+fn parse_pins(value: &str) -> anyhow::Result<Vec<Pin>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|pin| !pin.is_empty())
+        .map(|pin| {
+            let encoded = pin.trim_start_matches("sha256/").trim_start_matches('/');
+            let hash = STANDARD
+                .decode(encoded)
+                .with_context(|| format!("invalid pin {pin}"))?;
+            Pin::try_from(hash).map_err(|_| anyhow::anyhow!("pin {pin} is not a sha256 hash"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CERT: &str = "-----BEGIN CERTIFICATE-----
+MIIBPjCB8aADAgECAhQEpWtgEJsYOz0r1xJDogiKB6L/kDAFBgMrZXAwFDESMBAG
+A1UEAwwJbm9kZS50ZXN0MCAXDTI2MTAxODE0MzMyOFoYDzIxMjYwOTI0MTQzMzI4
+WjAUMRIwEAYDVQQDDAlub2RlLnRlc3QwKjAFBgMrZXADIQDZOwV9b17FNvXyQCUj
+AJskgNSptwxWVoN98/KQ02inEaNTMFEwHQYDVR0OBBYEFP4EalaQ8JU4434L+QfG
+5brOAxpYMB8GA1UdIwQYMBaAFP4EalaQ8JU4434L+QfG5brOAxpYMA8GA1UdEwEB
+/wQFMAMBAf8wBQYDK2VwA0EAaDaciuCMbhiCsNQ4FQp1Vxy9oYbQ0TvtrlwDnulM
+7fWpZEGeyz5pYt302SXqT+H6HKYhR+PUUDti05ZW4aI6Cw==
+-----END CERTIFICATE-----";
+
+    // openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | openssl dgst -sha256 -binary | base64
+    const PIN: &str = "Hs8gDCZWncQud7jZfhGlk9CLxdZSX5AfU34KvyFYPUA=";
+
+    #[test]
+    fn parses_pins() {
+        let pins = parse_pins(&format!("sha256/{PIN}, {PIN},")).unwrap();
+        assert_eq!(pins.len(), 2);
+        assert_eq!(pins[0], pins[1]);
+
+        assert!(parse_pins("not base64!").is_err());
+        assert!(parse_pins("c2hvcnQ=").is_err());
+    }
+
+    #[test]
+    fn hashes_certificate_public_key() {
+        let cert = CertificateDer::from_pem_slice(CERT.as_bytes()).unwrap();
+        let pins = parse_pins(PIN).unwrap();
+        assert_eq!(spki_sha256(&cert), Some(pins[0]));
+    }
+}
@@ -0,0 +1,214 @@
+use crate::audit::Access;
+use phirepass_common::protocol::Protocol;
+use phirepass_common::protocol::node::{SessionUsage, TransferUsage, UsageTotals};
+use phirepass_common::time::now_millis;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+// A long lived file browser can move a lot of small files; past this the
+// summary keeps the byte counts but drops the per transfer entries
+const MAX_TRANSFERS: usize = 1000;
+
+/// Totals of the tunnels one server link carried since the agent started,
+/// sent with that link's heartbeats.
+#[derive(Debug, Default)]
+pub(crate) struct LinkUsage {
+    sessions_closed: AtomicU64,
+    ssh_bytes_in: AtomicU64,
+    ssh_bytes_out: AtomicU64,
+    sftp_bytes_in: AtomicU64,
+    sftp_bytes_out: AtomicU64,
+    uploads_completed: AtomicU64,
+    downloads_completed: AtomicU64,
+}
+
+impl LinkUsage {
+    fn add_bytes(&self, protocol: Protocol, inbound: bool, bytes: usize) {
+        let counter = match (protocol, inbound) {
+            (Protocol::SSH, true) => &self.ssh_bytes_in,
+            (Protocol::SSH, false) => &self.ssh_bytes_out,
+            (Protocol::SFTP, true) => &self.sftp_bytes_in,
+            (Protocol::SFTP, false) => &self.sftp_bytes_out,
+        };
+        counter.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn totals(&self) -> UsageTotals {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        UsageTotals {
+            sessions_closed: load(&self.sessions_closed),
+            ssh_bytes_in: load(&self.ssh_bytes_in),
+            ssh_bytes_out: load(&self.ssh_bytes_out),
+            sftp_bytes_in: load(&self.sftp_bytes_in),
+            sftp_bytes_out: load(&self.sftp_bytes_out),
+            uploads_completed: load(&self.uploads_completed),
+            downloads_completed: load(&self.downloads_completed),
+        }
+    }
+}
+
+/// Bytes one tunnel moved, summarized into `TunnelClosed` when it ends.
+#[derive(Debug)]
+pub(crate) struct SessionMeter {
+    username: String,
+    protocol: Protocol,
+    link: Arc<LinkUsage>,
+    opened_at: u64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    transfers: Mutex<Vec<TransferUsage>>,
+}
+
+impl SessionMeter {
+    pub(crate) fn new(username: &str, protocol: Protocol, link: &Arc<LinkUsage>) -> Arc<Self> {
+        Arc::new(Self {
+            username: username.to_string(),
+            protocol,
+            link: Arc::clone(link),
+            opened_at: now_millis(),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            transfers: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Bytes from the web client written to the target.
+    pub(crate) fn received(&self, bytes: usize) {
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.link.add_bytes(self.protocol, true, bytes);
+    }
+
+    /// Bytes read from the target and sent to the web client.
+    pub(crate) fn sent(&self, bytes: usize) {
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.link.add_bytes(self.protocol, false, bytes);
+    }
+
+    /// Counts the tunnel as closed on its server link.
+    pub(crate) fn closed(&self) {
+        self.link.sessions_closed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Starts metering an upload or download of `path`; it is added to the
+    /// session once the returned meter is dropped.
+    pub(crate) fn transfer(self: &Arc<Self>, upload: bool, path: &str) -> TransferMeter {
+        TransferMeter {
+            session: Arc::clone(self),
+            record: TransferUsage {
+                upload,
+                path: path.to_string(),
+                started_at: now_millis(),
+                ..Default::default()
+            },
+            audit: None,
+        }
+    }
+
+    pub(crate) fn summary(&self) -> SessionUsage {
+        SessionUsage {
+            username: self.username.clone(),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            opened_at: self.opened_at,
+            closed_at: now_millis(),
+            transfers: self
+                .transfers
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .clone(),
+        }
+    }
+}
+
+/// Meters one file transfer; dropping it before `complete` records it as
+/// failed or abandoned.
+pub(crate) struct TransferMeter {
+    session: Arc<SessionMeter>,
+    record: TransferUsage,
+    audit: Option<Access>,
+}
+
+impl TransferMeter {
+    /// Writes an audit record for the transfer when it is dropped.
+    pub(crate) fn audited(mut self, access: Access) -> Self {
+        self.audit = Some(access);
+        self
+    }
+
+    pub(crate) fn add(&mut self, bytes: usize) {
+        self.record.bytes += bytes as u64;
+        if self.record.upload {
+            self.session.received(bytes);
+        } else {
+            self.session.sent(bytes);
+        }
+    }
+
+    pub(crate) fn complete(&mut self) {
+        self.record.completed = true;
+    }
+}
+
+impl Drop for TransferMeter {
+    fn drop(&mut self) {
+        let mut record = std::mem::take(&mut self.record);
+        record.finished_at = now_millis();
+        if record.completed {
+            let link = &self.session.link;
+            match record.upload {
+                true => &link.uploads_completed,
+                false => &link.downloads_completed,
+            }
+            .fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(access) = &self.audit {
+            access.transferred(&record);
+        }
+
+        let mut transfers = self
+            .session
+            .transfers
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        if transfers.len() < MAX_TRANSFERS {
+            transfers.push(record);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transfers_count_towards_the_session() {
+        let link = Arc::new(LinkUsage::default());
+        let session = SessionMeter::new("deploy", Protocol::SFTP, &link);
+        session.received(10);
+
+        let mut upload = session.transfer(true, "/tmp/a");
+        upload.add(100);
+        upload.complete();
+        drop(upload);
+
+        let mut download = session.transfer(false, "/tmp/b");
+        download.add(40);
+        drop(download);
+
+        let summary = session.summary();
+        assert_eq!(summary.username, "deploy");
+        assert_eq!((summary.bytes_in, summary.bytes_out), (110, 40));
+        assert_eq!(summary.transfers.len(), 2);
+        assert!(summary.transfers[0].upload && summary.transfers[0].completed);
+        assert_eq!(summary.transfers[1].bytes, 40);
+        assert!(!summary.transfers[1].completed);
+
+        let totals = link.totals();
+        assert_eq!((totals.sftp_bytes_in, totals.sftp_bytes_out), (110, 40));
+        assert_eq!(
+            (totals.uploads_completed, totals.downloads_completed),
+            (1, 0)
+        );
+    }
+}
@@ -1,5 +1,15 @@
-use crate::common::{send_frame_data, send_requires_password_error, send_requires_username_error};
+use crate::accounts::LocalAccounts;
+use crate::admin::{Connection, ConnectionState, Connections};
+use crate::audit::Access;
+use crate::common::{
+    send_account_denied_error, send_draining_error, send_frame_data, send_requires_password_error,
+    send_requires_username_error, send_session_limit_error,
+};
+use crate::drain::{self, Drain};
 use crate::env::Env;
+use crate::health;
+use crate::metrics::METRICS;
+use crate::proxy;
 use crate::session::{SessionCommand, SessionHandle, TunnelSessions};
 use crate::sftp::connection::{SFTPConfig, SFTPConfigAuth, SFTPConnection};
 use crate::sftp::session::{SFTPCommand, SFTPSessionHandle};
@@ -7,14 +17,17 @@ use crate::sftp::{SFTPActiveDownloads, SFTPActiveUploads};
 use crate::ssh::auth::SSHAuthMethod;
 use crate::ssh::connection::{SSHConfig, SSHConfigAuth, SSHConnection};
 use crate::ssh::session::{SSHCommand, SSHSessionHandle};
+use crate::systemd;
+use crate::tls;
+use crate::usage::LinkUsage;
 use anyhow::anyhow;
 use bytes::Bytes;
-use futures_util::stream::SplitStream;
+use futures_util::stream::{SplitSink, SplitStream};
 use futures_util::{SinkExt, StreamExt};
 use log::{debug, error, info, warn};
 use phirepass_common::env::Mode;
 use phirepass_common::protocol::Protocol;
-use phirepass_common::protocol::common::{Frame, FrameData, FrameError};
+use phirepass_common::protocol::common::{CloseReason, Frame, FrameData, FrameError};
 use phirepass_common::protocol::node::NodeFrameData;
 use phirepass_common::protocol::web::WebFrameData;
 use phirepass_common::stats::Stats;
@@ -27,21 +40,28 @@ use tokio::net::TcpStream;
 use tokio::sync::mpsc::{Receiver, Sender, channel};
 use tokio::sync::oneshot;
 use tokio_tungstenite::{
-    MaybeTlsStream, WebSocketStream, connect_async, tungstenite::protocol::Message,
+    Connector, MaybeTlsStream, WebSocketStream, client_async_tls_with_config,
+    tungstenite::protocol::Message,
 };
 use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
 type WebSocketReader = SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>;
+type WebSocketWriter = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
 
 pub(crate) struct WebSocketConnection {
     node_id: Uuid,
     token: SecretString,
     writer: Sender<Frame>,
     reader: Receiver<Frame>,
+    link: Arc<Connection>,
+    // Every server link of the agent, for limits that span all of them
+    links: Connections,
+    local_accounts: Option<Arc<LocalAccounts>>,
     sessions: TunnelSessions,
     uploads: SFTPActiveUploads,
     downloads: SFTPActiveDownloads,
+    drain: Arc<Drain>,
 }
 
 fn generate_server_endpoint(mode: &Mode, server_host: &str, server_port: u16) -> String {
@@ -69,7 +89,13 @@ fn generate_server_endpoint(mode: &Mode, server_host: &str, server_port: u16) ->
 }
 
 impl WebSocketConnection {
-    pub fn new(node_id: Uuid, token: SecretString) -> Self {
+    pub fn new(
+        node_id: Uuid,
+        token: SecretString,
+        link: Arc<Connection>,
+        links: Connections,
+        local_accounts: Option<Arc<LocalAccounts>>,
+    ) -> Self {
         // Cap the outbound queue to avoid unbounded memory use when the socket is back-pressured.
         let (tx, rx) = channel::<Frame>(1024);
         Self {
@@ -77,64 +103,40 @@ impl WebSocketConnection {
             token,
             reader: rx,
             writer: tx,
-            sessions: Arc::new(Default::default()),
-            uploads: Arc::new(Default::default()),
-            downloads: Arc::new(Default::default()),
+            sessions: Arc::clone(&link.sessions),
+            uploads: Arc::clone(&link.uploads),
+            downloads: Arc::clone(&link.downloads),
+            drain: Arc::clone(&link.drain),
+            link,
+            links,
+            local_accounts,
         }
     }
 
     pub async fn connect(self, config: Arc<Env>) -> anyhow::Result<()> {
-        info!("connecting ws...");
-
-        let endpoint = format!(
-            "{}/api/nodes/ws",
-            generate_server_endpoint(&config.mode, &config.server_host, config.server_port)
-        );
-
-        info!("trying {endpoint}");
-
-        let (stream, _) = connect_async(endpoint).await?;
-        let (mut write, mut read) = stream.split();
-
         let node_id = self.node_id;
-        let token = self.token.expose_secret().to_owned();
+        let (mut write, read, _) = open_authenticated(&config, node_id, &self.token, false).await?;
+        self.drain.reset();
 
-        let frame: Frame = NodeFrameData::Auth {
-            token,
-            node_id,
-            version: crate::env::version().to_string(),
+        // Sent on every connect so config changes reach the server
+        let frame: Frame = NodeFrameData::NodeMetadata {
+            labels: config.get_labels()?,
+            metadata: config.get_metadata()?,
         }
         .into();
-
         write
             .send(Message::Binary(frame.to_bytes()?.into()))
             .await?;
 
-        let (received_node_id, version) = read_auth_response(&mut read).await?;
-
-        if node_id != received_node_id {
-            error!(
-                "CRITICAL: node_id mismatch. Expected: {}, Received: {}. \
-                 This may indicate token corruption or server misconfiguration.",
-                node_id, received_node_id
-            );
-            anyhow::bail!(
-                "node_id mismatch: local={}, server={}",
-                node_id,
-                received_node_id
-            )
-        }
-
-        info!(
-            "agent authenticated successfully with server version {}",
-            version
-        );
+        self.link
+            .set_state(ConnectionState::Connected, Some(node_id));
+        systemd::ready();
 
         let last_heartbeat = Arc::new(AtomicU64::new(0));
         let cancellation_token = CancellationToken::new();
 
         let mut rx = self.reader;
-        let write_task = tokio::spawn(async move {
+        let mut write_task = tokio::spawn(async move {
             while let Some(frame) = rx.recv().await {
                 if let Ok(data) = frame.to_bytes()
                     && let Err(err) = write.send(Message::Binary(data.into())).await
@@ -142,9 +144,10 @@ impl WebSocketConnection {
                     warn!("failed to send frame: {err}");
                 }
             }
+            let _ = write.close().await;
         });
 
-        let reader_task = spawn_reader_task(
+        let mut reader_task = spawn_reader_task(
             node_id,
             read,
             self.writer.clone(),
@@ -152,12 +155,16 @@ impl WebSocketConnection {
             Arc::clone(&self.sessions),
             Arc::clone(&self.uploads),
             Arc::clone(&self.downloads),
+            Arc::clone(&self.link),
+            Arc::clone(&self.links),
+            self.local_accounts.clone(),
             last_heartbeat.clone(),
         );
 
         let heartbeat_task = spawn_heartbeat_task(
             self.writer.clone(),
-            config.stats_refresh_interval as u64,
+            Arc::clone(&self.link.usage),
+            Arc::clone(&config),
             cancellation_token.clone(),
             last_heartbeat.clone(),
         );
@@ -169,15 +176,22 @@ impl WebSocketConnection {
         );
 
         tokio::select! {
-            _ = write_task => warn!("write task ended"),
-            _ = reader_task => warn!("read task ended"),
+            _ = &mut write_task => warn!("write task ended"),
+            _ = &mut reader_task => warn!("read task ended"),
             _ = heartbeat_task => warn!("heartbeat task ended"),
             _ = cleanup_task => warn!("cleanup task ended"),
             _ = cancellation_token.cancelled() => warn!("cancellation token triggered"),
+            request = self.drain.requested() => {
+                drain::run(&request, &self.writer, &self.sessions, &self.uploads).await;
+                info!("drain finished, closing the connection");
+            }
         }
 
         // Cancel background tasks to prevent them from trying to send on a closed channel
         cancellation_token.cancel();
+        // The reader holds a sender too; without it the write task flushes
+        // what is queued and closes the socket once this connection is gone
+        reader_task.abort();
 
         // close all active sessions
         info!("closing all active sessions");
@@ -210,6 +224,82 @@ impl WebSocketConnection {
     }
 }
 
+/// Opens the node websocket and completes the auth exchange, returning the
+/// split stream and the server version.
+async fn open_authenticated(
+    config: &Env,
+    node_id: Uuid,
+    token: &SecretString,
+    probe: bool,
+) -> anyhow::Result<(WebSocketWriter, WebSocketReader, String)> {
+    info!("connecting ws...");
+
+    let endpoint = format!(
+        "{}/api/nodes/ws",
+        generate_server_endpoint(&config.mode, &config.server_host, config.server_port)
+    );
+
+    info!("trying {endpoint}");
+
+    let server_host = config
+        .server_host
+        .split_once("://")
+        .map(|(_, rest)| rest)
+        .unwrap_or(config.server_host.as_str());
+    let tcp = proxy::connect(server_host, config.server_port).await?;
+    let connector = Connector::Rustls(tls::client_config());
+    let (stream, _) = client_async_tls_with_config(endpoint, tcp, None, Some(connector)).await?;
+    let (mut write, mut read) = stream.split();
+
+    let token = token.expose_secret().to_owned();
+
+    let frame: Frame = NodeFrameData::Auth {
+        token,
+        node_id,
+        version: crate::env::version().to_string(),
+        probe,
+    }
+    .into();
+
+    write
+        .send(Message::Binary(frame.to_bytes()?.into()))
+        .await?;
+
+    let (received_node_id, version) = read_auth_response(&mut read).await?;
+
+    if node_id != received_node_id {
+        error!(
+            "CRITICAL: node_id mismatch. Expected: {}, Received: {}. \
+             This may indicate token corruption or server misconfiguration.",
+            node_id, received_node_id
+        );
+        anyhow::bail!(
+            "node_id mismatch: local={}, server={}",
+            node_id,
+            received_node_id
+        )
+    }
+
+    info!(
+        "agent authenticated successfully with server version {}",
+        version
+    );
+
+    Ok((write, read, version))
+}
+
+/// Authenticates a websocket and closes it again, for `doctor`. Sent as a
+/// probe, so a running agent with the same identity stays connected.
+pub(crate) async fn probe(
+    config: &Env,
+    node_id: Uuid,
+    token: &SecretString,
+) -> anyhow::Result<String> {
+    let (mut write, _read, version) = open_authenticated(config, node_id, token, true).await?;
+    let _ = write.send(Message::Close(None)).await;
+    Ok(version)
+}
+
 fn spawn_cleanup_task(
     uploads: SFTPActiveUploads,
     downloads: SFTPActiveDownloads,
@@ -234,6 +324,7 @@ fn spawn_cleanup_task(
     })
 }
 
+#[allow(clippy::too_many_arguments)]
 fn spawn_reader_task(
     target: Uuid,
     mut reader: WebSocketReader,
@@ -242,6 +333,9 @@ fn spawn_reader_task(
     sessions: TunnelSessions,
     uploads: SFTPActiveUploads,
     downloads: SFTPActiveDownloads,
+    link: Arc<Connection>,
+    links: Connections,
+    local_accounts: Option<Arc<LocalAccounts>>,
     last_heartbeat: Arc<AtomicU64>,
 ) -> tokio::task::JoinHandle<()> {
     tokio::spawn(async move {
@@ -274,6 +368,9 @@ fn spawn_reader_task(
                         &sessions,
                         &uploads,
                         &downloads,
+                        &link,
+                        &links,
+                        local_accounts.as_deref(),
                         Arc::clone(&last_heartbeat),
                     )
                     .await;
@@ -291,12 +388,14 @@ fn spawn_reader_task(
 
 fn spawn_heartbeat_task(
     sender: Sender<Frame>,
-    interval: u64,
+    usage: Arc<LinkUsage>,
+    config: Arc<Env>,
     cancellation_token: CancellationToken,
     last_heartbeat: Arc<AtomicU64>,
 ) -> tokio::task::JoinHandle<()> {
     tokio::spawn(async move {
-        let mut interval = tokio::time::interval(Duration::from_secs(interval));
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(config.stats_refresh_interval as u64));
         let timeout_ms = (interval.period().as_millis() * 3) as u64;
 
         loop {
@@ -311,13 +410,23 @@ fn spawn_heartbeat_task(
                         break;
                     }
 
+                    systemd::watchdog();
+
                     let Some(stats) = Stats::get() else {
                         warn!("failed to get stats for heartbeat");
                         continue;
                     };
 
                     let sent_at = now_millis();
-                    send_frame_data(&sender, NodeFrameData::Heartbeat { stats, sent_at });
+                    send_frame_data(
+                        &sender,
+                        NodeFrameData::Heartbeat {
+                            stats: Box::new(stats),
+                            health: health::latest(),
+                            sent_at,
+                            usage: Some(usage.totals()),
+                        },
+                    );
                 }
                 _ = cancellation_token.cancelled() => {
                     debug!("heartbeat task cancelled");
@@ -379,6 +488,7 @@ async fn read_next_frame(reader: &mut WebSocketReader) -> Option<NodeFrameData>
     None
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_message(
     node_id: Uuid,
     data: NodeFrameData,
@@ -387,9 +497,13 @@ async fn handle_message(
     sessions: &TunnelSessions,
     uploads: &SFTPActiveUploads,
     downloads: &SFTPActiveDownloads,
+    link: &Arc<Connection>,
+    links: &Connections,
+    local_accounts: Option<&LocalAccounts>,
     last_heartbeat: Arc<AtomicU64>,
 ) {
     debug!("handling message: {data:?}");
+    let drain = &link.drain;
 
     match data {
         NodeFrameData::OpenTunnel {
@@ -398,9 +512,32 @@ async fn handle_message(
             username,
             password,
             msg_id,
+            client_ip,
+            user,
         } => {
             info!("received open tunnel with protocol {protocol}");
 
+            if drain.is_active() {
+                warn!("refusing tunnel for {cid}: draining");
+                send_draining_error(sender, cid, msg_id);
+                return;
+            }
+
+            // Checked before the password is asked for
+            if let Some(username) = username
+                .as_deref()
+                .filter(|username| !username.trim().is_empty())
+                && local_accounts.is_some_and(|accounts| !accounts.permits(user.as_ref(), username))
+            {
+                let user_id = user.as_ref().map(|user| user.user_id.to_string());
+                warn!(
+                    "refusing tunnel for {cid}: user {} may not log in as {username}",
+                    user_id.as_deref().unwrap_or("<unknown>")
+                );
+                send_account_denied_error(sender, cid, msg_id, username);
+                return;
+            }
+
             if let Err(err) = ensure_credentials(sender, config, cid, &username, &password, msg_id)
             {
                 warn!("credentials verification error: {err}");
@@ -409,6 +546,14 @@ async fn handle_message(
 
             info!("credentials verification succeeded");
 
+            if let Ok(protocol) = Protocol::try_from(protocol)
+                && let Err(message) = config.session_limits().check(links, protocol, cid)
+            {
+                warn!("refusing {protocol:?} tunnel for {cid}: {message}");
+                send_session_limit_error(sender, cid, msg_id, message);
+                return;
+            }
+
             match Protocol::try_from(protocol) {
                 Ok(Protocol::SFTP) => {
                     let auth = match config.ssh_auth_mode {
@@ -422,7 +567,17 @@ async fn handle_message(
                     };
 
                     start_sftp_tunnel(
-                        sender, cid, config, auth, sessions, uploads, downloads, msg_id,
+                        sender,
+                        cid,
+                        config,
+                        auth,
+                        sessions,
+                        uploads,
+                        downloads,
+                        drain,
+                        &link.usage,
+                        msg_id,
+                        client_ip,
                     )
                     .await;
                 }
@@ -437,7 +592,19 @@ async fn handle_message(
                         ),
                     };
 
-                    start_ssh_tunnel(sender, node_id, cid, config, auth, sessions, msg_id).await;
+                    start_ssh_tunnel(
+                        sender,
+                        node_id,
+                        cid,
+                        config,
+                        auth,
+                        sessions,
+                        drain,
+                        &link.usage,
+                        msg_id,
+                        client_ip,
+                    )
+                    .await;
                 }
                 Err(err) => warn!("invalid protocol value {protocol}: {err:?}"),
             }
@@ -449,8 +616,18 @@ async fn handle_message(
             let now = now_millis();
             last_heartbeat.store(now, Ordering::Relaxed);
             let latency = now.saturating_sub(sent_at);
+            link.set_heartbeat_rtt(latency);
             debug!("heartbeat ack received, latency: {latency}ms");
         }
+        NodeFrameData::Drain {
+            reason,
+            timeout_secs,
+        } => {
+            info!("server requested a drain within {timeout_secs}s: {reason}");
+            let server_timeout =
+                (timeout_secs > 0).then(|| Duration::from_secs(timeout_secs.into()));
+            drain.request(config.drain_request(&reason, false, server_timeout));
+        }
         NodeFrameData::ConnectionDisconnect { cid } => {
             info!("received connection disconnect for {cid}");
             close_tunnels_for_cid(cid, sessions).await;
@@ -569,6 +746,61 @@ async fn handle_message(
                 warn!("failed to forward sftp delete data: {err}");
             }
         }
+        NodeFrameData::SFTPDiskUsage {
+            cid,
+            sid,
+            msg_id,
+            data,
+        } => {
+            if let Err(err) = send_sftp_disk_usage_data(cid, sid, msg_id, data, sessions).await {
+                warn!("failed to forward sftp disk usage data: {err}");
+            }
+        }
+        NodeFrameData::SFTPSearch {
+            cid,
+            sid,
+            msg_id,
+            data,
+        } => {
+            if let Err(err) = send_sftp_search_data(cid, sid, msg_id, data, sessions).await {
+                warn!("failed to forward sftp search data: {err}");
+            }
+        }
+        NodeFrameData::SFTPCopy {
+            cid,
+            sid,
+            msg_id,
+            data,
+        } => {
+            if let Err(err) = send_sftp_copy_data(cid, sid, msg_id, data, sessions).await {
+                warn!("failed to forward sftp copy data: {err}");
+            }
+        }
+        NodeFrameData::SFTPExtract {
+            cid,
+            sid,
+            msg_id,
+            data,
+        } => {
+            if let Err(err) = send_sftp_extract_data(cid, sid, msg_id, data, sessions).await {
+                warn!("failed to forward sftp extract data: {err}");
+            }
+        }
+        NodeFrameData::SFTPWatch {
+            cid,
+            sid,
+            msg_id,
+            data,
+        } => {
+            if let Err(err) = send_sftp_watch_data(cid, sid, msg_id, data, sessions).await {
+                warn!("failed to forward sftp watch data: {err}");
+            }
+        }
+        NodeFrameData::SFTPUnwatch { cid, sid, msg_id } => {
+            if let Err(err) = send_sftp_unwatch_data(cid, sid, msg_id, sessions).await {
+                warn!("failed to forward sftp unwatch data: {err}");
+            }
+        }
         o => warn!("not implemented yet: {o:?}"),
     }
 }
@@ -665,6 +897,143 @@ async fn send_sftp_delete_data(
         .map_err(|err| anyhow!(err))
 }
 
+async fn send_sftp_disk_usage_data(
+    cid: Uuid,
+    sid: u32,
+    msg_id: Option<u32>,
+    data: phirepass_common::protocol::sftp::SFTPDiskUsage,
+    sessions: &TunnelSessions,
+) -> anyhow::Result<()> {
+    let stdin = sessions.get(&(cid, sid)).map(|s| s.get_stdin());
+
+    let Some(stdin) = stdin else {
+        anyhow::bail!(format!("no session found for connection {cid}"))
+    };
+
+    let SessionCommand::Sftp(stdin) = stdin else {
+        anyhow::bail!(format!("no sftp tunnel found for connection {cid}"))
+    };
+
+    stdin
+        .send(SFTPCommand::DiskUsage { data, msg_id })
+        .await
+        .map_err(|err| anyhow!(err))
+}
+
+async fn send_sftp_search_data(
+    cid: Uuid,
+    sid: u32,
+    msg_id: Option<u32>,
+    data: phirepass_common::protocol::sftp::SFTPSearch,
+    sessions: &TunnelSessions,
+) -> anyhow::Result<()> {
+    let stdin = sessions.get(&(cid, sid)).map(|s| s.get_stdin());
+
+    let Some(stdin) = stdin else {
+        anyhow::bail!(format!("no session found for connection {cid}"))
+    };
+
+    let SessionCommand::Sftp(stdin) = stdin else {
+        anyhow::bail!(format!("no sftp tunnel found for connection {cid}"))
+    };
+
+    stdin
+        .send(SFTPCommand::Search { data, msg_id })
+        .await
+        .map_err(|err| anyhow!(err))
+}
+
+async fn send_sftp_copy_data(
+    cid: Uuid,
+    sid: u32,
+    msg_id: Option<u32>,
+    data: phirepass_common::protocol::sftp::SFTPCopy,
+    sessions: &TunnelSessions,
+) -> anyhow::Result<()> {
+    let stdin = sessions.get(&(cid, sid)).map(|s| s.get_stdin());
+
+    let Some(stdin) = stdin else {
+        anyhow::bail!(format!("no session found for connection {cid}"))
+    };
+
+    let SessionCommand::Sftp(stdin) = stdin else {
+        anyhow::bail!(format!("no sftp tunnel found for connection {cid}"))
+    };
+
+    stdin
+        .send(SFTPCommand::Copy { data, msg_id })
+        .await
+        .map_err(|err| anyhow!(err))
+}
+
+async fn send_sftp_extract_data(
+    cid: Uuid,
+    sid: u32,
+    msg_id: Option<u32>,
+    data: phirepass_common::protocol::sftp::SFTPExtract,
+    sessions: &TunnelSessions,
+) -> anyhow::Result<()> {
+    let stdin = sessions.get(&(cid, sid)).map(|s| s.get_stdin());
+
+    let Some(stdin) = stdin else {
+        anyhow::bail!(format!("no session found for connection {cid}"))
+    };
+
+    let SessionCommand::Sftp(stdin) = stdin else {
+        anyhow::bail!(format!("no sftp tunnel found for connection {cid}"))
+    };
+
+    stdin
+        .send(SFTPCommand::Extract { data, msg_id })
+        .await
+        .map_err(|err| anyhow!(err))
+}
+
+async fn send_sftp_watch_data(
+    cid: Uuid,
+    sid: u32,
+    msg_id: Option<u32>,
+    data: phirepass_common::protocol::sftp::SFTPWatch,
+    sessions: &TunnelSessions,
+) -> anyhow::Result<()> {
+    let stdin = sessions.get(&(cid, sid)).map(|s| s.get_stdin());
+
+    let Some(stdin) = stdin else {
+        anyhow::bail!(format!("no session found for connection {cid}"))
+    };
+
+    let SessionCommand::Sftp(stdin) = stdin else {
+        anyhow::bail!(format!("no sftp tunnel found for connection {cid}"))
+    };
+
+    stdin
+        .send(SFTPCommand::Watch { data, msg_id })
+        .await
+        .map_err(|err| anyhow!(err))
+}
+
+async fn send_sftp_unwatch_data(
+    cid: Uuid,
+    sid: u32,
+    msg_id: Option<u32>,
+    sessions: &TunnelSessions,
+) -> anyhow::Result<()> {
+    let stdin = sessions.get(&(cid, sid)).map(|s| s.get_stdin());
+
+    let Some(stdin) = stdin else {
+        anyhow::bail!(format!("no session found for connection {cid}"))
+    };
+
+    let SessionCommand::Sftp(stdin) = stdin else {
+        anyhow::bail!(format!("no sftp tunnel found for connection {cid}"))
+    };
+
+    stdin
+        .send(SFTPCommand::Unwatch { msg_id })
+        .await
+        .map_err(|err| anyhow!(err))
+}
+
 async fn send_sftp_download_start_data(
     cid: Uuid,
     sid: u32,
@@ -893,6 +1262,16 @@ fn ensure_credentials(
     Ok(())
 }
 
+// Sessions that end on their own during a drain are reported as drained
+// too; the user is being moved off the node either way
+fn close_reason(drain: &Drain) -> CloseReason {
+    if drain.is_active() {
+        CloseReason::Drain
+    } else {
+        CloseReason::Ended
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 async fn start_sftp_tunnel(
     tx: &Sender<Frame>,
@@ -902,8 +1281,12 @@ async fn start_sftp_tunnel(
     sessions: &TunnelSessions,
     uploads: &SFTPActiveUploads,
     downloads: &SFTPActiveDownloads,
+    drain: &Arc<Drain>,
+    usage: &Arc<LinkUsage>,
     msg_id: Option<u32>,
+    client_ip: Option<String>,
 ) {
+    let username = credentials.username().to_string();
     let (stdin_tx, stdin_rx) = channel::<SFTPCommand>(2048);
     let (stop_tx, stop_rx) = oneshot::channel();
     let sender = tx.clone();
@@ -914,9 +1297,11 @@ async fn start_sftp_tunnel(
         port: config.ssh_port,
         credentials,
         inactivity_timeout: config.get_ssh_inactivity_duration(),
+        watch_interval: config.get_sftp_watch_interval(),
     });
 
     let sid = conn.get_session_id();
+    let access = Access::new(cid, sid, Protocol::SFTP, &username, client_ip, usage);
 
     info!(
         "connecting sftp for connection {cid}: {}:{}",
@@ -926,19 +1311,42 @@ async fn start_sftp_tunnel(
     let uploads = uploads.clone();
     let downloads = downloads.clone();
 
+    let handle = SessionHandle::Sftp(SFTPSessionHandle {
+        stop: Some(stop_tx),
+        stdin: stdin_tx,
+        username,
+        started_at: now_millis(),
+    });
+
+    info!("sftp session handle {sid} created");
+
+    let previous = sessions.insert((cid, sid), handle);
+
+    if let Some(prev) = previous {
+        info!("removing previous sftp session {cid}");
+        prev.shutdown().await;
+    }
+
+    let task_sessions = Arc::clone(sessions);
+    let drain = Arc::clone(drain);
+
     // Background task will run to completion or until stop_rx is triggered.
-    // Not awaited here; cleanup is managed via the SessionHandle (stop_tx).
+    // Not awaited here; cleanup is managed via the SessionHandle (stop_tx),
+    // which is registered first so the task can always remove it again.
     let _sftp_task = tokio::spawn(async move {
         info!("sftp task started for connection {cid}");
 
         match conn
             .connect(
-                cid, &sender, msg_id, &uploads, &downloads, stdin_rx, stop_rx,
+                cid, &sender, msg_id, &access, &uploads, &downloads, stdin_rx, stop_rx,
             )
             .await
         {
             Ok(sid) => {
                 info!("sftp connection {sid}:{cid} ended");
+                access.closed();
+                METRICS.session_closed();
+                access.usage().closed();
                 send_frame_data(
                     &sender,
                     NodeFrameData::TunnelClosed {
@@ -946,11 +1354,15 @@ async fn start_sftp_tunnel(
                         cid,
                         sid,
                         msg_id,
+                        usage: Some(access.usage().summary()),
+                        reason: Some(close_reason(&drain)),
                     },
                 );
             }
             Err((id, err)) => {
                 warn!("sftp connection error for {cid}: {err}");
+                health::tunnel_failed(&err);
+                access.failed(&err);
                 send_frame_data(
                     &tx_for_opened,
                     NodeFrameData::WebFrame {
@@ -964,23 +1376,13 @@ async fn start_sftp_tunnel(
                 );
             }
         }
-    });
 
-    let handle = SessionHandle::Sftp(SFTPSessionHandle {
-        stop: Some(stop_tx),
-        stdin: stdin_tx,
+        // Frees the slot for session limits and the admin api
+        task_sessions.remove(&(cid, sid));
     });
-
-    info!("sftp session handle {sid} created");
-
-    let previous = sessions.insert((cid, sid), handle);
-
-    if let Some(prev) = previous {
-        info!("removing previous sftp session {cid}");
-        prev.shutdown().await;
-    }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn start_ssh_tunnel(
     tx: &Sender<Frame>,
     node_id: Uuid,
@@ -988,8 +1390,12 @@ async fn start_ssh_tunnel(
     config: &Arc<Env>,
     credentials: SSHConfigAuth,
     sessions: &TunnelSessions,
+    drain: &Arc<Drain>,
+    usage: &Arc<LinkUsage>,
     msg_id: Option<u32>,
+    client_ip: Option<String>,
 ) {
+    let username = credentials.username().to_string();
     let (stdin_tx, stdin_rx) = channel::<SSHCommand>(512);
     let (stop_tx, stop_rx) = oneshot::channel();
     let sender = tx.clone();
@@ -1003,23 +1409,48 @@ async fn start_ssh_tunnel(
     });
 
     let sid = conn.get_session_id();
+    let access = Access::new(cid, sid, Protocol::SSH, &username, client_ip, usage);
 
     info!(
         "connecting ssh for connection {cid}: {}:{}",
         config.ssh_host, config.ssh_port
     );
 
+    let handle = SessionHandle::Ssh(SSHSessionHandle {
+        stop: Some(stop_tx),
+        stdin: stdin_tx,
+        username,
+        started_at: now_millis(),
+    });
+
+    info!("ssh session handle {sid} created");
+    info!("total ssh sessions {}", sessions.len() + 1);
+
+    let previous = sessions.insert((cid, sid), handle);
+
+    if let Some(prev) = previous {
+        info!("removing previous ssh session {cid}");
+        prev.shutdown().await;
+    }
+
+    let task_sessions = Arc::clone(sessions);
+    let drain = Arc::clone(drain);
+
     // Background task will run to completion or until stop_rx is triggered.
-    // Not awaited here; cleanup is managed via the SessionHandle (stop_tx).
+    // Not awaited here; cleanup is managed via the SessionHandle (stop_tx),
+    // which is registered first so the task can always remove it again.
     let _ssh_task = tokio::spawn(async move {
         info!("ssh task started for connection {cid}");
 
         match conn
-            .connect(node_id, cid, &sender, msg_id, stdin_rx, stop_rx)
+            .connect(node_id, cid, &sender, msg_id, &access, stdin_rx, stop_rx)
             .await
         {
             Ok(sid) => {
                 info!("ssh connection {sid}:{cid} ended");
+                access.closed();
+                METRICS.session_closed();
+                access.usage().closed();
                 send_frame_data(
                     &sender,
                     NodeFrameData::TunnelClosed {
@@ -1027,11 +1458,15 @@ async fn start_ssh_tunnel(
                         cid,
                         sid,
                         msg_id,
+                        usage: Some(access.usage().summary()),
+                        reason: Some(close_reason(&drain)),
                     },
                 );
             }
             Err((id, err)) => {
                 warn!("ssh connection error for {cid}: {err}");
+                health::tunnel_failed(&err);
+                access.failed(&err);
                 send_frame_data(
                     &tx_for_opened,
                     NodeFrameData::WebFrame {
@@ -1045,20 +1480,8 @@ async fn start_ssh_tunnel(
                 );
             }
         }
-    });
 
-    let handle = SessionHandle::Ssh(SSHSessionHandle {
-        stop: Some(stop_tx),
-        stdin: stdin_tx,
+        // Frees the slot for session limits and the admin api
+        task_sessions.remove(&(cid, sid));
     });
-
-    info!("ssh session handle {sid} created");
-    info!("total ssh sessions {}", sessions.len() + 1);
-
-    let previous = sessions.insert((cid, sid), handle);
-
-    if let Some(prev) = previous {
-        info!("removing previous ssh session {cid}");
-        prev.shutdown().await;
-    }
 }
@@ -4,6 +4,9 @@ import init, {
 } from "/pkg/debug/phirepass-channel.js";
 import { SFTPBrowser } from "./sftp.js";
 
+// CloseReason::Drain in the TunnelClosed frame
+const CLOSE_REASON_DRAIN = 10;
+
 async function setup() {
     await init(); // Load WebAssembly module
 }
@@ -77,6 +80,44 @@ const formatBytes = (bytes) => {
     return `${size.toFixed(1)} ${unit}`;
 };
 
+const containerLines = (cgroup) => {
+    if (!cgroup) return [];
+    const lines = [];
+    if (cgroup.cpu_limit_cores != null) {
+        lines.push(
+            `limit_cpu: ${formatNumber(cgroup.cpu_percent, 1)}% of ${formatNumber(
+                cgroup.cpu_limit_cores,
+                2
+            )} cores`
+        );
+    }
+    if (cgroup.memory_limit_bytes != null) {
+        lines.push(
+            `limit_mem: ${formatBytes(cgroup.memory_used_bytes)} / ${formatBytes(
+                cgroup.memory_limit_bytes
+            )}`
+        );
+    }
+    return lines;
+};
+
+const diskLines = (disks = []) =>
+    disks.map((disk) => {
+        const used = disk.total_bytes - disk.available_bytes;
+        const percent = disk.total_bytes ? (used / disk.total_bytes) * 100 : 0;
+        const warning = percent >= 90 ? " (!)" : "";
+        return `disk ${disk.mount_point}: ${formatNumber(percent, 0)}%${warning}, ${formatBytes(
+            disk.available_bytes
+        )} free`;
+    });
+
+const networkLine = (networks = []) => {
+    if (!networks.length) return null;
+    const rx = networks.reduce((sum, net) => sum + net.rx_bytes_per_sec, 0);
+    const tx = networks.reduce((sum, net) => sum + net.tx_bytes_per_sec, 0);
+    return `net: ${formatBytes(rx)}/s in, ${formatBytes(tx)}/s out`;
+};
+
 const switchTab = (tabName) => {
     currentTab = tabName;
 
@@ -151,7 +192,11 @@ const renderNodes = (list, containerEl, tabType) => {
             `host_mem: ${formatBytes(stats.host_mem_used_bytes)} / ${formatBytes(
                 stats.host_mem_total_bytes
             )}`,
+            ...containerLines(stats.cgroup),
+            ...diskLines(stats.disks),
+            networkLine(stats.networks),
         ]
+            .filter(Boolean)
             .map((line) => `<div>${line}</div>`)
             .join("");
         card.appendChild(meta);
@@ -513,6 +558,26 @@ function connectSFTP() {
                     }
                 }
                 break;
+            case "SFTPWatchEvent":
+                if (sftpBrowser && currentTab === "sftp") {
+                    sftpBrowser.handleWatchEvent(frame.data.web.msg_id, frame.data.web.event);
+                }
+                break;
+            case "SFTPOperationProgress":
+                if (sftpBrowser && currentTab === "sftp") {
+                    sftpBrowser.handleOperationProgress(frame.data.web.msg_id, frame.data.web.progress);
+                }
+                break;
+            case "SFTPSearchResults":
+                if (sftpBrowser && currentTab === "sftp") {
+                    sftpBrowser.handleSearchResults(frame.data.web.msg_id, frame.data.web.results);
+                }
+                break;
+            case "SFTPDiskUsageResult":
+                if (sftpBrowser && currentTab === "sftp") {
+                    sftpBrowser.handleDiskUsageResult(frame.data.web.msg_id, frame.data.web.result);
+                }
+                break;
             case "TunnelOpened":
                 if (currentTab === "sftp") {
                     if (!sftpBrowser) {
@@ -528,9 +593,18 @@ function connectSFTP() {
                     if (sftpUploadBtn) sftpUploadBtn.disabled = false;
                 }
                 break;
+            case "NodeDraining":
+                if (currentTab === "sftp") {
+                    log(`Node is draining (${frame.data.web.reason}), session closes in ${frame.data.web.closes_in_secs}s`);
+                    setStatus("Node draining", "warn");
+                }
+                break;
             case "TunnelClosed":
                 if (currentTab === "sftp") {
                     log(`SFTP Tunnel closed - Session ID: ${frame.data.web.sid}`);
+                    if (frame.data.web.reason === CLOSE_REASON_DRAIN) {
+                        log("The node was drained, reconnect to continue");
+                    }
                     sftpBrowser.disconnect();
                     setStatus("SFTP Disconnected", "warn");
                     // Disable upload button
@@ -643,8 +717,15 @@ function connect() {
                     channel.send_ssh_terminal_resize(selected_node_id, session_id, term.cols, term.rows);
                 }
                 break;
+            case "NodeDraining":
+                log(`Node is draining (${frame.data.web.reason}), session closes in ${frame.data.web.closes_in_secs}s`);
+                setStatus("Node draining", "warn");
+                break;
             case "TunnelClosed":
                 log(`SSH Tunnel closed - Session ID: ${frame.data.web.sid}`);
+                if (frame.data.web.reason === CLOSE_REASON_DRAIN) {
+                    log("The node was drained, reconnect to continue");
+                }
                 setStatus("Tunnel closed", "warn");
                 term.reset();
                 cleanup();
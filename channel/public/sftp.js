@@ -17,6 +17,9 @@ export class SFTPBrowser {
         this.errorMessage = null; // Store current error for recovery
         this.deletePoll = null; // Track ongoing delete polling
         this.activeOps = 0; // Track ongoing blocking operations
+        this.activeSearch = null; // { msgId, path, pattern, items }
+        this.activeOperations = new Map(); // msgId -> { label } for remote copy / extract
+        this.watch = null; // { msgId, path } of the directory the agent watches for changes
 
         this.setupElements();
         this.setupEventListeners();
@@ -28,6 +31,8 @@ export class SFTPBrowser {
         this.pathInput = document.getElementById("sftp-path");
         this.backBtn = document.getElementById("sftp-back");
         this.refreshBtn = document.getElementById("sftp-refresh");
+        this.searchInput = document.getElementById("sftp-search");
+        this.usageBtn = document.getElementById("sftp-usage");
 
         this.credentialsModal = document.getElementById("sftp-credentials-modal");
         this.usernameInput = document.getElementById("sftp-username");
@@ -46,6 +51,14 @@ export class SFTPBrowser {
     setupEventListeners() {
         this.backBtn.addEventListener("click", () => this.goBack());
         this.refreshBtn.addEventListener("click", () => this.refresh());
+        if (this.usageBtn) {
+            this.usageBtn.addEventListener("click", () => this.showDiskUsage(this.currentPath));
+        }
+        if (this.searchInput) {
+            this.searchInput.addEventListener("keypress", (e) => {
+                if (e.key === "Enter") this.search(this.searchInput.value.trim());
+            });
+        }
 
         this.credsSubmitBtn.addEventListener("click", () => this.submitCredentials());
         this.credsCancelBtn.addEventListener("click", () => this.cancelCredentials());
@@ -76,6 +89,7 @@ export class SFTPBrowser {
 
     handleTunnelOpened(sessionId) {
         this.sessionId = sessionId;
+        this.watch = null; // watches belong to the previous tunnel
         console.log("SFTP tunnel opened, session ID:", sessionId, "- Requesting directory listing for $HOME");
         // Request directory listing when tunnel is opened; start at root for consistency
         this.listDirectory(".");
@@ -111,6 +125,39 @@ export class SFTPBrowser {
         }
     }
 
+    watchDirectory(path) {
+        if (this.watch && this.watch.path === path) return;
+
+        const msgId = this.msgId++;
+        this.watch = { msgId, path };
+        this.socket.send_sftp_watch(this.selectedNode, this.sessionId, path, msgId);
+    }
+
+    handleWatchEvent(msgId, event) {
+        if (!this.watch || this.watch.msgId !== msgId) return;
+        // Keep search results and other views untouched, the listing refreshes on return
+        if (this.activeSearch || event.path !== this.currentPath) return;
+
+        for (const change of event.changes) {
+            const item = {
+                name: change.item.name,
+                is_dir: change.item.kind === "Folder" || change.item.kind === 1,
+                size: change.item.attributes ? change.item.attributes.size : 0
+            };
+
+            this.currentItems = this.currentItems.filter((existing) => existing.name !== item.name);
+            if (change.kind !== "Deleted") {
+                this.currentItems.push(item);
+            }
+        }
+
+        this.renderBrowser();
+        this.previousState = {
+            path: this.currentPath,
+            items: [...this.currentItems]
+        };
+    }
+
     handleListComplete(msgId, path) {
         // Handle completion of directory listing, even if empty
         if (!this.pendingListings.has(msgId)) {
@@ -138,6 +185,7 @@ export class SFTPBrowser {
                 path: path,
                 items: [...listing.items]
             };
+            this.watchDirectory(path);
         }
     }
 
@@ -238,10 +286,20 @@ export class SFTPBrowser {
                 });
                 buttonContainer.appendChild(deleteBtn);
 
+                if (this.isArchive(item.name)) {
+                    buttonContainer.prepend(this.createActionButton("📦", "Extract here", () => this.extractArchive(item.name)));
+                }
+                buttonContainer.prepend(this.createActionButton("⧉", "Copy", () => this.copyItem(item.name)));
+
                 itemEl.appendChild(buttonContainer);
             }
 
             if (item.is_dir) {
+                const buttonContainer = document.createElement("div");
+                buttonContainer.style.cssText = "margin-left: auto; display: flex; gap: 6px;";
+                buttonContainer.appendChild(this.createActionButton("⧉", "Copy", () => this.copyItem(item.name)));
+                itemEl.appendChild(buttonContainer);
+
                 itemEl.style.cursor = "pointer";
                 itemEl.addEventListener("click", () => {
                     const newPath = this.normalizePath(this.currentPath, item.name);
@@ -253,6 +311,65 @@ export class SFTPBrowser {
         });
     }
 
+    createActionButton(label, title, onClick) {
+        const button = document.createElement("button");
+        button.textContent = label;
+        button.title = title;
+        button.style.cssText = "padding: 4px 12px; background-color: #6b7280; color: white; border: none; border-radius: 4px; cursor: pointer; font-size: 16px;";
+        button.addEventListener("click", (e) => {
+            e.stopPropagation();
+            onClick();
+        });
+        return button;
+    }
+
+    isArchive(name) {
+        const lower = name.toLowerCase();
+        return [".tar", ".tar.gz", ".tgz", ".zip"].some((ext) => lower.endsWith(ext));
+    }
+
+    copyItem(name) {
+        const source = this.normalizePath(this.currentPath, name);
+        const destination = prompt(`Copy "${name}" to:`, `${source}.copy`);
+        if (!destination) return;
+
+        const msgId = this.msgId++;
+        this.activeOperations.set(msgId, { label: "Copying" });
+        this.showLoader(`Copying ${name}...`, true);
+        this.socket.send_sftp_copy(this.selectedNode, this.sessionId, source, destination, false, msgId);
+    }
+
+    extractArchive(name) {
+        if (!confirm(`Extract "${name}" into ${this.currentPath}? Existing files will be overwritten.`)) {
+            return;
+        }
+
+        const msgId = this.msgId++;
+        this.activeOperations.set(msgId, { label: "Extracting" });
+        this.showLoader(`Extracting ${name}...`, true);
+        // An empty destination extracts next to the archive
+        this.socket.send_sftp_extract(this.selectedNode, this.sessionId, this.normalizePath(this.currentPath, name), "", true, msgId);
+    }
+
+    handleOperationProgress(msgId, progress) {
+        const operation = this.activeOperations.get(msgId);
+        if (!operation) return;
+
+        if (progress.done) {
+            this.activeOperations.delete(msgId);
+            this.hideLoader();
+            if (progress.skipped_entries > 0) {
+                alert(`${operation.label} finished, ${progress.skipped_entries} link(s) or unsafe path(s) were skipped`);
+            }
+            this.refresh();
+            return;
+        }
+
+        const info = `${progress.entries_done} item(s), ${this.formatBytes(progress.bytes_done)}`;
+        const percent = progress.total_bytes ? (progress.bytes_done / progress.total_bytes) * 100 : 0;
+        this.setLoaderProgress(percent, info);
+    }
+
     normalizePath(currentPath, name) {
         if (currentPath === "/") {
             return `/${name}`;
@@ -272,6 +389,169 @@ export class SFTPBrowser {
         this.listDirectory(this.currentPath);
     }
 
+    search(pattern) {
+        if (!this.socket || !this.sessionId) {
+            this.browser.innerHTML = '<div class="sftp-item-loading">Not connected</div>';
+            return;
+        }
+
+        if (!pattern) {
+            this.activeSearch = null;
+            this.refresh();
+            return;
+        }
+
+        const msgId = this.msgId++;
+        this.activeSearch = { msgId, path: this.currentPath, pattern, items: [] };
+        this.browser.innerHTML = `<div class="sftp-item-loading">Searching for "${pattern}"...</div>`;
+
+        // 0 lets the agent apply its default depth, result and timeout limits
+        this.socket.send_sftp_search(this.selectedNode, this.sessionId, this.currentPath, pattern, 0, 0, 0, msgId);
+    }
+
+    handleSearchResults(msgId, results) {
+        const search = this.activeSearch;
+        if (!search || search.msgId !== msgId) {
+            // Results for a search that has since been replaced
+            return;
+        }
+
+        search.items.push(...results.items);
+
+        if (results.done) {
+            this.renderSearchResults(results.truncated, results.timed_out);
+        } else {
+            this.browser.innerHTML = `<div class="sftp-item-loading">Searching for "${search.pattern}"... ${search.items.length} found</div>`;
+        }
+    }
+
+    renderSearchResults(truncated, timedOut) {
+        const search = this.activeSearch;
+        this.browser.innerHTML = "";
+
+        const header = document.createElement("div");
+        header.className = "sftp-item-loading";
+        header.textContent = `${search.items.length} match(es) for "${search.pattern}" in ${search.path}`;
+        if (truncated) header.textContent += " (limit reached)";
+        if (timedOut) header.textContent += " (timed out)";
+        this.browser.appendChild(header);
+
+        search.items.forEach((item) => {
+            const isDir = item.kind === "Folder" || item.kind === 1;
+            const fullPath = this.normalizePath(item.path, item.name);
+
+            const itemEl = document.createElement("div");
+            itemEl.className = "sftp-item";
+            itemEl.style.cursor = "pointer";
+            itemEl.title = fullPath;
+
+            const icon = document.createElement("div");
+            icon.className = "sftp-item-icon";
+            icon.textContent = isDir ? "📁" : "📄";
+            itemEl.appendChild(icon);
+
+            const name = document.createElement("div");
+            name.className = `sftp-item-name ${isDir ? "sftp-item-dir" : "sftp-item-file"}`;
+            name.textContent = fullPath;
+            itemEl.appendChild(name);
+
+            if (!isDir && item.attributes) {
+                const size = document.createElement("div");
+                size.className = "sftp-item-size";
+                size.textContent = this.formatBytes(item.attributes.size);
+                itemEl.appendChild(size);
+            }
+
+            // Open the folder itself, or the folder containing the file
+            itemEl.addEventListener("click", () => {
+                this.activeSearch = null;
+                this.listDirectory(isDir ? fullPath : item.path);
+            });
+
+            this.browser.appendChild(itemEl);
+        });
+    }
+
+    // Size of each entry of `path`, largest first: "what's eating /var"
+    async showDiskUsage(path) {
+        if (!this.socket || !this.sessionId) {
+            this.browser.innerHTML = '<div class="sftp-item-loading">Not connected</div>';
+            return;
+        }
+
+        this.activeSearch = null;
+        this.browser.innerHTML = "";
+        const loading = document.createElement("div");
+        loading.className = "sftp-item-loading";
+        loading.textContent = `Measuring ${path}...`;
+        this.browser.appendChild(loading);
+
+        let usage;
+        try {
+            // The walk can take a while on big trees; 0 entries uses the agent default
+            usage = await this.requestDiskUsage(path, 3, 0, 60000);
+        } catch (err) {
+            loading.textContent = `Failed to measure ${path}: ${err.message}`;
+            return;
+        }
+
+        this.renderDiskUsage(usage);
+    }
+
+    renderDiskUsage(usage) {
+        this.browser.innerHTML = "";
+
+        const header = document.createElement("div");
+        header.className = "sftp-item-loading";
+        header.textContent = `${this.formatBytes(usage.total_size)} in ${usage.path} (${usage.scanned_entries} entries)`;
+        if (usage.truncated) header.textContent += " (limit reached, sizes are a lower bound)";
+        this.browser.appendChild(header);
+
+        const fs = usage.filesystem;
+        if (fs) {
+            const used = fs.total_bytes - fs.free_bytes;
+            const filesystem = document.createElement("div");
+            filesystem.className = "sftp-item-loading";
+            filesystem.textContent =
+                `Filesystem: ${this.formatBytes(used)} of ${this.formatBytes(fs.total_bytes)} used, ` +
+                `${this.formatBytes(fs.available_bytes)} available, ` +
+                `${fs.total_inodes - fs.free_inodes} of ${fs.total_inodes} inodes used`;
+            this.browser.appendChild(filesystem);
+        }
+
+        usage.children.forEach((child) => {
+            const isDir = child.kind === "Folder" || child.kind === 1;
+            const share = usage.total_size ? (child.size / usage.total_size) * 100 : 0;
+
+            const itemEl = document.createElement("div");
+            itemEl.className = "sftp-item";
+            itemEl.title = child.path;
+
+            const icon = document.createElement("div");
+            icon.className = "sftp-item-icon";
+            icon.textContent = isDir ? "📁" : "📄";
+            itemEl.appendChild(icon);
+
+            const name = document.createElement("div");
+            name.className = `sftp-item-name ${isDir ? "sftp-item-dir" : "sftp-item-file"}`;
+            name.textContent = child.name;
+            itemEl.appendChild(name);
+
+            const size = document.createElement("div");
+            size.className = "sftp-item-size";
+            size.textContent = `${this.formatBytes(child.size)} (${share.toFixed(1)}%)`;
+            itemEl.appendChild(size);
+
+            // Drill down into folders
+            if (isDir) {
+                itemEl.style.cursor = "pointer";
+                itemEl.addEventListener("click", () => this.showDiskUsage(child.path));
+            }
+
+            this.browser.appendChild(itemEl);
+        });
+    }
+
     showCredentialsModal() {
         this.awaitingCredentials = true;
         this.usernameInput.value = "";
@@ -321,6 +601,26 @@ export class SFTPBrowser {
     }
 
     handleListingError(msgId, message) {
+        // A failed free space probe should not replace the current listing
+        const diskUsage = this.pendingDiskUsage?.get(msgId);
+        if (diskUsage) {
+            clearTimeout(diskUsage.timeout);
+            diskUsage.reject(new Error(message));
+            this.pendingDiskUsage.delete(msgId);
+            return;
+        }
+
+        if (this.activeOperations.delete(msgId)) {
+            this.hideLoader();
+        }
+
+        // Losing the watch only means the listing needs a manual refresh
+        if (this.watch && this.watch.msgId === msgId) {
+            console.warn(`Directory watch stopped: ${message}`);
+            this.watch = null;
+            return;
+        }
+
         this.errorMessage = message;
         // If there's a msg_id, check if it matches the current pending listing
         if (msgId !== null && msgId !== undefined && this.pendingListings.has(msgId)) {
@@ -384,6 +684,7 @@ export class SFTPBrowser {
         this.sessionId = null;
         this.selectedNode = null;
         this.currentItems = [];
+        this.watch = null;
         this.container.style.display = "none";
         this.hideCredentialsModal();
         this.hideLoader(true);
@@ -779,6 +1080,22 @@ export class SFTPBrowser {
             return;
         }
 
+        // Warn before starting an upload that the remote filesystem cannot hold
+        const usage = await this.requestDiskUsage(this.currentPath).catch((err) => {
+            console.warn("Could not check remote free space:", err);
+            return null;
+        });
+        const available = usage?.filesystem?.available_bytes;
+        if (available !== undefined && available < file.size) {
+            const proceed = confirm(
+                `Only ${this.formatBytes(available)} is available on the remote filesystem, ` +
+                `but "${file.name}" is ${this.formatBytes(file.size)}. Upload anyway?`
+            );
+            if (!proceed) {
+                return;
+            }
+        }
+
         this.showLoader("Uploading...", true);
 
         const CHUNK_SIZE = 64 * 1024; // 64KB chunks
@@ -896,6 +1213,35 @@ export class SFTPBrowser {
         }
     }
 
+    requestDiskUsage(path, maxDepth = 0, maxEntries = 0, timeoutMs = 10000) {
+        const msgId = this.msgId++;
+
+        return new Promise((resolve, reject) => {
+            const timeout = setTimeout(() => {
+                this.pendingDiskUsage.delete(msgId);
+                reject(new Error("Disk usage timeout"));
+            }, timeoutMs);
+
+            this.pendingDiskUsage = this.pendingDiskUsage || new Map();
+            this.pendingDiskUsage.set(msgId, { resolve, reject, timeout });
+
+            this.socket.send_sftp_disk_usage(this.selectedNode, this.sessionId, path, maxDepth, maxEntries, msgId);
+        });
+    }
+
+    handleDiskUsageResult(msgId, result) {
+        if (!this.pendingDiskUsage) {
+            this.pendingDiskUsage = new Map();
+        }
+
+        const pending = this.pendingDiskUsage.get(msgId);
+        if (pending) {
+            clearTimeout(pending.timeout);
+            pending.resolve(result);
+            this.pendingDiskUsage.delete(msgId);
+        }
+    }
+
     handleDownloadStartResponse(msgId, download_id, total_size, total_chunks) {
         if (!this.pendingDownloadStarts) {
             this.pendingDownloadStarts = new Map();
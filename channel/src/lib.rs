@@ -392,6 +392,117 @@ impl Channel {
         })
     }
 
+    pub fn send_sftp_disk_usage(
+        &self,
+        node_id: String,
+        sid: u32,
+        path: String,
+        max_depth: u32,
+        max_entries: u32,
+        msg_id: Option<u32>,
+    ) {
+        let data = phirepass_common::protocol::sftp::SFTPDiskUsage {
+            path,
+            max_depth,
+            max_entries,
+        };
+        self.send_frame_data(WebFrameData::SFTPDiskUsage {
+            node_id,
+            sid,
+            msg_id,
+            data,
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn send_sftp_search(
+        &self,
+        node_id: String,
+        sid: u32,
+        path: String,
+        pattern: String,
+        max_depth: u32,
+        max_results: u32,
+        timeout_secs: u32,
+        msg_id: Option<u32>,
+    ) {
+        let data = phirepass_common::protocol::sftp::SFTPSearch {
+            path,
+            pattern,
+            max_depth,
+            max_results,
+            timeout_secs,
+        };
+        self.send_frame_data(WebFrameData::SFTPSearch {
+            node_id,
+            sid,
+            msg_id,
+            data,
+        })
+    }
+
+    pub fn send_sftp_copy(
+        &self,
+        node_id: String,
+        sid: u32,
+        source: String,
+        destination: String,
+        overwrite: bool,
+        msg_id: Option<u32>,
+    ) {
+        let data = phirepass_common::protocol::sftp::SFTPCopy {
+            source,
+            destination,
+            overwrite,
+        };
+        self.send_frame_data(WebFrameData::SFTPCopy {
+            node_id,
+            sid,
+            msg_id,
+            data,
+        })
+    }
+
+    pub fn send_sftp_extract(
+        &self,
+        node_id: String,
+        sid: u32,
+        path: String,
+        destination: String,
+        overwrite: bool,
+        msg_id: Option<u32>,
+    ) {
+        let data = phirepass_common::protocol::sftp::SFTPExtract {
+            path,
+            destination,
+            overwrite,
+        };
+        self.send_frame_data(WebFrameData::SFTPExtract {
+            node_id,
+            sid,
+            msg_id,
+            data,
+        })
+    }
+
+    pub fn send_sftp_watch(&self, node_id: String, sid: u32, path: String, msg_id: Option<u32>) {
+        let data = phirepass_common::protocol::sftp::SFTPWatch { path };
+        self.send_frame_data(WebFrameData::SFTPWatch {
+            node_id,
+            sid,
+            msg_id,
+            data,
+        })
+    }
+
+    pub fn send_sftp_unwatch(&self, node_id: String, sid: u32, msg_id: Option<u32>) {
+        self.send_frame_data(WebFrameData::SFTPUnwatch {
+            node_id,
+            sid,
+            msg_id,
+        })
+    }
+
     pub fn is_connected(&self) -> bool {
         if let Some(socket) = self.state.borrow().socket.as_ref() {
             socket.ready_state() == WebSocket::OPEN
@@ -445,6 +556,9 @@ pub enum ErrorType {
     Generic = 0,
     RequiresUsername = 100,
     RequiresPassword = 110,
+    SessionLimit = 120,
+    Draining = 130,
+    AccountDenied = 140,
 }
 
 #[repr(u8)]
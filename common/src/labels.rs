@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+
+pub type Labels = BTreeMap<String, String>;
+
+const MAX_KEY_LEN: usize = 63;
+const MAX_VALUE_LEN: usize = 63;
+
+/// Keys are 1-63 characters and values up to 63, both limited to
+/// alphanumerics, `-`, `_`, `.` and, for keys, a `/` prefix separator.
+pub fn validate(labels: &Labels) -> Result<(), String> {
+    for (key, value) in labels {
+        if key.is_empty() || key.len() > MAX_KEY_LEN || !key.chars().all(|c| valid_char(c, true)) {
+            return Err(format!("invalid label key {key:?}"));
+        }
+
+        if value.len() > MAX_VALUE_LEN || !value.chars().all(|c| valid_char(c, false)) {
+            return Err(format!("invalid value {value:?} for label {key}"));
+        }
+    }
+
+    Ok(())
+}
+
+fn valid_char(c: char, key: bool) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') || (key && c == '/')
+}
+
+/// Parses `key=value,key=value`.
+pub fn parse(value: &str) -> Result<Labels, String> {
+    let labels = value
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) => Ok((key.trim().to_string(), value.trim().to_string())),
+            None => Err(format!("label {pair:?} is not key=value")),
+        })
+        .collect::<Result<Labels, _>>()?;
+
+    validate(&labels)?;
+    Ok(labels)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Requirement {
+    Equals(String, String),
+    NotEquals(String, String),
+    In(String, Vec<String>),
+    NotIn(String, Vec<String>),
+    Exists(String),
+    NotExists(String),
+}
+
+impl Requirement {
+    fn matches(&self, labels: &Labels) -> bool {
+        match self {
+            Requirement::Equals(key, value) => labels.get(key) == Some(value),
+            Requirement::NotEquals(key, value) => labels.get(key) != Some(value),
+            Requirement::In(key, values) => labels.get(key).is_some_and(|v| values.contains(v)),
+            Requirement::NotIn(key, values) => !labels.get(key).is_some_and(|v| values.contains(v)),
+            Requirement::Exists(key) => labels.contains_key(key),
+            Requirement::NotExists(key) => !labels.contains_key(key),
+        }
+    }
+}
+
+/// A label selector in the Kubernetes style: comma separated requirements,
+/// all of which must hold. Supports `key=value` (or `==`), `key!=value`,
+/// `key in (a,b)`, `key notin (a,b)`, `key` and `!key`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Selector {
+    requirements: Vec<Requirement>,
+}
+
+impl Selector {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let requirements = split_requirements(value)?
+            .into_iter()
+            .map(parse_requirement)
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { requirements })
+    }
+
+    pub fn matches(&self, labels: &Labels) -> bool {
+        self.requirements
+            .iter()
+            .all(|requirement| requirement.matches(labels))
+    }
+}
+
+// Splits on commas outside of `( ... )` value sets
+fn split_requirements(value: &str) -> Result<Vec<&str>, String> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in value.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Err("unbalanced ')' in selector".to_string()),
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    if depth != 0 {
+        return Err("unbalanced '(' in selector".to_string());
+    }
+    parts.push(&value[start..]);
+
+    Ok(parts
+        .into_iter()
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .collect())
+}
+
+fn parse_requirement(part: &str) -> Result<Requirement, String> {
+    let key = |key: &str| -> Result<String, String> {
+        let key = key.trim();
+        if key.is_empty() || key.len() > MAX_KEY_LEN || !key.chars().all(|c| valid_char(c, true)) {
+            return Err(format!("invalid label key {key:?} in selector"));
+        }
+        Ok(key.to_string())
+    };
+
+    if let Some((k, v)) = part.split_once("!=") {
+        return Ok(Requirement::NotEquals(key(k)?, v.trim().to_string()));
+    }
+    if let Some((k, v)) = part.split_once("==").or_else(|| part.split_once('=')) {
+        return Ok(Requirement::Equals(key(k)?, v.trim().to_string()));
+    }
+    if let Some((k, values)) = split_set(part, " notin ")? {
+        return Ok(Requirement::NotIn(key(k)?, values));
+    }
+    if let Some((k, values)) = split_set(part, " in ")? {
+        return Ok(Requirement::In(key(k)?, values));
+    }
+    if let Some(k) = part.strip_prefix('!') {
+        return Ok(Requirement::NotExists(key(k)?));
+    }
+
+    Ok(Requirement::Exists(key(part)?))
+}
+
+fn split_set<'a>(part: &'a str, operator: &str) -> Result<Option<(&'a str, Vec<String>)>, String> {
+    let Some((key, set)) = part.split_once(operator) else {
+        return Ok(None);
+    };
+
+    let values = set
+        .trim()
+        .strip_prefix('(')
+        .and_then(|set| set.strip_suffix(')'))
+        .ok_or_else(|| format!("expected a (a,b) value set in {part:?}"))?
+        .split(',')
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .collect();
+
+    Ok(Some((key, values)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> Labels {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_and_validates_labels() {
+        assert_eq!(
+            parse("environment=prod, team=payments,region=").unwrap(),
+            labels(&[
+                ("environment", "prod"),
+                ("team", "payments"),
+                ("region", "")
+            ])
+        );
+        assert!(parse("environment").is_err());
+        assert!(parse("bad key=x").is_err());
+        assert!(parse(&format!("k={}", "v".repeat(64))).is_err());
+    }
+
+    #[test]
+    fn matches_selectors() {
+        let node = labels(&[("environment", "prod"), ("team", "payments")]);
+
+        let matches = |selector: &str| Selector::parse(selector).unwrap().matches(&node);
+        assert!(matches(""));
+        assert!(matches("environment=prod"));
+        assert!(matches("environment==prod,team"));
+        assert!(matches("team in (payments, search),!region"));
+        assert!(matches("environment notin (dev,staging)"));
+        assert!(matches("region!=eu"));
+
+        assert!(!matches("environment=dev"));
+        assert!(!matches("team notin (payments)"));
+        assert!(!matches("region"));
+        assert!(!matches("!team"));
+
+        assert!(Selector::parse("team in (a").is_err());
+        assert!(Selector::parse("team in a").is_err());
+        assert!(Selector::parse("bad key=x").is_err());
+    }
+}
@@ -2,6 +2,8 @@
 pub mod env;
 #[cfg(feature = "ip")]
 pub mod ip;
+#[cfg(feature = "labels")]
+pub mod labels;
 pub mod logger;
 #[cfg(feature = "node")]
 pub mod node;
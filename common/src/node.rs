@@ -1,3 +1,4 @@
+use crate::protocol::node::{TargetHealth, UsageTotals};
 use crate::stats::Stats;
 use serde::{Deserialize, Serialize};
 use std::net::IpAddr;
@@ -9,4 +10,8 @@ pub struct Node {
     pub last_heartbeat: SystemTime,
     pub ip: IpAddr,
     pub last_stats: Option<Stats>,
+    #[serde(default)]
+    pub last_health: Option<TargetHealth>,
+    #[serde(default)]
+    pub last_usage: Option<UsageTotals>,
 }
@@ -169,6 +169,12 @@ pub enum FrameError {
     Authentication = 10,
     RequiresUsername = 100,
     RequiresPassword = 110,
+    /// the agent refused a tunnel because it is at a session limit
+    SessionLimit = 120,
+    /// the agent refused a tunnel because it is draining
+    Draining = 130,
+    /// the agent does not let this user log in as the requested account
+    AccountDenied = 140,
 }
 
 impl Serialize for FrameError {
@@ -197,7 +203,48 @@ impl From<u8> for FrameError {
             10 => Self::Authentication,
             100 => Self::RequiresUsername,
             110 => Self::RequiresPassword,
+            120 => Self::SessionLimit,
+            130 => Self::Draining,
+            140 => Self::AccountDenied,
             _ => Self::Generic,
         }
     }
 }
+
+/// Why the agent closed a tunnel, sent with `TunnelClosed`.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseReason {
+    /// the session ended on its own or was closed by the user
+    Ended = 0,
+    /// the agent drained for a shutdown or server maintenance
+    Drain = 10,
+}
+
+impl Serialize for CloseReason {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_u8(*self as u8)
+    }
+}
+
+impl<'de> Deserialize<'de> for CloseReason {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = u8::deserialize(deserializer)?;
+        Ok(Self::from(value))
+    }
+}
+
+impl From<u8> for CloseReason {
+    fn from(value: u8) -> Self {
+        match value {
+            10 => Self::Drain,
+            _ => Self::Ended,
+        }
+    }
+}
@@ -1,10 +1,13 @@
+use crate::protocol::common::CloseReason;
 use crate::protocol::sftp::{
-    SFTPDelete, SFTPDownloadChunk, SFTPDownloadStart, SFTPUploadChunk, SFTPUploadStart,
+    SFTPCopy, SFTPDelete, SFTPDiskUsage, SFTPDownloadChunk, SFTPDownloadStart, SFTPExtract,
+    SFTPSearch, SFTPUploadChunk, SFTPUploadStart, SFTPWatch,
 };
 use crate::protocol::web::WebFrameData;
 use crate::stats::Stats;
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use uuid::Uuid;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -13,12 +16,83 @@ pub enum WebFrameId {
     ConnectionId(Uuid),
 }
 
+/// State of the agent's local SSH target, probed on every heartbeat.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct TargetHealth {
+    pub host: String,
+    pub port: u16,
+    /// Whether `host:port` accepted a TCP connection.
+    pub reachable: bool,
+    /// Identification line sent by sshd, e.g. `SSH-2.0-OpenSSH_9.6`.
+    pub banner: Option<String>,
+    pub checked_at: u64,
+    pub last_tunnel_error: Option<TunnelError>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TunnelError {
+    pub message: String,
+    pub at: u64,
+}
+
+/// Phirepass user the server authenticated for a web connection, sent with
+/// `OpenTunnel` so the agent can map it to local accounts.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct WebUser {
+    pub user_id: Uuid,
+    pub roles: Vec<String>,
+}
+
+/// Bytes and lifetime of one tunnel as seen by the agent. `bytes_in` flows
+/// from the web client to the target, `bytes_out` back; for SFTP only file
+/// data is counted.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct SessionUsage {
+    pub username: String,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub opened_at: u64,
+    pub closed_at: u64,
+    /// uploads and downloads that ended during the session
+    pub transfers: Vec<TransferUsage>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct TransferUsage {
+    pub upload: bool,
+    pub path: String,
+    pub bytes: u64,
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub completed: bool,
+}
+
+/// Totals of the tunnels one server link carried since the agent started,
+/// sent with every heartbeat on that link.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct UsageTotals {
+    pub sessions_closed: u64,
+    pub ssh_bytes_in: u64,
+    pub ssh_bytes_out: u64,
+    pub sftp_bytes_in: u64,
+    pub sftp_bytes_out: u64,
+    pub uploads_completed: u64,
+    pub downloads_completed: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "type")]
 pub enum NodeFrameData {
     Heartbeat {
-        stats: Stats,
+        // boxed to keep the other frames small; serialized the same
+        stats: Box<Stats>,
         sent_at: u64,
+        // last, and defaulted, so frames from agents that predate target
+        // health still decode
+        #[serde(default)]
+        health: Option<TargetHealth>,
+        #[serde(default)]
+        usage: Option<UsageTotals>,
     },
 
     HeartbeatAck {
@@ -32,6 +106,11 @@ pub enum NodeFrameData {
         token: String,
         node_id: Uuid,
         version: String,
+        /// a `doctor` check: the server answers but does not register the
+        /// connection, so the running agent keeps its own. Left out when
+        /// false, so older servers still decode a regular auth frame
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        probe: bool,
     },
 
     /// server must validate the token again and again and respond
@@ -41,12 +120,26 @@ pub enum NodeFrameData {
         version: String,
     },
 
+    /// labels and metadata from the agent config, sent after every
+    /// successful auth
+    NodeMetadata {
+        labels: BTreeMap<String, String>,
+        metadata: serde_json::Value,
+    },
+
     OpenTunnel {
         protocol: u8,
         cid: Uuid,
         username: Option<String>,
         password: Option<String>,
         msg_id: Option<u32>, // custom web user supplied. easier to track responses and map them to requests
+        /// address of the browser that asked for the tunnel, for the agent's
+        /// audit log
+        #[serde(default)]
+        client_ip: Option<String>,
+        /// `None` from servers that predate it or skip web authentication
+        #[serde(default)]
+        user: Option<WebUser>,
     },
 
     TunnelOpened {
@@ -68,6 +161,10 @@ pub enum NodeFrameData {
         cid: Uuid,
         sid: u32,
         msg_id: Option<u32>, // echo back the user supplied msg_id
+        #[serde(default)]
+        usage: Option<SessionUsage>,
+        #[serde(default)]
+        reason: Option<CloseReason>,
     }, // notify web that the tunnel is closed
 
     SSHWindowResize {
@@ -129,6 +226,47 @@ pub enum NodeFrameData {
         data: SFTPDelete,
     },
 
+    SFTPDiskUsage {
+        cid: Uuid,
+        sid: u32,
+        msg_id: Option<u32>,
+        data: SFTPDiskUsage,
+    },
+
+    SFTPSearch {
+        cid: Uuid,
+        sid: u32,
+        msg_id: Option<u32>,
+        data: SFTPSearch,
+    },
+
+    SFTPCopy {
+        cid: Uuid,
+        sid: u32,
+        msg_id: Option<u32>,
+        data: SFTPCopy,
+    },
+
+    SFTPExtract {
+        cid: Uuid,
+        sid: u32,
+        msg_id: Option<u32>,
+        data: SFTPExtract,
+    },
+
+    SFTPWatch {
+        cid: Uuid,
+        sid: u32,
+        msg_id: Option<u32>,
+        data: SFTPWatch,
+    },
+
+    SFTPUnwatch {
+        cid: Uuid,
+        sid: u32,
+        msg_id: Option<u32>,
+    },
+
     WebFrame {
         frame: WebFrameData,
         id: WebFrameId,
@@ -137,6 +275,13 @@ pub enum NodeFrameData {
     ConnectionDisconnect {
         cid: Uuid,
     },
+
+    /// server asks the agent to warn its users, stop taking tunnels and
+    /// close its sessions within `timeout_secs`, then reconnect
+    Drain {
+        reason: String,
+        timeout_secs: u32,
+    },
 }
 
 impl NodeFrameData {
@@ -146,6 +291,7 @@ impl NodeFrameData {
             NodeFrameData::HeartbeatAck { .. } => 2,
             NodeFrameData::Auth { .. } => 10,
             NodeFrameData::AuthResponse { .. } => 11,
+            NodeFrameData::NodeMetadata { .. } => 12,
             NodeFrameData::OpenTunnel { .. } => 20,
             NodeFrameData::TunnelOpened { .. } => 21,
             NodeFrameData::TunnelData { .. } => 22,
@@ -158,8 +304,208 @@ impl NodeFrameData {
             NodeFrameData::SFTPUploadStart { .. } => 35,
             NodeFrameData::SFTPUpload { .. } => 36,
             NodeFrameData::SFTPDelete { .. } => 37,
+            NodeFrameData::SFTPDiskUsage { .. } => 38,
+            NodeFrameData::SFTPSearch { .. } => 39,
+            NodeFrameData::SFTPCopy { .. } => 40,
+            NodeFrameData::SFTPExtract { .. } => 41,
+            NodeFrameData::SFTPWatch { .. } => 42,
+            NodeFrameData::SFTPUnwatch { .. } => 43,
             NodeFrameData::WebFrame { .. } => 50,
             NodeFrameData::ConnectionDisconnect { .. } => 60,
+            NodeFrameData::Drain { .. } => 61,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::stats::{CgroupStats, DiskStats, NetworkStats};
+
+    /// `Stats` as sent by agents that predate disks, networks and cgroup.
+    #[derive(Serialize)]
+    struct OldStats {
+        last_refreshed_secs: u64,
+        proc_id: String,
+        proc_threads: usize,
+        proc_cpu: f32,
+        proc_mem_bytes: u64,
+        proc_uptime_secs: u64,
+        host_name: String,
+        host_ip: String,
+        host_mac: String,
+        host_cpu: f32,
+        host_mem_used_bytes: u64,
+        host_mem_total_bytes: u64,
+        host_uptime_secs: u64,
+        host_load_average: [f64; 3],
+        host_os_info: String,
+        host_connections: usize,
+        host_processes: usize,
+    }
+
+    #[derive(Serialize)]
+    #[serde(tag = "type")]
+    enum OldNodeFrameData {
+        Heartbeat { stats: OldStats, sent_at: u64 },
+    }
+
+    #[derive(Serialize)]
+    #[serde(tag = "type")]
+    enum OldAuthFrame {
+        Auth {
+            token: String,
+            node_id: Uuid,
+            version: String,
+        },
+    }
+
+    #[test]
+    fn decodes_heartbeat_without_health() {
+        let stats = OldStats {
+            last_refreshed_secs: 0,
+            proc_id: "1".into(),
+            proc_threads: 1,
+            proc_cpu: 0.0,
+            proc_mem_bytes: 0,
+            proc_uptime_secs: 0,
+            host_name: "node".into(),
+            host_ip: "127.0.0.1".into(),
+            host_mac: String::new(),
+            host_cpu: 0.0,
+            host_mem_used_bytes: 0,
+            host_mem_total_bytes: 0,
+            host_uptime_secs: 0,
+            host_load_average: [0.0; 3],
+            host_os_info: String::new(),
+            host_connections: 0,
+            host_processes: 0,
+        };
+        let old = OldNodeFrameData::Heartbeat { stats, sent_at: 7 };
+
+        let decoded: NodeFrameData =
+            rmp_serde::from_slice(&rmp_serde::to_vec(&old).unwrap()).unwrap();
+        let NodeFrameData::Heartbeat {
+            stats,
+            sent_at,
+            health,
+            usage,
+        } = decoded
+        else {
+            panic!("expected a heartbeat");
+        };
+
+        assert_eq!(sent_at, 7);
+        assert_eq!(health, None);
+        assert_eq!(usage, None);
+        assert_eq!(stats.host_name, "node");
+        assert!(stats.disks.is_empty());
+        assert!(stats.networks.is_empty());
+        assert_eq!(stats.cgroup, None);
+    }
+
+    #[test]
+    fn round_trips_heartbeat_resource_stats() {
+        let disk = DiskStats {
+            mount_point: "/".into(),
+            file_system: "ext4".into(),
+            total_bytes: 100,
+            available_bytes: 25,
+        };
+        let network = NetworkStats {
+            interface: "eth0".into(),
+            rx_bytes_per_sec: 10,
+            tx_bytes_per_sec: 20,
+            rx_total_bytes: 1000,
+            tx_total_bytes: 2000,
+        };
+        let cgroup = CgroupStats {
+            cpu_limit_cores: Some(1.5),
+            cpu_percent: Some(40.0),
+            memory_limit_bytes: Some(512),
+            memory_used_bytes: 128,
+        };
+        let stats = Stats {
+            last_refreshed_secs: 0,
+            proc_id: "1".into(),
+            proc_threads: 1,
+            proc_cpu: 0.0,
+            proc_mem_bytes: 0,
+            proc_uptime_secs: 0,
+            host_name: "node".into(),
+            host_ip: "127.0.0.1".into(),
+            host_mac: String::new(),
+            host_cpu: 0.0,
+            host_mem_used_bytes: 0,
+            host_mem_total_bytes: 0,
+            host_uptime_secs: 0,
+            host_load_average: [0.0; 3],
+            host_os_info: String::new(),
+            host_connections: 0,
+            host_processes: 0,
+            disks: vec![disk.clone()],
+            networks: vec![network.clone()],
+            cgroup: Some(cgroup.clone()),
+        };
+        let frame = NodeFrameData::Heartbeat {
+            stats: Box::new(stats),
+            sent_at: 7,
+            health: None,
+            usage: None,
+        };
+
+        let decoded: NodeFrameData =
+            rmp_serde::from_slice(&rmp_serde::to_vec(&frame).unwrap()).unwrap();
+        let NodeFrameData::Heartbeat { stats, .. } = decoded else {
+            panic!("expected a heartbeat");
+        };
+
+        assert_eq!(stats.disks, vec![disk]);
+        assert_eq!(stats.networks, vec![network]);
+        assert_eq!(stats.cgroup, Some(cgroup));
+    }
+
+    #[test]
+    fn auth_without_probe_keeps_the_old_shape() {
+        let node_id = Uuid::new_v4();
+        let old = OldAuthFrame::Auth {
+            token: "jwt".into(),
+            node_id,
+            version: "0.1.0".into(),
+        };
+        let auth = |probe| NodeFrameData::Auth {
+            token: "jwt".into(),
+            node_id,
+            version: "0.1.0".into(),
+            probe,
+        };
+
+        let old_bytes = rmp_serde::to_vec(&old).unwrap();
+        assert_eq!(rmp_serde::to_vec(&auth(false)).unwrap(), old_bytes);
+
+        let decoded: NodeFrameData = rmp_serde::from_slice(&old_bytes).unwrap();
+        assert!(matches!(decoded, NodeFrameData::Auth { probe: false, .. }));
+
+        let decoded: NodeFrameData =
+            rmp_serde::from_slice(&rmp_serde::to_vec(&auth(true)).unwrap()).unwrap();
+        assert!(matches!(decoded, NodeFrameData::Auth { probe: true, .. }));
+    }
+
+    #[test]
+    fn round_trips_node_metadata() {
+        let frame = NodeFrameData::NodeMetadata {
+            labels: BTreeMap::from([("environment".to_string(), "prod".to_string())]),
+            metadata: serde_json::json!({ "rack": { "row": 4 } }),
+        };
+
+        let decoded: NodeFrameData =
+            rmp_serde::from_slice(&rmp_serde::to_vec(&frame).unwrap()).unwrap();
+        let NodeFrameData::NodeMetadata { labels, metadata } = decoded else {
+            panic!("expected node metadata");
+        };
+
+        assert_eq!(labels["environment"], "prod");
+        assert_eq!(metadata["rack"]["row"], 4);
+    }
+}
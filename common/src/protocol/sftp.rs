@@ -69,3 +69,112 @@ pub struct SFTPDelete {
     pub path: String,
     pub filename: String,
 }
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SFTPDiskUsage {
+    pub path: String,
+    pub max_depth: u32, // 0 only reports filesystem info, 1 sizes the entries of `path` one level deep
+    pub max_entries: u32, // stop walking after this many entries, 0 uses the agent default
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SFTPFilesystemInfo {
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+    pub available_bytes: u64, // free space for unprivileged users
+    pub total_inodes: u64,
+    pub free_inodes: u64,
+    pub available_inodes: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SFTPDiskUsageEntry {
+    pub name: String,
+    pub path: String,
+    pub kind: SFTPListItemKind,
+    pub size: u64,
+    pub entries: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SFTPDiskUsageResult {
+    pub path: String,
+    pub filesystem: Option<SFTPFilesystemInfo>, // none if the server lacks statvfs@openssh.com
+    pub total_size: u64,
+    pub scanned_entries: u64,
+    pub truncated: bool, // depth or entry limit was hit, sizes are a lower bound
+    pub children: Vec<SFTPDiskUsageEntry>, // largest first
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SFTPSearch {
+    pub path: String,
+    pub pattern: String,   // `*` and `?` glob, plain text matches as a substring
+    pub max_depth: u32,    // 1 only searches `path` itself, 0 uses the agent default
+    pub max_results: u32,  // 0 uses the agent default
+    pub timeout_secs: u32, // 0 uses the agent default
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SFTPSearchResults {
+    pub path: String,
+    pub items: Vec<SFTPListItem>, // matches found since the previous batch
+    pub done: bool,               // last batch for this search
+    pub truncated: bool,          // depth or result limit was hit
+    pub timed_out: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SFTPCopy {
+    pub source: String,      // file or directory, copied recursively
+    pub destination: String, // full target path, not the parent directory
+    pub overwrite: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SFTPExtract {
+    pub path: String,        // .tar, .tar.gz, .tgz or .zip archive
+    pub destination: String, // empty extracts next to the archive
+    pub overwrite: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum SFTPOperationKind {
+    Copy = 0,
+    Extract = 1,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SFTPOperationProgress {
+    pub kind: SFTPOperationKind,
+    pub path: String, // entry currently being processed
+    pub entries_done: u64,
+    pub skipped_entries: u64, // links and unsafe archive paths are not copied or extracted
+    pub bytes_done: u64,
+    pub total_bytes: Option<u64>, // only known when copying a single file
+    pub done: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SFTPWatch {
+    pub path: String, // directory to watch, replaces any previous watch on the session
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum SFTPWatchChangeKind {
+    Created = 0,
+    Modified = 1,
+    Deleted = 2,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SFTPWatchChange {
+    pub kind: SFTPWatchChangeKind,
+    pub item: SFTPListItem, // last known state for deleted items
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SFTPWatchEvent {
+    pub path: String,
+    pub changes: Vec<SFTPWatchChange>,
+}
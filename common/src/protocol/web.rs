@@ -1,7 +1,9 @@
-use crate::protocol::common::FrameError;
+use crate::protocol::common::{CloseReason, FrameError};
 use crate::protocol::sftp::{
-    SFTPDelete, SFTPDownloadChunk, SFTPDownloadStart, SFTPDownloadStartResponse, SFTPListItem,
-    SFTPUploadChunk, SFTPUploadStart, SFTPUploadStartResponse,
+    SFTPCopy, SFTPDelete, SFTPDiskUsage, SFTPDiskUsageResult, SFTPDownloadChunk, SFTPDownloadStart,
+    SFTPDownloadStartResponse, SFTPExtract, SFTPListItem, SFTPOperationProgress, SFTPSearch,
+    SFTPSearchResults, SFTPUploadChunk, SFTPUploadStart, SFTPUploadStartResponse, SFTPWatch,
+    SFTPWatchEvent,
 };
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
@@ -55,8 +57,15 @@ pub enum WebFrameData {
         protocol: u8,
         sid: u32,
         msg_id: Option<u32>, // echo back the user supplied msg_id
+        #[serde(default)]
+        reason: Option<CloseReason>,
     }, // notify web that the tunnel is closed
 
+    NodeDraining {
+        reason: String,
+        closes_in_secs: u32,
+    }, // the node will close this connection's tunnels; sent once per drain
+
     SSHWindowResize {
         node_id: String,
         sid: u32,
@@ -140,6 +149,71 @@ pub enum WebFrameData {
         data: SFTPDelete,
     },
 
+    SFTPDiskUsage {
+        node_id: String,
+        sid: u32,
+        msg_id: Option<u32>,
+        data: SFTPDiskUsage,
+    },
+
+    SFTPDiskUsageResult {
+        sid: u32,
+        msg_id: Option<u32>, // echo back the user supplied msg_id
+        result: SFTPDiskUsageResult,
+    },
+
+    SFTPSearch {
+        node_id: String,
+        sid: u32,
+        msg_id: Option<u32>,
+        data: SFTPSearch,
+    },
+
+    SFTPSearchResults {
+        sid: u32,
+        msg_id: Option<u32>, // echo back the user supplied msg_id
+        results: SFTPSearchResults,
+    }, // streamed in batches until `done` is set
+
+    SFTPCopy {
+        node_id: String,
+        sid: u32,
+        msg_id: Option<u32>,
+        data: SFTPCopy,
+    },
+
+    SFTPExtract {
+        node_id: String,
+        sid: u32,
+        msg_id: Option<u32>,
+        data: SFTPExtract,
+    },
+
+    SFTPOperationProgress {
+        sid: u32,
+        msg_id: Option<u32>, // echo back the user supplied msg_id
+        progress: SFTPOperationProgress,
+    }, // sent periodically while a copy or extract runs, and once with `done` set
+
+    SFTPWatch {
+        node_id: String,
+        sid: u32,
+        msg_id: Option<u32>,
+        data: SFTPWatch,
+    },
+
+    SFTPUnwatch {
+        node_id: String,
+        sid: u32,
+        msg_id: Option<u32>,
+    },
+
+    SFTPWatchEvent {
+        sid: u32,
+        msg_id: Option<u32>, // msg_id of the SFTPWatch request
+        event: SFTPWatchEvent,
+    }, // changes in the watched directory since the last event
+
     Error {
         kind: FrameError,
         message: String,
@@ -170,6 +244,17 @@ impl WebFrameData {
             WebFrameData::SFTPUploadChunkAck { .. } => 49,
             WebFrameData::SFTPDelete { .. } => 50,
             WebFrameData::Error { .. } => 51,
+            WebFrameData::SFTPDiskUsage { .. } => 52,
+            WebFrameData::SFTPDiskUsageResult { .. } => 53,
+            WebFrameData::SFTPSearch { .. } => 54,
+            WebFrameData::SFTPSearchResults { .. } => 55,
+            WebFrameData::SFTPCopy { .. } => 56,
+            WebFrameData::SFTPExtract { .. } => 57,
+            WebFrameData::SFTPOperationProgress { .. } => 58,
+            WebFrameData::SFTPWatch { .. } => 59,
+            WebFrameData::SFTPUnwatch { .. } => 60,
+            WebFrameData::SFTPWatchEvent { .. } => 61,
+            WebFrameData::NodeDraining { .. } => 62,
         }
     }
 }
@@ -3,10 +3,12 @@ use mac_address::get_mac_address;
 use netstat2::{AddressFamilyFlags, ProtocolFlags, get_sockets_info};
 use os_info;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
 use std::sync::{Mutex, OnceLock};
 use std::time::{Duration, Instant};
-use sysinfo::{ProcessStatus, ProcessesToUpdate, System, get_current_pid};
+use sysinfo::{Disks, Networks, ProcessStatus, ProcessesToUpdate, System, get_current_pid};
 use thread_count::thread_count;
 
 pub fn format_mem(bytes: u64) -> String {
@@ -47,6 +49,53 @@ pub struct Stats {
     pub host_os_info: String,
     pub host_connections: usize,
     pub host_processes: usize,
+    // appended last so peers without them still decode
+    #[serde(default)]
+    pub disks: Vec<DiskStats>,
+    #[serde(default)]
+    pub networks: Vec<NetworkStats>,
+    #[serde(default)]
+    pub cgroup: Option<CgroupStats>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DiskStats {
+    pub mount_point: String,
+    pub file_system: String,
+    pub total_bytes: u64,
+    pub available_bytes: u64,
+}
+
+impl DiskStats {
+    pub fn used_percent(&self) -> f64 {
+        if self.total_bytes == 0 {
+            return 0.0;
+        }
+        (self.total_bytes - self.available_bytes.min(self.total_bytes)) as f64 * 100.0
+            / self.total_bytes as f64
+    }
+}
+
+/// Throughput of one interface, averaged since the previous refresh.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NetworkStats {
+    pub interface: String,
+    pub rx_bytes_per_sec: u64,
+    pub tx_bytes_per_sec: u64,
+    pub rx_total_bytes: u64,
+    pub tx_total_bytes: u64,
+}
+
+/// Limits of the cgroup (v2) the process runs in, present only when at
+/// least one of them is set, as in a container.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CgroupStats {
+    pub cpu_limit_cores: Option<f64>,
+    /// usage since the previous refresh as a percentage of the cpu limit
+    pub cpu_percent: Option<f32>,
+    pub memory_limit_bytes: Option<u64>,
+    /// working set: `memory.current` minus inactive file cache
+    pub memory_used_bytes: u64,
 }
 
 static HOST_IP: OnceLock<String> = OnceLock::new();
@@ -57,6 +106,17 @@ static SYS_INFO: OnceLock<Mutex<System>> = OnceLock::new();
 static CONNECTIONS_CACHE: OnceLock<Mutex<ConnectionCache>> = OnceLock::new();
 static PROCESS_COUNT_CACHE: OnceLock<Mutex<ProcessCountCache>> = OnceLock::new();
 static STATS_CACHE: OnceLock<Mutex<StatsCache>> = OnceLock::new();
+static DISKS: OnceLock<Mutex<Disks>> = OnceLock::new();
+static NETWORKS: OnceLock<Mutex<NetworkSample>> = OnceLock::new();
+static CGROUP_CPU: Mutex<Option<CpuSample>> = Mutex::new(None);
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+// Pseudo and in-memory file systems that never fill a disk
+const IGNORED_FILE_SYSTEMS: &[&str] = &[
+    "tmpfs", "devtmpfs", "ramfs", "squashfs", "proc", "sysfs", "cgroup2", "nsfs", "autofs",
+    "efivarfs",
+];
 
 const SOCKETS_CACHE_TTL: Duration = Duration::from_secs(30);
 const PROCESS_COUNT_TTL: Duration = Duration::from_secs(60);
@@ -73,6 +133,17 @@ struct ProcessCountCache {
     total: usize,
 }
 
+struct NetworkSample {
+    networks: Networks,
+    last_refresh: Instant,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CpuSample {
+    usage_usec: u64,
+    at: Instant,
+}
+
 #[derive(Debug, Clone)]
 struct StatsCache {
     stats: Option<Stats>,
@@ -159,6 +230,9 @@ impl Stats {
         let host_mac = Self::mac();
 
         let host_processes = Self::process_count(&mut sys);
+        let disks = Self::disks();
+        let networks = Self::networks();
+        let cgroup = Self::cgroup();
 
         Some(Self {
             last_refreshed_secs: 0,
@@ -178,6 +252,116 @@ impl Stats {
             host_os_info,
             host_connections,
             host_processes,
+            disks,
+            networks,
+            cgroup,
+        })
+    }
+
+    fn disks() -> Vec<DiskStats> {
+        let disks = DISKS.get_or_init(|| Mutex::new(Disks::new_with_refreshed_list()));
+        let Ok(mut disks) = disks.lock() else {
+            return vec![];
+        };
+        disks.refresh(true);
+
+        let mut seen = HashSet::new();
+        disks
+            .list()
+            .iter()
+            .filter(|disk| disk.total_space() > 0)
+            .filter(|disk| {
+                let fs = disk.file_system().to_string_lossy();
+                !IGNORED_FILE_SYSTEMS.contains(&fs.as_ref())
+            })
+            .filter(|disk| seen.insert(disk.mount_point().to_path_buf()))
+            .map(|disk| DiskStats {
+                mount_point: disk.mount_point().to_string_lossy().into_owned(),
+                file_system: disk.file_system().to_string_lossy().into_owned(),
+                total_bytes: disk.total_space(),
+                available_bytes: disk.available_space(),
+            })
+            .collect()
+    }
+
+    fn networks() -> Vec<NetworkStats> {
+        let sample = NETWORKS.get_or_init(|| {
+            Mutex::new(NetworkSample {
+                networks: Networks::new_with_refreshed_list(),
+                last_refresh: Instant::now(),
+            })
+        });
+        let Ok(mut sample) = sample.lock() else {
+            return vec![];
+        };
+
+        let elapsed = sample.last_refresh.elapsed().as_secs_f64();
+        sample.networks.refresh(true);
+        sample.last_refresh = Instant::now();
+
+        // The first refresh right after the initial listing has no useful window
+        let rate = |bytes: u64| {
+            if elapsed < 1.0 {
+                0
+            } else {
+                (bytes as f64 / elapsed) as u64
+            }
+        };
+
+        let mut networks: Vec<_> = sample
+            .networks
+            .list()
+            .iter()
+            .filter(|(name, _)| name.as_str() != "lo")
+            .map(|(name, data)| NetworkStats {
+                interface: name.clone(),
+                rx_bytes_per_sec: rate(data.received()),
+                tx_bytes_per_sec: rate(data.transmitted()),
+                rx_total_bytes: data.total_received(),
+                tx_total_bytes: data.total_transmitted(),
+            })
+            .collect();
+        networks.sort_by(|a, b| a.interface.cmp(&b.interface));
+        networks
+    }
+
+    fn cgroup() -> Option<CgroupStats> {
+        let dir = cgroup_dir()?;
+        let read = |file: &str| std::fs::read_to_string(dir.join(file)).ok();
+
+        let cpu_limit_cores = read("cpu.max").as_deref().and_then(parse_cpu_max);
+        let memory_limit_bytes = read("memory.max").as_deref().and_then(parse_memory_max);
+        if cpu_limit_cores.is_none() && memory_limit_bytes.is_none() {
+            return None;
+        }
+
+        let memory_current = read("memory.current")
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+        let inactive_file = read("memory.stat")
+            .as_deref()
+            .and_then(|stat| stat_value(stat, "inactive_file"))
+            .unwrap_or(0);
+
+        let cpu_percent = cpu_limit_cores.and_then(|cores| {
+            let usage_usec = stat_value(&read("cpu.stat")?, "usage_usec")?;
+            let now = CpuSample {
+                usage_usec,
+                at: Instant::now(),
+            };
+            let mut last = CGROUP_CPU.lock().ok()?;
+            let previous = last.replace(now)?;
+
+            let window_usec = now.at.duration_since(previous.at).as_micros() as f64;
+            let used_usec = now.usage_usec.saturating_sub(previous.usage_usec) as f64;
+            (window_usec > 0.0).then(|| (used_usec * 100.0 / (window_usec * cores)) as f32)
+        });
+
+        Some(CgroupStats {
+            cpu_limit_cores,
+            cpu_percent,
+            memory_limit_bytes,
+            memory_used_bytes: memory_current.saturating_sub(inactive_file),
         })
     }
 
@@ -263,7 +447,7 @@ impl Stats {
     }
 
     pub fn log_line(&self) -> String {
-        format!(
+        let mut line = format!(
             "pid={} threads={} cpu={:.1}% mem={} uptime={} | host={} ip={} os={} cpu={:.1}% mem={}/{} procs={} host_conns={} load={:.2}/{:.2}/{:.2} uptime={}",
             self.proc_id,
             self.proc_threads,
@@ -282,7 +466,37 @@ impl Stats {
             self.host_load_average[1],
             self.host_load_average[2],
             format_duration(self.host_uptime_secs),
-        )
+        );
+
+        if let Some(disk) = self
+            .disks
+            .iter()
+            .max_by(|a, b| a.used_percent().total_cmp(&b.used_percent()))
+        {
+            line.push_str(&format!(
+                " fullest_disk={}:{:.0}%",
+                disk.mount_point,
+                disk.used_percent()
+            ));
+        }
+
+        if let Some(cgroup) = &self.cgroup {
+            if let Some(cores) = cgroup.cpu_limit_cores {
+                line.push_str(&format!(
+                    " | cgroup cpu={:.1}%/{cores:.2} cores",
+                    cgroup.cpu_percent.unwrap_or(0.0)
+                ));
+            }
+            if let Some(limit) = cgroup.memory_limit_bytes {
+                line.push_str(&format!(
+                    " | cgroup mem={}/{}",
+                    format_mem(cgroup.memory_used_bytes),
+                    format_mem(limit)
+                ));
+            }
+        }
+
+        line
     }
 
     pub fn to_json(&self) -> anyhow::Result<String> {
@@ -297,6 +511,48 @@ fn format_duration(secs: u64) -> String {
     format!("{hours:02}:{minutes:02}:{seconds:02}")
 }
 
+/// The process's own cgroup v2 directory, from the `0::` line of
+/// `/proc/self/cgroup`; `None` on cgroup v1 or non-linux hosts.
+fn cgroup_dir() -> Option<PathBuf> {
+    let cgroups = std::fs::read_to_string("/proc/self/cgroup").ok()?;
+    let relative = parse_cgroup_path(&cgroups)?;
+    let dir = Path::new(CGROUP_ROOT).join(relative.trim_start_matches('/'));
+
+    // Inside a private cgroup namespace the path may not be mounted as seen
+    dir.join("cgroup.controllers")
+        .exists()
+        .then_some(dir)
+        .or_else(|| {
+            let root = PathBuf::from(CGROUP_ROOT);
+            root.join("cgroup.controllers").exists().then_some(root)
+        })
+}
+
+fn parse_cgroup_path(cgroups: &str) -> Option<&str> {
+    cgroups.lines().find_map(|line| line.strip_prefix("0::"))
+}
+
+/// `cpu.max` is `<quota> <period>` in microseconds, or `max <period>`.
+fn parse_cpu_max(value: &str) -> Option<f64> {
+    let mut parts = value.split_whitespace();
+    let quota = parts.next()?.parse::<f64>().ok()?;
+    let period = parts.next().map_or(Some(100_000.0), |p| p.parse().ok())?;
+    (period > 0.0).then(|| quota / period)
+}
+
+/// `memory.max` is a byte count or `max`.
+fn parse_memory_max(value: &str) -> Option<u64> {
+    value.trim().parse().ok()
+}
+
+/// Looks up `key` in a flat keyed file such as `cpu.stat` or `memory.stat`.
+fn stat_value(stat: &str, key: &str) -> Option<u64> {
+    stat.lines().find_map(|line| {
+        let (name, value) = line.split_once(' ')?;
+        (name == key).then(|| value.trim().parse().ok()).flatten()
+    })
+}
+
 fn resolve_host_ip() -> String {
     let interfaces = get_if_addrs().ok();
     match interfaces {
@@ -317,3 +573,27 @@ fn resolve_host_ip() -> String {
         None => "unknown".into(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cgroup_v2_files() {
+        assert_eq!(
+            parse_cgroup_path("0::/system.slice/phirepass-agent.service\n"),
+            Some("/system.slice/phirepass-agent.service")
+        );
+        assert_eq!(parse_cgroup_path("12:cpu,cpuacct:/docker/abc\n"), None);
+
+        assert_eq!(parse_cpu_max("150000 100000\n"), Some(1.5));
+        assert_eq!(parse_cpu_max("max 100000\n"), None);
+
+        assert_eq!(parse_memory_max("536870912\n"), Some(536_870_912));
+        assert_eq!(parse_memory_max("max\n"), None);
+
+        let stat = "anon 1024\nfile 4096\ninactive_file 2048\n";
+        assert_eq!(stat_value(stat, "inactive_file"), Some(2048));
+        assert_eq!(stat_value(stat, "active_file"), None);
+    }
+}
@@ -28,6 +28,12 @@ pub fn extract_creds(token: String) -> anyhow::Result<(String, String)> {
     Ok((token_id.to_string(), secret.to_string()))
 }
 
+/// Message signed by both the current and the new node key during key
+/// rotation, binding the new public key to a single server challenge.
+pub fn key_rotation_message(challenge: &str, new_public_key: &str) -> String {
+    format!("phirepass-key-rotation:{}:{}", challenge, new_public_key)
+}
+
 pub fn mask_after_10(s: &str) -> String {
     let mut chars = s.chars();
     let first_10: String = chars.by_ref().take(10).collect();
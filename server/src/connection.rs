@@ -1,11 +1,13 @@
 use crate::db::common::NodeRecord;
 use phirepass_common::node::Node;
-use phirepass_common::protocol::node::NodeFrameData;
+use phirepass_common::protocol::node::{NodeFrameData, WebUser};
 use phirepass_common::protocol::web::WebFrameData;
 use serde::Serialize;
 use serde_json::json;
 use std::net::IpAddr;
+use std::sync::Arc;
 use std::time::SystemTime;
+use tokio::sync::Notify;
 use tokio::sync::mpsc::Sender;
 use uuid::Uuid;
 
@@ -15,10 +17,13 @@ pub(crate) struct WebConnection {
     pub(crate) last_heartbeat: SystemTime,
     pub(crate) ip: IpAddr,
     pub(crate) tx: Sender<WebFrameData>,
+    /// forwarded with every `OpenTunnel`; `None` when authentication is
+    /// bypassed in debug builds
+    pub(crate) user: Option<WebUser>,
 }
 
 impl WebConnection {
-    pub(crate) fn new(ip: IpAddr, tx: Sender<WebFrameData>) -> Self {
+    pub(crate) fn new(ip: IpAddr, tx: Sender<WebFrameData>, user: Option<WebUser>) -> Self {
         let now = SystemTime::now();
 
         Self {
@@ -26,6 +31,7 @@ impl WebConnection {
             last_heartbeat: now,
             ip,
             tx,
+            user,
         }
     }
 }
@@ -39,6 +45,9 @@ pub(crate) struct NodeConnection {
     pub(crate) node_record: NodeRecord,
     #[serde(skip_serializing)]
     pub(crate) server_id: Uuid,
+    /// Closes the node websocket, e.g. once the node has been revoked
+    #[serde(skip_serializing)]
+    pub(crate) shutdown: Arc<Notify>,
 }
 
 impl NodeConnection {
@@ -47,6 +56,7 @@ impl NodeConnection {
         ip: IpAddr,
         tx: Sender<NodeFrameData>,
         node_record: NodeRecord,
+        shutdown: Arc<Notify>,
     ) -> Self {
         let now = SystemTime::now();
 
@@ -56,10 +66,13 @@ impl NodeConnection {
                 last_heartbeat: now,
                 ip,
                 last_stats: None,
+                last_health: None,
+                last_usage: None,
             },
             tx,
             node_record,
             server_id,
+            shutdown,
         }
     }
 
@@ -1,4 +1,5 @@
 use chrono::{DateTime, Utc};
+use phirepass_common::labels::Labels;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use uuid::Uuid;
@@ -21,6 +22,8 @@ pub struct NodeRecord {
     pub user_id: Uuid,
     pub name: Option<String>,
     pub hostname: String,
+    #[serde(default)]
+    pub metadata: Value,
     pub created_at: DateTime<Utc>,
 }
 
@@ -28,6 +31,14 @@ impl NodeRecord {
     pub fn to_json(&self) -> anyhow::Result<String> {
         serde_json::to_string(self).map_err(|e| e.into())
     }
+
+    /// Labels the agent declared, kept under `labels` in the metadata.
+    pub fn labels(&self) -> Labels {
+        self.metadata
+            .get("labels")
+            .and_then(|labels| serde_json::from_value(labels.clone()).ok())
+            .unwrap_or_default()
+    }
 }
 
 #[derive(Debug, sqlx::FromRow)]
@@ -50,3 +61,18 @@ pub struct AuthChallengeRecord {
     pub challenge: String,
     pub expires_at: DateTime<Utc>,
 }
+
+/// Usage of one node by one ssh account, summed over `session_usage`.
+#[derive(Debug, sqlx::FromRow, Serialize)]
+pub struct UsageRecord {
+    pub node_id: Uuid,
+    pub user_id: Uuid,
+    pub username: String,
+    pub sessions: i64,
+    pub bytes_in: i64,
+    pub bytes_out: i64,
+    pub uploads: i64,
+    pub downloads: i64,
+    pub first_opened_at: Option<DateTime<Utc>>,
+    pub last_closed_at: Option<DateTime<Utc>>,
+}
@@ -2,9 +2,12 @@ use crate::db::common::AuthChallengeRecord;
 use crate::db::common::NodeClaimRecord;
 use crate::db::common::NodeRecord;
 use crate::db::common::TokenRecord;
+use crate::db::common::UsageRecord;
 use crate::env::Env;
 use anyhow::Context;
 use argon2::Argon2;
+use chrono::{DateTime, Utc};
+use phirepass_common::protocol::node::SessionUsage;
 use serde_json::Value;
 use sqlx::PgPool;
 use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
@@ -37,7 +40,7 @@ impl Database {
     pub async fn get_node_by_id(&self, node_id: &Uuid) -> anyhow::Result<NodeRecord> {
         let node_record = sqlx::query_as::<_, NodeRecord>(
             r#"
-            SELECT id, user_id, name, hostname, created_at
+            SELECT id, user_id, name, hostname, metadata, created_at
             FROM nodes
             WHERE id = $1
             "#,
@@ -87,6 +90,23 @@ impl Database {
         Ok(exists)
     }
 
+    pub async fn get_user_roles(&self, user_id: &Uuid) -> anyhow::Result<Vec<String>> {
+        let roles = sqlx::query_scalar::<_, Vec<String>>(
+            r#"
+            SELECT roles
+            FROM users
+            WHERE id = $1
+            "#,
+        )
+        .persistent(false)
+        .bind(user_id)
+        .fetch_one(&self.pool)
+        .await
+        .context("failed to fetch user roles")?;
+
+        Ok(roles)
+    }
+
     pub async fn get_node_by_public_key(
         &self,
         public_key: &str,
@@ -244,6 +264,86 @@ impl Database {
         Ok(())
     }
 
+    /// Swaps the node key only if it still matches the one the rotation was
+    /// verified against, so concurrent rotations cannot both succeed.
+    pub async fn rotate_node_public_key(
+        &self,
+        node_id: &Uuid,
+        old_public_key: &str,
+        new_public_key: &str,
+    ) -> anyhow::Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE nodes
+            SET public_key = $3
+            WHERE id = $1 AND public_key = $2 AND revoked = FALSE
+            "#,
+        )
+        .persistent(false)
+        .bind(node_id)
+        .bind(old_public_key)
+        .bind(new_public_key)
+        .execute(&self.pool)
+        .await
+        .context("failed to rotate node public key")?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    pub async fn revoke_node(&self, node_id: &Uuid) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE nodes
+            SET revoked = TRUE
+            WHERE id = $1
+            "#,
+        )
+        .persistent(false)
+        .bind(node_id)
+        .execute(&self.pool)
+        .await
+        .context("failed to revoke node")?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM auth_challenges
+            WHERE node_id = $1
+            "#,
+        )
+        .persistent(false)
+        .bind(node_id)
+        .execute(&self.pool)
+        .await
+        .context("failed to delete auth challenges of revoked node")?;
+
+        Ok(())
+    }
+
+    /// Sets the top-level keys of `patch` in the node metadata, replacing
+    /// each whole. Returns the new metadata, or `None` when nothing changed.
+    pub async fn update_node_metadata(
+        &self,
+        node_id: &Uuid,
+        patch: &Value,
+    ) -> anyhow::Result<Option<Value>> {
+        let metadata = sqlx::query_scalar::<_, Value>(
+            r#"
+            UPDATE nodes
+            SET metadata = metadata || $2
+            WHERE id = $1 AND metadata IS DISTINCT FROM metadata || $2
+            RETURNING metadata
+            "#,
+        )
+        .persistent(false)
+        .bind(node_id)
+        .bind(patch)
+        .fetch_optional(&self.pool)
+        .await
+        .context("failed to update node metadata")?;
+
+        Ok(metadata)
+    }
+
     pub async fn touch_node_last_seen(&self, node_id: &Uuid) -> anyhow::Result<()> {
         sqlx::query(
             r#"
@@ -260,4 +360,133 @@ impl Database {
 
         Ok(())
     }
+
+    /// Stores a closed tunnel as one row of its protocol (`ssh` or `sftp`)
+    /// plus one row per upload and download it carried.
+    pub async fn insert_session_usage(
+        &self,
+        node_id: &Uuid,
+        user_id: &Uuid,
+        kind: &str,
+        usage: &SessionUsage,
+    ) -> anyhow::Result<()> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .context("failed to begin session usage transaction")?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO session_usage
+                (node_id, user_id, kind, username, bytes_in, bytes_out, completed, opened_at, closed_at)
+            VALUES ($1, $2, $3, $4, $5, $6, TRUE, $7, $8)
+            "#,
+        )
+        .persistent(false)
+        .bind(node_id)
+        .bind(user_id)
+        .bind(kind)
+        .bind(&usage.username)
+        .bind(usage.bytes_in as i64)
+        .bind(usage.bytes_out as i64)
+        .bind(timestamp(usage.opened_at))
+        .bind(timestamp(usage.closed_at))
+        .execute(&mut *tx)
+        .await
+        .context("failed to insert session usage")?;
+
+        for transfer in &usage.transfers {
+            let (kind, bytes_in, bytes_out) = if transfer.upload {
+                ("upload", transfer.bytes, 0)
+            } else {
+                ("download", 0, transfer.bytes)
+            };
+
+            sqlx::query(
+                r#"
+                INSERT INTO session_usage
+                    (node_id, user_id, kind, username, path, bytes_in, bytes_out, completed, opened_at, closed_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                "#,
+            )
+            .persistent(false)
+            .bind(node_id)
+            .bind(user_id)
+            .bind(kind)
+            .bind(&usage.username)
+            .bind(&transfer.path)
+            .bind(bytes_in as i64)
+            .bind(bytes_out as i64)
+            .bind(transfer.completed)
+            .bind(timestamp(transfer.started_at))
+            .bind(timestamp(transfer.finished_at))
+            .execute(&mut *tx)
+            .await
+            .context("failed to insert transfer usage")?;
+        }
+
+        tx.commit()
+            .await
+            .context("failed to commit session usage")?;
+
+        Ok(())
+    }
+
+    /// Sums stored usage per node and ssh account. Every filter is optional;
+    /// `since` and `until` bound the time a session closed.
+    /// Usage summed per node and ssh account; `owner_id` limits it to the
+    /// nodes of that user.
+    pub async fn get_usage(
+        &self,
+        owner_id: Option<Uuid>,
+        user_id: Option<Uuid>,
+        node_id: Option<Uuid>,
+        username: Option<&str>,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Vec<UsageRecord>> {
+        // transfer bytes are part of their session's totals already, so only
+        // the session rows are summed
+        let records = sqlx::query_as::<_, UsageRecord>(
+            r#"
+            SELECT
+                node_id,
+                user_id,
+                username,
+                COUNT(*) FILTER (WHERE kind IN ('ssh', 'sftp')) AS sessions,
+                COALESCE(SUM(bytes_in) FILTER (WHERE kind IN ('ssh', 'sftp')), 0)::BIGINT AS bytes_in,
+                COALESCE(SUM(bytes_out) FILTER (WHERE kind IN ('ssh', 'sftp')), 0)::BIGINT AS bytes_out,
+                COUNT(*) FILTER (WHERE kind = 'upload' AND completed) AS uploads,
+                COUNT(*) FILTER (WHERE kind = 'download' AND completed) AS downloads,
+                MIN(opened_at) AS first_opened_at,
+                MAX(closed_at) AS last_closed_at
+            FROM session_usage
+            WHERE ($1::uuid IS NULL OR user_id = $1)
+              AND ($2::uuid IS NULL OR node_id = $2)
+              AND ($3::text IS NULL OR username = $3)
+              AND ($4::timestamptz IS NULL OR closed_at >= $4)
+              AND ($5::timestamptz IS NULL OR closed_at < $5)
+              AND ($6::uuid IS NULL OR node_id IN (SELECT id FROM nodes WHERE user_id = $6))
+            GROUP BY node_id, user_id, username
+            ORDER BY node_id, username
+            "#,
+        )
+        .persistent(false)
+        .bind(user_id)
+        .bind(node_id)
+        .bind(username)
+        .bind(since)
+        .bind(until)
+        .bind(owner_id)
+        .fetch_all(&self.pool)
+        .await
+        .context("failed to retrieve usage")?;
+
+        Ok(records)
+    }
+}
+
+fn timestamp(millis: u64) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(millis as i64).unwrap_or_default()
 }
@@ -1,7 +1,8 @@
 use crate::db::common::NodeRecord;
 use crate::env::Env;
 use anyhow::Context;
-use log::debug;
+use futures_util::{Stream, StreamExt};
+use log::{debug, warn};
 use phirepass_common::server::ServerIdentifier;
 use redis::AsyncCommands;
 use redis::aio::ConnectionManager;
@@ -10,10 +11,16 @@ use std::net::IpAddr;
 use std::sync::Arc;
 use uuid::Uuid;
 
+// Every server instance listens here, so a revoke closes the node's
+// websocket whichever instance it is connected to
+const NODE_REVOKED_CHANNEL: &str = "phirepass:nodes:revoked";
+
 pub struct MemoryDB {
     // ConnectionManager is Arc-internally inexpensive to clone and is the recommended way
     // to share it across concurrent callers when the struct is behind Arc<MemoryDB>.
     manager: ConnectionManager,
+    // pub/sub needs a dedicated connection
+    client: redis::Client,
 }
 
 impl MemoryDB {
@@ -21,11 +28,11 @@ impl MemoryDB {
         let client = redis::Client::open(config.redis_database_url.clone())
             .context("failed to create redis client")?;
 
-        let manager = ConnectionManager::new(client)
+        let manager = ConnectionManager::new(client.clone())
             .await
             .context("failed to create redis connection manager")?;
 
-        Ok(Self { manager })
+        Ok(Self { manager, client })
     }
 
     pub async fn set_node_connected(
@@ -33,7 +40,7 @@ impl MemoryDB {
         node: &NodeRecord,
         server: &Arc<ServerIdentifier>,
     ) -> anyhow::Result<()> {
-        self.update_node_stats(node, server, String::from(""))
+        self.update_node_stats(node, server, String::from(""), String::from(""))
             .await
             .context("failed to set node connected by updating node stats")
     }
@@ -59,6 +66,7 @@ impl MemoryDB {
         node: &NodeRecord,
         server: &Arc<ServerIdentifier>,
         stats_payload: String,
+        health_payload: String,
     ) -> anyhow::Result<()> {
         let node_payload = node.to_json()?;
         let server_payload = server.get_encoded()?;
@@ -67,6 +75,7 @@ impl MemoryDB {
         let fields_values = [
             ("node", node_payload.as_str()),
             ("stats", stats_payload.as_str()),
+            ("health", health_payload.as_str()),
             ("server", server_payload.as_str()),
         ];
 
@@ -87,6 +96,37 @@ impl MemoryDB {
         Ok(())
     }
 
+    pub async fn publish_node_revoked(&self, node_id: &Uuid) -> anyhow::Result<()> {
+        let mut conn = self.manager.clone();
+        let _: i64 = conn
+            .publish(NODE_REVOKED_CHANNEL, node_id.to_string())
+            .await?;
+
+        Ok(())
+    }
+
+    /// Ids of revoked nodes as they are published. The stream ends when the
+    /// subscription connection drops.
+    pub async fn subscribe_node_revoked(&self) -> anyhow::Result<impl Stream<Item = Uuid>> {
+        let mut pubsub = self
+            .client
+            .get_async_pubsub()
+            .await
+            .context("failed to open redis pubsub connection")?;
+        pubsub.subscribe(NODE_REVOKED_CHANNEL).await?;
+
+        Ok(pubsub.into_on_message().filter_map(|msg| async move {
+            let payload: String = msg.get_payload().ok()?;
+            match Uuid::parse_str(&payload) {
+                Ok(node_id) => Some(node_id),
+                Err(err) => {
+                    warn!("ignoring invalid revoked node id {payload}: {err}");
+                    None
+                }
+            }
+        }))
+    }
+
     pub async fn set_connection_connected(
         &self,
         cid: &Uuid,
@@ -39,6 +39,10 @@ pub(crate) struct Env {
 
     #[envconfig(from = "CHALLENGE_TTL_SECS", default = "60")]
     pub challenge_ttl_secs: i64,
+
+    // how long connected agents get to drain their sessions on shutdown
+    #[envconfig(from = "DRAIN_TIMEOUT", default = "60")]
+    pub drain_timeout_secs: u64,
 }
 
 pub fn init() -> anyhow::Result<Env> {
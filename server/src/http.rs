@@ -1,16 +1,22 @@
 use crate::connection::{NodeConnection, WebConnection};
 use crate::db::postgres::Database;
 use crate::db::redis::MemoryDB;
+use crate::env;
 use crate::env::Env;
 use crate::error::ServerError;
+use axum::Extension;
 use axum::Json;
-use axum::extract::State;
+use axum::extract::{Query, State};
 use axum::http::{HeaderValue, Method, StatusCode};
 use axum::response::IntoResponse;
+use chrono::{DateTime, Utc};
 use dashmap::DashMap;
-use log::debug;
+use log::{debug, warn};
+use phirepass_common::labels::Selector;
+use phirepass_common::protocol::node::WebUser;
 use phirepass_common::protocol::web::WebFrameData;
 use phirepass_common::server::ServerIdentifier;
+use serde::Deserialize;
 use serde_json::json;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -38,8 +44,15 @@ pub type Connections = Arc<DashMap<Uuid, WebConnection>>;
 
 pub type TunnelSessions = Arc<DashMap<TunnelSessionKey, (Uuid, Uuid)>>;
 
+/// Phirepass user who opened each tunnel, for its usage record
+pub type TunnelUsers = Arc<DashMap<TunnelSessionKey, Uuid>>;
+
 pub static READY: AtomicBool = AtomicBool::new(false);
 
+/// Set once the server drains its nodes for a shutdown; node websockets are
+/// refused from then on so drained agents reconnect elsewhere.
+pub static DRAINING: AtomicBool = AtomicBool::new(false);
+
 #[derive(Clone)]
 pub(crate) struct AppState {
     pub(crate) id: Arc<Uuid>,
@@ -50,6 +63,7 @@ pub(crate) struct AppState {
     pub(crate) nodes: Nodes,
     pub(crate) connections: Connections,
     pub(crate) tunnel_sessions: TunnelSessions,
+    pub(crate) tunnel_users: TunnelUsers,
 }
 
 impl AppState {
@@ -154,17 +168,47 @@ pub fn build_cors(state: &AppState) -> CorsLayer {
     cors
 }
 
-pub async fn list_nodes(State(state): State<AppState>) -> impl IntoResponse {
+#[derive(Debug, Deserialize)]
+pub struct ListNodesQuery {
+    /// Label selector, e.g. `environment=prod,team in (payments,search)`
+    selector: Option<String>,
+}
+
+pub async fn list_nodes(
+    State(state): State<AppState>,
+    Query(query): Query<ListNodesQuery>,
+) -> impl IntoResponse {
     let now = SystemTime::now();
 
+    let selector = match query.selector.as_deref().map(Selector::parse).transpose() {
+        Ok(selector) => selector.unwrap_or_default(),
+        Err(err) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "success": false,
+                    "code": "INVALID_SELECTOR",
+                    "error": err,
+                })),
+            )
+                .into_response();
+        }
+    };
+
     let data: Vec<_> = state
         .nodes
         .iter()
-        .map(|entry| {
+        .filter_map(|entry| {
             let (id, info) = entry.pair();
-            json!({
+            let labels = info.node_record.labels();
+            if !selector.matches(&labels) {
+                return None;
+            }
+
+            Some(json!({
                 "id": id,
                 "name": info.node_record.hostname,
+                "labels": labels,
                 "ip": info.node.ip,
                 "server_id": info.server_id,
                 "connected_for_secs": now
@@ -176,11 +220,13 @@ pub async fn list_nodes(State(state): State<AppState>) -> impl IntoResponse {
                     .unwrap_or_default()
                     .as_secs(),
                 "stats": &info.node.last_stats,
-            })
+                "health": &info.node.last_health,
+                "usage": &info.node.last_usage,
+            }))
         })
         .collect();
 
-    Json(data)
+    Json(data).into_response()
 }
 
 pub async fn list_connections(State(state): State<AppState>) -> impl IntoResponse {
@@ -209,6 +255,54 @@ pub async fn list_connections(State(state): State<AppState>) -> impl IntoRespons
     Json(data)
 }
 
+#[derive(Debug, Deserialize)]
+pub struct UsageQuery {
+    user_id: Option<Uuid>,
+    node_id: Option<Uuid>,
+    /// ssh account on the node
+    username: Option<String>,
+    /// RFC 3339; sessions that closed at or after this time
+    since: Option<DateTime<Utc>>,
+    /// RFC 3339; sessions that closed before this time
+    until: Option<DateTime<Utc>>,
+}
+
+pub async fn get_usage(
+    State(state): State<AppState>,
+    Extension(user): Extension<WebUser>,
+    Query(query): Query<UsageQuery>,
+) -> impl IntoResponse {
+    // Only admins see the usage of nodes they do not own
+    let owner_id = (!user.roles.iter().any(|role| role == "admin")).then_some(user.user_id);
+
+    match state
+        .db
+        .get_usage(
+            owner_id,
+            query.user_id,
+            query.node_id,
+            query.username.as_deref(),
+            query.since,
+            query.until,
+        )
+        .await
+    {
+        Ok(records) => Json(records).into_response(),
+        Err(err) => {
+            warn!("failed to query usage: {err:?}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "success": false,
+                    "code": "USAGE_UNAVAILABLE",
+                    "error": "failed to query usage",
+                })),
+            )
+                .into_response()
+        }
+    }
+}
+
 pub async fn readiness() -> impl IntoResponse {
     if READY.load(Ordering::Acquire) {
         StatusCode::OK
@@ -217,6 +311,12 @@ pub async fn readiness() -> impl IntoResponse {
     }
 }
 
+pub async fn get_version() -> impl IntoResponse {
+    Json(json!({
+        "version": env::version(),
+    }))
+}
+
 pub async fn healthz() -> impl IntoResponse {
     StatusCode::OK
 }
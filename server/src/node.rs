@@ -2,7 +2,7 @@ use crate::connection::NodeConnection;
 use crate::db::common::TokenRecord;
 use crate::db::postgres::Database;
 use crate::env;
-use crate::http::AppState;
+use crate::http::{AppState, DRAINING, READY};
 use crate::node_auth::authenticate_node_jwt;
 use argon2::{PasswordHash, PasswordVerifier};
 use axum::Json;
@@ -16,8 +16,12 @@ use chrono::Utc;
 use futures_util::{SinkExt, StreamExt};
 use log::{debug, info, warn};
 use phirepass_common::ip::resolve_client_ip;
-use phirepass_common::protocol::common::{Frame, FrameData};
-use phirepass_common::protocol::node::{NodeFrameData, WebFrameId};
+use phirepass_common::labels::{self, Labels};
+use phirepass_common::protocol::Protocol;
+use phirepass_common::protocol::common::{CloseReason, Frame, FrameData};
+use phirepass_common::protocol::node::{
+    NodeFrameData, SessionUsage, TargetHealth, UsageTotals, WebFrameId,
+};
 use phirepass_common::protocol::web::WebFrameData;
 use phirepass_common::stats::Stats;
 use phirepass_common::time::now_millis;
@@ -27,10 +31,14 @@ use serde_json::Value;
 use serde_json::json;
 use std::net::IpAddr;
 use std::sync::Arc;
-use std::time::SystemTime;
-use tokio::sync::mpsc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, SystemTime};
+use tokio::sync::{Notify, mpsc};
 use uuid::Uuid;
 
+// Upper bound for the labels and metadata a node may store
+const MAX_METADATA_BYTES: usize = 16 * 1024;
+
 pub(crate) async fn ws_node_handler(
     State(state): State<AppState>,
     ClientIp(client_ip): ClientIp,
@@ -39,6 +47,11 @@ pub(crate) async fn ws_node_handler(
 ) -> impl IntoResponse {
     let ip = resolve_client_ip(&headers, client_ip);
 
+    if DRAINING.load(Ordering::Acquire) {
+        debug!("refusing node websocket from {ip}: draining");
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+
     debug!("NODE websocket headers {:?}", headers);
     debug!("NODE Client IP {:?}", ip);
     for (name, value) in headers.iter() {
@@ -46,14 +59,56 @@ pub(crate) async fn ws_node_handler(
     }
 
     ws.on_upgrade(move |socket| handle_node_socket(socket, state, ip))
+        .into_response()
 }
 
+/// Asks every connected node to drain and returns once they have all
+/// disconnected or `timeout` has passed.
+pub(crate) async fn drain_nodes(state: &AppState, timeout: Duration) {
+    // a little longer than the agents get, for their closing frames
+    const CLOSE_WAIT: Duration = Duration::from_secs(5);
+
+    DRAINING.store(true, Ordering::Release);
+    READY.store(false, Ordering::Release);
+
+    let nodes: Vec<_> = state
+        .nodes
+        .iter()
+        .map(|entry| (*entry.key(), entry.value().tx.clone()))
+        .collect();
+    info!("draining {} node(s) within {timeout:?}", nodes.len());
+
+    for (node_id, tx) in nodes {
+        let frame = NodeFrameData::Drain {
+            reason: "server maintenance".to_string(),
+            timeout_secs: timeout.as_secs() as u32,
+        };
+        if tx.send(frame).await.is_err() {
+            debug!("node {node_id} went away before it could be drained");
+        }
+    }
+
+    let deadline = tokio::time::Instant::now() + timeout + CLOSE_WAIT;
+    while !state.nodes.is_empty() {
+        if tokio::time::Instant::now() >= deadline {
+            warn!(
+                "{} node(s) still connected after the drain",
+                state.nodes.len()
+            );
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+}
+
+/// Returns the authenticated node id and whether the connection is only a
+/// `doctor` probe.
 async fn wait_for_auth(
     ws_rx: &mut futures_util::stream::SplitStream<WebSocket>,
     tx: &mpsc::Sender<NodeFrameData>,
     state: &AppState,
     ip: IpAddr,
-) -> anyhow::Result<Uuid> {
+) -> anyhow::Result<(Uuid, bool)> {
     let msg = ws_rx
         .next()
         .await
@@ -83,6 +138,7 @@ async fn wait_for_auth(
             token,
             node_id,
             version: _,
+            probe,
         } => {
             info!("auth request received for node {node_id}");
 
@@ -137,7 +193,7 @@ async fn wait_for_auth(
                     .await
                     .map_err(|err| anyhow::anyhow!("failed to send auth response: {err}"))?;
 
-                    Ok(node_id)
+                    Ok((node_id, probe))
                 }
                 Ok(Some(_)) => {
                     warn!(
@@ -197,7 +253,22 @@ async fn handle_node_socket(socket: WebSocket, state: AppState, ip: IpAddr) {
     let (tx, mut rx) = mpsc::channel::<NodeFrameData>(256);
 
     let node_id = match wait_for_auth(&mut ws_rx, &tx, &state, ip).await {
-        Ok(node_id) => node_id,
+        Ok((node_id, false)) => node_id,
+        Ok((node_id, true)) => {
+            // Registering a probe would replace the node's live connection
+            info!("node {node_id} ({ip}) authenticated a probe connection");
+            if let Ok(node_frame) = rx.try_recv() {
+                let frame: Frame = node_frame.into();
+                match frame.to_bytes() {
+                    Ok(frame) => {
+                        let _ = ws_tx.send(Message::Binary(frame.into())).await;
+                    }
+                    Err(err) => warn!("failed to encode probe auth response: {err}"),
+                }
+            }
+            let _ = ws_tx.close().await;
+            return;
+        }
         Err(err) => {
             warn!("authentication failed from {ip}: {err}");
             let _ = ws_tx.close().await;
@@ -222,11 +293,13 @@ async fn handle_node_socket(socket: WebSocket, state: AppState, ip: IpAddr) {
         warn!("failed to update node {node_id} as connected in postgres: {err}");
     }
 
+    let shutdown = Arc::new(Notify::new());
+
     {
         let server_id = *state.id.as_ref();
         state.nodes.insert(
             node_id,
-            NodeConnection::new(server_id, ip, tx.clone(), node_record),
+            NodeConnection::new(server_id, ip, tx.clone(), node_record, shutdown.clone()),
         );
         let total = state.nodes.len();
         info!("node {node_id} ({ip}) authenticated and registered (total: {total})");
@@ -251,7 +324,10 @@ async fn handle_node_socket(socket: WebSocket, state: AppState, ip: IpAddr) {
     });
 
     // Handle messages in a separate function to ensure cleanup always happens
-    handle_node_messages(&mut ws_rx, &state, node_id).await;
+    tokio::select! {
+        _ = handle_node_messages(&mut ws_rx, &state, node_id) => {}
+        _ = shutdown.notified() => info!("node {node_id} disconnected by server"),
+    }
 
     // Always abort a write task regardless of how we exited the message loop
     drop(tx); // Close sender first to wake a write task
@@ -299,14 +375,23 @@ async fn handle_node_messages(
                 };
 
                 match node_frame {
-                    NodeFrameData::Heartbeat { stats, sent_at } => {
+                    NodeFrameData::Heartbeat {
+                        stats,
+                        health,
+                        sent_at,
+                        usage,
+                    } => {
                         if let Err(err) =
-                            handle_node_heartbeat(state, &node_id, stats, sent_at).await
+                            handle_node_heartbeat(state, &node_id, *stats, health, usage, sent_at)
+                                .await
                         {
                             warn!("failed to update node heartbeat: {err}");
                             break; // cleanup handled by caller
                         }
                     }
+                    NodeFrameData::NodeMetadata { labels, metadata } => {
+                        handle_node_metadata(state, &node_id, labels, metadata).await;
+                    }
                     NodeFrameData::Auth { .. } => {
                         warn!(
                             "received [Auth] message after initial authentication from node {node_id}"
@@ -331,8 +416,13 @@ async fn handle_node_messages(
                         cid,
                         sid,
                         msg_id,
+                        usage,
+                        reason,
                     } => {
-                        handle_tunnel_closed(state, protocol, cid, sid, &node_id, msg_id).await;
+                        handle_tunnel_closed(
+                            state, protocol, cid, sid, &node_id, msg_id, usage, reason,
+                        )
+                        .await;
                     }
                     o => warn!("unhandled node frame: {o:?}"),
                 }
@@ -369,6 +459,7 @@ async fn handle_frame_response(state: &AppState, node_frame: NodeFrameData, node
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_tunnel_closed(
     state: &AppState,
     protocol: u8,
@@ -376,11 +467,14 @@ async fn handle_tunnel_closed(
     sid: u32,
     node_id: &Uuid,
     msg_id: Option<u32>,
+    usage: Option<SessionUsage>,
+    reason: Option<CloseReason>,
 ) {
     debug!("handling tunnel closed for connection {cid} with session {sid}");
 
     let key = crate::http::TunnelSessionKey::new(*node_id, sid);
     state.tunnel_sessions.remove(&key);
+    let user_id = state.tunnel_users.remove(&key).map(|(_, user_id)| user_id);
 
     if state
         .notify_client_by_cid(
@@ -389,6 +483,7 @@ async fn handle_tunnel_closed(
                 protocol,
                 sid,
                 msg_id,
+                reason,
             },
         )
         .await
@@ -397,6 +492,53 @@ async fn handle_tunnel_closed(
         info!("tunnel closed notification sent to web client {cid}")
         // Error already logged in notify_client_by_cid
     }
+
+    // agents that predate usage reporting send none
+    if let Some(usage) = usage {
+        store_session_usage(state, node_id, user_id, protocol, sid, &usage).await;
+    }
+}
+
+async fn store_session_usage(
+    state: &AppState,
+    node_id: &Uuid,
+    user_id: Option<Uuid>,
+    protocol: u8,
+    sid: u32,
+    usage: &SessionUsage,
+) {
+    let kind = match Protocol::try_from(protocol) {
+        Ok(Protocol::SSH) => "ssh",
+        Ok(Protocol::SFTP) => "sftp",
+        Err(_) => {
+            warn!("ignoring usage of session {sid} with unknown protocol {protocol}");
+            return;
+        }
+    };
+
+    // Tunnels opened without an authenticated user (debug builds) are
+    // attributed to the node owner
+    let Some(user_id) = user_id.or_else(|| {
+        state
+            .nodes
+            .get(node_id)
+            .map(|info| info.node_record.user_id)
+    }) else {
+        warn!("ignoring usage of session {sid}: node {node_id} not found");
+        return;
+    };
+
+    match state
+        .db
+        .insert_session_usage(node_id, &user_id, kind, usage)
+        .await
+    {
+        Ok(()) => info!(
+            "stored usage of {kind} session {sid} on node {node_id}: {} bytes in, {} bytes out",
+            usage.bytes_in, usage.bytes_out
+        ),
+        Err(err) => warn!("failed to store usage of session {sid} on node {node_id}: {err}"),
+    }
 }
 
 async fn handle_tunnel_opened(
@@ -411,6 +553,13 @@ async fn handle_tunnel_opened(
 
     let key = crate::http::TunnelSessionKey::new(*node_id, sid);
     state.tunnel_sessions.insert(key, (cid, *node_id));
+    if let Some(user_id) = state
+        .connections
+        .get(&cid)
+        .and_then(|conn| conn.user.as_ref().map(|user| user.user_id))
+    {
+        state.tunnel_users.insert(key, user_id);
+    }
 
     if state
         .notify_client_by_cid(
@@ -463,6 +612,7 @@ async fn notify_all_clients_for_closed_tunnel(state: &AppState, id: &Uuid) -> u3
 
     for (key, (cid, _)) in sessions_to_close {
         state.tunnel_sessions.remove(&key);
+        state.tunnel_users.remove(&key);
 
         if state
             .notify_client_by_cid(
@@ -471,6 +621,7 @@ async fn notify_all_clients_for_closed_tunnel(state: &AppState, id: &Uuid) -> u3
                     protocol: 0,
                     sid: key.sid,
                     msg_id: None,
+                    reason: None,
                 },
             )
             .await
@@ -484,10 +635,44 @@ async fn notify_all_clients_for_closed_tunnel(state: &AppState, id: &Uuid) -> u3
     count
 }
 
+/// Persists the labels and metadata the agent declares on connect under
+/// `labels` and `agent` in `nodes.metadata`, replacing both whole so keys
+/// the agent no longer declares do not linger.
+async fn handle_node_metadata(state: &AppState, node_id: &Uuid, labels: Labels, metadata: Value) {
+    if let Err(err) = labels::validate(&labels) {
+        warn!("ignoring metadata from node {node_id}: {err}");
+        return;
+    }
+
+    if !metadata.is_object() {
+        warn!("ignoring metadata from node {node_id}: not a JSON object");
+        return;
+    }
+
+    let patch = json!({ "labels": labels, "agent": metadata });
+    if patch.to_string().len() > MAX_METADATA_BYTES {
+        warn!("ignoring metadata from node {node_id}: larger than {MAX_METADATA_BYTES} bytes");
+        return;
+    }
+
+    match state.db.update_node_metadata(node_id, &patch).await {
+        Ok(Some(metadata)) => {
+            info!("node {node_id} metadata updated");
+            if let Some(mut info) = state.nodes.get_mut(node_id) {
+                info.node_record.metadata = metadata;
+            }
+        }
+        Ok(None) => debug!("node {node_id} metadata unchanged"),
+        Err(err) => warn!("failed to update metadata of node {node_id}: {err}"),
+    }
+}
+
 async fn handle_node_heartbeat(
     state: &AppState,
     node_id: &Uuid,
     stats: Stats,
+    health: Option<TargetHealth>,
+    usage: Option<UsageTotals>,
     sent_at: u64,
 ) -> anyhow::Result<()> {
     let mut info = match state.nodes.get_mut(node_id) {
@@ -511,9 +696,28 @@ async fn handle_node_heartbeat(
         return Ok(());
     };
 
+    if let Some(health) = health.as_ref().filter(|health| !health.reachable) {
+        warn!(
+            "node {node_id} cannot reach its ssh target {}:{}",
+            health.host, health.port
+        );
+    }
+
+    let Ok(health_payload) = serde_json::to_string(&health) else {
+        warn!("failed to encode target health");
+        return Ok(());
+    };
+    info.node.last_health = health;
+    info.node.last_usage = usage;
+
     if let Err(err) = state
         .memory_db
-        .update_node_stats(&info.node_record, &state.server, extended_stats)
+        .update_node_stats(
+            &info.node_record,
+            &state.server,
+            extended_stats,
+            health_payload,
+        )
         .await
     {
         warn!("failed to update node stats for node {node_id}: {err}");
@@ -595,12 +799,12 @@ fn extract_bearer_token(headers: &HeaderMap) -> anyhow::Result<String> {
     Ok(token.to_string())
 }
 
-enum CredentialValidationError {
+pub(crate) enum CredentialValidationError {
     Unauthorized(String),
     Internal(String),
 }
 
-async fn validate_creds(
+pub(crate) async fn validate_creds(
     db: Arc<Database>,
     token_id: String,
     token_secret: String,
@@ -11,6 +11,8 @@ use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use chrono::{Duration, Utc};
 use ed25519_dalek::{Signature, Verifier, VerifyingKey};
 use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use log::{info, warn};
+use phirepass_common::token::key_rotation_message;
 use rand::RngCore;
 use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
@@ -40,6 +42,24 @@ pub struct VerifyResponse {
     pub expires_at: chrono::DateTime<Utc>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct RotateKeyRequest {
+    pub node_id: Uuid,
+    pub challenge: String,
+    pub new_public_key: String,
+    /// Signature of the rotation message by the current key
+    pub signature: String,
+    /// Signature of the rotation message by the new key
+    pub new_signature: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RotateKeyResponse {
+    pub success: bool,
+    pub node_id: Uuid,
+    pub public_key: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct NodeJwtClaims {
     node_id: Uuid,
@@ -130,21 +150,34 @@ pub async fn verify_auth_challenge(
         return unauthorized_verify();
     }
 
-    let challenge_record = match state
-        .db
-        .get_auth_challenge(&payload.node_id, payload.challenge.trim())
-        .await
+    if let Err(response) = take_challenge(
+        &state,
+        &payload.node_id,
+        payload.challenge.trim(),
+        unauthorized_verify,
+    )
+    .await
     {
-        Ok(Some(record)) => record,
-        Ok(None) => {
-            return unauthorized_verify();
-        }
+        return response;
+    }
+
+    if let Err(err) = verify_signature(
+        node.public_key.as_str(),
+        payload.challenge.trim(),
+        payload.signature.trim(),
+    ) {
+        let _ = err;
+        return unauthorized_verify();
+    }
+
+    let (access_token, expires_at) = match issue_node_jwt(&state.env, payload.node_id) {
+        Ok(result) => result,
         Err(err) => {
             return (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(json!({
                     "success": false,
-                    "code": "AUTH_CHALLENGE_LOOKUP_FAILED",
+                    "code": "AUTH_JWT_ISSUE_FAILED",
                     "error": err.to_string()
                 })),
             )
@@ -152,56 +185,132 @@ pub async fn verify_auth_challenge(
         }
     };
 
-    // Challenges are one-time: consume before cryptographic verification to limit replay attempts.
-    if let Err(err) = state
-        .db
-        .consume_auth_challenge(&payload.node_id, payload.challenge.trim())
-        .await
-    {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
+    (
+        StatusCode::OK,
+        Json(json!(VerifyResponse {
+            access_token,
+            expires_at,
+        })),
+    )
+        .into_response()
+}
+
+pub async fn rotate_node_key(
+    State(state): State<AppState>,
+    Json(payload): Json<RotateKeyRequest>,
+) -> Response {
+    let unauthorized_rotate = || {
+        (
+            StatusCode::UNAUTHORIZED,
             Json(json!({
                 "success": false,
-                "code": "AUTH_CHALLENGE_CONSUME_FAILED",
-                "error": err.to_string()
+                "code": "AUTH_ROTATE_FAILED",
+                "error": "key rotation failed"
             })),
         )
-            .into_response();
+            .into_response()
+    };
+
+    let node = match state.db.get_node_claim_by_id(&payload.node_id).await {
+        Ok(node) => node,
+        Err(_) => {
+            return unauthorized_rotate();
+        }
+    };
+
+    if node.revoked {
+        return unauthorized_rotate();
     }
 
-    if challenge_record.expires_at <= Utc::now() {
-        return unauthorized_verify();
+    if let Err(response) = take_challenge(
+        &state,
+        &payload.node_id,
+        payload.challenge.trim(),
+        unauthorized_rotate,
+    )
+    .await
+    {
+        return response;
     }
 
-    if let Err(err) = verify_signature(
+    let new_public_key = payload.new_public_key.trim();
+
+    if let Err(err) = verify_rotation(
         node.public_key.as_str(),
+        new_public_key,
         payload.challenge.trim(),
         payload.signature.trim(),
+        payload.new_signature.trim(),
     ) {
-        let _ = err;
-        return unauthorized_verify();
+        warn!("key rotation rejected for node {}: {err}", payload.node_id);
+        return unauthorized_rotate();
     }
 
-    let (access_token, expires_at) = match issue_node_jwt(&state.env, payload.node_id) {
-        Ok(result) => result,
+    match state.db.get_node_by_public_key(new_public_key).await {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            return (
+                StatusCode::CONFLICT,
+                Json(json!({
+                    "success": false,
+                    "code": "AUTH_ROTATE_KEY_IN_USE",
+                    "error": "public key is already in use"
+                })),
+            )
+                .into_response();
+        }
         Err(err) => {
             return (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(json!({
                     "success": false,
-                    "code": "AUTH_JWT_ISSUE_FAILED",
+                    "code": "AUTH_ROTATE_LOOKUP_FAILED",
                     "error": err.to_string()
                 })),
             )
                 .into_response();
         }
-    };
+    }
+
+    match state
+        .db
+        .rotate_node_public_key(&payload.node_id, node.public_key.as_str(), new_public_key)
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            // The key changed or the node was revoked since it was loaded
+            return (
+                StatusCode::CONFLICT,
+                Json(json!({
+                    "success": false,
+                    "code": "AUTH_ROTATE_CONFLICT",
+                    "error": "node key changed during rotation"
+                })),
+            )
+                .into_response();
+        }
+        Err(err) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "success": false,
+                    "code": "AUTH_ROTATE_WRITE_FAILED",
+                    "error": err.to_string()
+                })),
+            )
+                .into_response();
+        }
+    }
+
+    info!("rotated public key for node {}", payload.node_id);
 
     (
         StatusCode::OK,
-        Json(json!(VerifyResponse {
-            access_token,
-            expires_at,
+        Json(json!(RotateKeyResponse {
+            success: true,
+            node_id: payload.node_id,
+            public_key: new_public_key.to_string(),
         })),
     )
         .into_response()
@@ -230,6 +339,45 @@ pub async fn heartbeat(
         .into_response()
 }
 
+/// Revokes the calling node. Its websocket on this server is closed and
+/// further challenges, JWT checks and reconnects are rejected.
+pub async fn revoke_node(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthenticatedNode>,
+) -> Response {
+    if let Err(err) = state.db.revoke_node(&auth.node_id).await {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"success": false, "error": err.to_string()})),
+        )
+            .into_response();
+    }
+
+    info!("node {} revoked", auth.node_id);
+
+    if let Some(node) = state.nodes.get(&auth.node_id) {
+        node.shutdown.notify_one();
+    }
+
+    // The node may be connected to another server instance
+    if let Err(err) = state.memory_db.publish_node_revoked(&auth.node_id).await {
+        warn!(
+            "failed to publish revocation of node {}: {err}",
+            auth.node_id
+        );
+    }
+
+    (
+        StatusCode::OK,
+        Json(json!({
+            "success": true,
+            "node_id": auth.node_id,
+            "revoked": true,
+        })),
+    )
+        .into_response()
+}
+
 pub async fn require_node_jwt(
     State(state): State<AppState>,
     mut request: Request,
@@ -294,6 +442,52 @@ async fn authenticate_node_jwt_with(
     })
 }
 
+/// Looks up and consumes a one-time challenge. The error is the response
+/// the handler should return.
+async fn take_challenge(
+    state: &AppState,
+    node_id: &Uuid,
+    challenge: &str,
+    unauthorized: impl Fn() -> Response,
+) -> Result<(), Response> {
+    let challenge_record = match state.db.get_auth_challenge(node_id, challenge).await {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            return Err(unauthorized());
+        }
+        Err(err) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "success": false,
+                    "code": "AUTH_CHALLENGE_LOOKUP_FAILED",
+                    "error": err.to_string()
+                })),
+            )
+                .into_response());
+        }
+    };
+
+    // Challenges are one-time: consume before cryptographic verification to limit replay attempts.
+    if let Err(err) = state.db.consume_auth_challenge(node_id, challenge).await {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "success": false,
+                "code": "AUTH_CHALLENGE_CONSUME_FAILED",
+                "error": err.to_string()
+            })),
+        )
+            .into_response());
+    }
+
+    if challenge_record.expires_at <= Utc::now() {
+        return Err(unauthorized());
+    }
+
+    Ok(())
+}
+
 fn generate_challenge() -> String {
     let mut bytes = [0u8; 32];
     OsRng.fill_bytes(&mut bytes);
@@ -324,6 +518,28 @@ fn verify_signature(public_key: &str, challenge: &str, signature: &str) -> anyho
         .map_err(|_| anyhow::anyhow!("signature verification failed"))
 }
 
+/// Both keys sign the same rotation message, proving possession of the
+/// current key and of the one replacing it.
+fn verify_rotation(
+    old_public_key: &str,
+    new_public_key: &str,
+    challenge: &str,
+    signature: &str,
+    new_signature: &str,
+) -> anyhow::Result<()> {
+    if old_public_key == new_public_key {
+        anyhow::bail!("new public key must differ from the current one")
+    }
+
+    let message = key_rotation_message(challenge, new_public_key);
+
+    verify_signature(old_public_key, &message, signature)
+        .map_err(|err| anyhow::anyhow!("current key: {err}"))?;
+
+    verify_signature(new_public_key, &message, new_signature)
+        .map_err(|err| anyhow::anyhow!("new key: {err}"))
+}
+
 fn issue_node_jwt(env: &Env, node_id: Uuid) -> anyhow::Result<(String, chrono::DateTime<Utc>)> {
     let iat = Utc::now();
     let expires_at = iat + Duration::seconds(env.jwt_ttl_secs);
@@ -343,7 +559,7 @@ fn issue_node_jwt(env: &Env, node_id: Uuid) -> anyhow::Result<(String, chrono::D
     Ok((token, expires_at))
 }
 
-fn extract_bearer_token(headers: &axum::http::HeaderMap) -> anyhow::Result<String> {
+pub(crate) fn extract_bearer_token(headers: &axum::http::HeaderMap) -> anyhow::Result<String> {
     let header = headers
         .get(axum::http::header::AUTHORIZATION)
         .ok_or_else(|| anyhow::anyhow!("missing authorization header"))?;
@@ -385,6 +601,7 @@ mod tests {
             jwt_secret: "test-secret-key-for-unit-tests".into(),
             jwt_ttl_secs,
             challenge_ttl_secs: 60,
+            drain_timeout_secs: 60,
         }
     }
 
@@ -507,6 +724,90 @@ mod tests {
         assert!(result.is_err());
     }
 
+    // ── key rotation ──────────────────────────────────────────────────────────
+
+    fn rotation_keys() -> (SigningKey, SigningKey, String, String) {
+        let old_key = SigningKey::generate(&mut OsRng);
+        let new_key = SigningKey::generate(&mut OsRng);
+        let old_public = URL_SAFE_NO_PAD.encode(old_key.verifying_key().as_bytes());
+        let new_public = URL_SAFE_NO_PAD.encode(new_key.verifying_key().as_bytes());
+        (old_key, new_key, old_public, new_public)
+    }
+
+    fn sign(key: &SigningKey, message: &str) -> String {
+        URL_SAFE_NO_PAD.encode(key.sign(message.as_bytes()).to_bytes())
+    }
+
+    #[test]
+    fn rotation_signed_by_both_keys_is_accepted() {
+        let (old_key, new_key, old_public, new_public) = rotation_keys();
+        let message = key_rotation_message("challenge", &new_public);
+
+        let result = verify_rotation(
+            &old_public,
+            &new_public,
+            "challenge",
+            &sign(&old_key, &message),
+            &sign(&new_key, &message),
+        );
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn rotation_without_new_key_possession_is_rejected() {
+        let (old_key, _, old_public, new_public) = rotation_keys();
+        let message = key_rotation_message("challenge", &new_public);
+
+        // The new signature comes from the old key, not the key being installed
+        let result = verify_rotation(
+            &old_public,
+            &new_public,
+            "challenge",
+            &sign(&old_key, &message),
+            &sign(&old_key, &message),
+        );
+
+        assert!(result.unwrap_err().to_string().contains("new key"));
+    }
+
+    #[test]
+    fn rotation_signature_is_bound_to_new_key() {
+        let (old_key, _, old_public, new_public) = rotation_keys();
+        let (_, other_key, _, other_public) = rotation_keys();
+
+        // The current key approved new_public, not the key presented
+        let approved = key_rotation_message("challenge", &new_public);
+        let presented = key_rotation_message("challenge", &other_public);
+
+        let result = verify_rotation(
+            &old_public,
+            &other_public,
+            "challenge",
+            &sign(&old_key, &approved),
+            &sign(&other_key, &presented),
+        );
+
+        assert!(result.unwrap_err().to_string().contains("current key"));
+    }
+
+    #[test]
+    fn rotation_to_same_key_is_rejected() {
+        let (old_key, _, old_public, _) = rotation_keys();
+        let message = key_rotation_message("challenge", &old_public);
+        let signature = sign(&old_key, &message);
+
+        let result = verify_rotation(
+            &old_public,
+            &old_public,
+            "challenge",
+            &signature,
+            &signature,
+        );
+
+        assert!(result.is_err());
+    }
+
     // ── challenge generation ──────────────────────────────────────────────────
 
     #[test]
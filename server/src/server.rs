@@ -5,16 +5,23 @@ use std::time::Duration;
 use crate::db::postgres::Database;
 use crate::db::redis::MemoryDB;
 use crate::env::Env;
-use crate::http::{AppState, READY, build_cors, healthz, list_connections, list_nodes, readiness};
-use crate::node::{claim_node, ws_node_handler};
-use crate::node_auth::{create_auth_challenge, heartbeat, require_node_jwt, verify_auth_challenge};
-use crate::web::ws_web_handler;
+use crate::http::{
+    AppState, READY, build_cors, get_usage, get_version, healthz, list_connections, list_nodes,
+    readiness,
+};
+use crate::node::{claim_node, drain_nodes, ws_node_handler};
+use crate::node_auth::{
+    create_auth_challenge, heartbeat, require_node_jwt, revoke_node, rotate_node_key,
+    verify_auth_challenge,
+};
+use crate::web::{require_web_user, ws_web_handler};
 use crate::{stun, tasks};
 use anyhow::Context;
 use axum::Router;
 use axum::middleware;
 use axum::routing::{get, post};
 use dashmap::DashMap;
+use futures_util::StreamExt;
 use log::{info, warn};
 use phirepass_common::server::ServerIdentifier;
 use tokio::signal;
@@ -62,6 +69,7 @@ pub async fn start(config: Env) -> anyhow::Result<()> {
         nodes: Arc::new(DashMap::new()),
         connections: Arc::new(DashMap::new()),
         tunnel_sessions: Arc::new(DashMap::new()),
+        tunnel_users: Arc::new(DashMap::new()),
     };
 
     info!("state ready");
@@ -69,15 +77,25 @@ pub async fn start(config: Env) -> anyhow::Result<()> {
     let server_task = spawn_server_update_task(&state, 30u64, shutdown_tx.subscribe());
     let conns_refresh_task = spawn_connections_refresh_task(&state, 30u64, shutdown_tx.subscribe());
     let stats_task = spawn_stats_log_task(&state, 60u64, shutdown_tx.subscribe());
+    let revocations_task = spawn_revocations_task(&state, shutdown_tx.subscribe());
+    let drain_state = state.clone();
+    let drain_timeout = Duration::from_secs(state.env.drain_timeout_secs);
     let http_task = start_http_server(state, shutdown_tx.subscribe());
 
     info!("server tasks started");
 
+    let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())
+        .context("failed to listen for SIGTERM")?;
     let shutdown_signal = async {
-        if let Err(err) = signal::ctrl_c().await {
-            warn!("failed to listen for shutdown signal: {}", err);
-        } else {
-            info!("ctrl+c pressed, shutting down");
+        tokio::select! {
+            res = signal::ctrl_c() => match res {
+                Ok(()) => info!("ctrl+c pressed, shutting down"),
+                Err(err) => {
+                    warn!("failed to listen for shutdown signal: {}", err);
+                    std::future::pending::<()>().await
+                }
+            },
+            _ = terminate.recv() => info!("SIGTERM received, shutting down"),
         }
     };
 
@@ -87,12 +105,20 @@ pub async fn start(config: Env) -> anyhow::Result<()> {
 
     info!("server is ready to accept connections");
 
-    tokio::select! {
-        _ = server_task => warn!("server task terminated"),
-        _ = http_task => warn!("http task ended"),
-        _ = stats_task => warn!("stats logger task ended"),
-        _ = conns_refresh_task => warn!("connections refresh task ended"),
-        _ = shutdown_signal => info!("shutdown signal received"),
+    let signalled = tokio::select! {
+        _ = server_task => { warn!("server task terminated"); false }
+        _ = http_task => { warn!("http task ended"); false }
+        _ = stats_task => { warn!("stats logger task ended"); false }
+        _ = conns_refresh_task => { warn!("connections refresh task ended"); false }
+        _ = revocations_task => { warn!("revocations listener ended"); false }
+        _ = shutdown_signal => { info!("shutdown signal received"); true }
+    };
+
+    if signalled {
+        tokio::select! {
+            _ = drain_nodes(&drain_state, drain_timeout) => info!("nodes drained"),
+            _ = signal::ctrl_c() => warn!("interrupted again, skipping the drain"),
+        }
     }
 
     info!("shutting down server");
@@ -117,10 +143,12 @@ fn start_http_server(
         let app = Router::new()
             .route("/healthz", get(healthz))
             .route("/readyz", get(readiness))
+            .route("/version", get(get_version))
             .route("/api/web/ws", get(ws_web_handler))
             .route("/api/nodes/claim", post(claim_node))
             .route("/api/nodes/auth/challenge", post(create_auth_challenge))
             .route("/api/nodes/auth/verify", post(verify_auth_challenge))
+            .route("/api/nodes/auth/rotate", post(rotate_node_key))
             .route(
                 "/api/nodes/heartbeat",
                 post(heartbeat).route_layer(middleware::from_fn_with_state(
@@ -128,9 +156,23 @@ fn start_http_server(
                     require_node_jwt,
                 )),
             )
+            .route(
+                "/api/nodes/revoke",
+                post(revoke_node).route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    require_node_jwt,
+                )),
+            )
             .route("/api/nodes/ws", get(ws_node_handler))
             .route("/api/nodes", get(list_nodes))
             .route("/api/connections", get(list_connections))
+            .route(
+                "/api/usage",
+                get(get_usage).route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    require_web_user,
+                )),
+            )
             .layer(ip_source.into_extension())
             .layer(cors)
             .with_state(state);
@@ -205,6 +247,49 @@ fn spawn_stats_log_task(
     })
 }
 
+/// Closes the websocket of nodes revoked through any server instance.
+fn spawn_revocations_task(
+    state: &AppState,
+    mut shutdown: broadcast::Receiver<()>,
+) -> tokio::task::JoinHandle<()> {
+    const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+    info!("starting revocations listener");
+    let state = state.clone();
+    tokio::spawn(async move {
+        loop {
+            let listen = async {
+                let revoked = match state.memory_db.subscribe_node_revoked().await {
+                    Ok(revoked) => revoked,
+                    Err(err) => {
+                        warn!("failed to subscribe to node revocations: {err}");
+                        tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+                        return;
+                    }
+                };
+
+                let mut revoked = std::pin::pin!(revoked);
+                while let Some(node_id) = revoked.next().await {
+                    if let Some(node) = state.nodes.get(&node_id) {
+                        info!("closing websocket of revoked node {node_id}");
+                        node.shutdown.notify_one();
+                    }
+                }
+                warn!("node revocations subscription dropped");
+                tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+            };
+
+            tokio::select! {
+                _ = listen => {}
+                _ = shutdown.recv() => {
+                    info!("revocations listener shutting down");
+                    break;
+                }
+            }
+        }
+    })
+}
+
 fn spawn_connections_refresh_task(
     state: &AppState,
     interval: u64,
@@ -1,9 +1,14 @@
 use crate::connection::WebConnection;
 use crate::http::AppState;
+use crate::node::{CredentialValidationError, validate_creds};
+use crate::node_auth::extract_bearer_token;
+use axum::Json;
 use axum::extract::ws::{Message, WebSocket};
-use axum::extract::{State, WebSocketUpgrade};
-use axum::http::HeaderMap;
+use axum::extract::{Request, State, WebSocketUpgrade};
 use axum::http::header::SEC_WEBSOCKET_PROTOCOL;
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
 use axum_client_ip::ClientIp;
 use bytes::Bytes;
 use futures_util::{SinkExt, StreamExt};
@@ -11,9 +16,11 @@ use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode};
 use log::{debug, info, warn};
 use phirepass_common::ip::resolve_client_ip;
 use phirepass_common::protocol::common::{Frame, FrameData, FrameError};
-use phirepass_common::protocol::node::NodeFrameData;
+use phirepass_common::protocol::node::{NodeFrameData, WebUser};
 use phirepass_common::protocol::web::WebFrameData;
+use phirepass_common::token::extract_creds;
 use serde::Deserialize;
+use serde_json::json;
 use std::net::IpAddr;
 use std::time::{Duration, SystemTime};
 use tokio::sync::mpsc;
@@ -70,7 +77,7 @@ async fn wait_for_auth(
     ws_rx: &mut futures_util::stream::SplitStream<WebSocket>,
     tx: &Sender<WebFrameData>,
     state: &AppState,
-) -> anyhow::Result<Uuid> {
+) -> anyhow::Result<(Uuid, Option<WebUser>)> {
     let msg = ws_rx
         .next()
         .await
@@ -106,16 +113,17 @@ async fn wait_for_auth(
             info!("authenticating connection {cid} for node {node_id} for version {version}");
 
             // to enable local dev
-            if !cfg!(debug_assertions) {
+            let user = if !cfg!(debug_assertions) {
                 let claims = validate_jwt(tx, state, &token, cid, msg_id).await?;
-                validate_user_node(tx, state, claims, node_id, cid, msg_id).await?;
+                Some(validate_user_node(tx, state, claims, node_id, cid, msg_id).await?)
             } else {
                 warn!("authentication bypass is active (debug build) — do not use in production");
-            }
+                None
+            };
 
             successful_auth(tx, cid, version, msg_id).await?;
 
-            Ok(cid)
+            Ok((cid, user))
         }
         other => {
             anyhow::bail!("expected Auth as first message, got: {:?}", other);
@@ -177,7 +185,7 @@ async fn validate_user_node(
     node_id: String,
     cid: Uuid,
     msg_id: Option<u32>,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<WebUser> {
     let user_id = Uuid::parse_str(claims.sub.as_str())
         .map_err(|err| anyhow::anyhow!("invalid jwt sub claim: {err}"))?;
 
@@ -221,7 +229,17 @@ async fn validate_user_node(
 
     info!("websocket node ownership validated for client {cid} on node {node_id}");
 
-    Ok(())
+    // Without roles the agent only allows the accounts open to everyone
+    let roles = state
+        .db
+        .get_user_roles(&user_id)
+        .await
+        .unwrap_or_else(|err| {
+            warn!("failed to load roles of user {user_id}: {err}");
+            vec![]
+        });
+
+    Ok(WebUser { user_id, roles })
 }
 
 async fn handle_web_socket(socket: WebSocket, state: AppState, ip: IpAddr) {
@@ -231,8 +249,8 @@ async fn handle_web_socket(socket: WebSocket, state: AppState, ip: IpAddr) {
 
     let (tx, mut rx) = mpsc::channel::<WebFrameData>(256);
 
-    let cid = match wait_for_auth(&mut ws_rx, &tx, &state).await {
-        Ok(uuid) => uuid,
+    let (cid, user) = match wait_for_auth(&mut ws_rx, &tx, &state).await {
+        Ok(auth) => auth,
         Err(err) => {
             warn!("authentication failed from {ip}: {err}");
             let _ = ws_tx.close().await;
@@ -243,7 +261,7 @@ async fn handle_web_socket(socket: WebSocket, state: AppState, ip: IpAddr) {
     {
         state
             .connections
-            .insert(cid, WebConnection::new(ip, tx.clone()));
+            .insert(cid, WebConnection::new(ip, tx.clone(), user));
         let total = state.connections.len();
 
         info!("connection {cid} ({ip}) established (total: {total})");
@@ -365,6 +383,12 @@ async fn handle_web_messages(
                         );
                         break;
                     }
+                    WebFrameData::NodeDraining { .. } => {
+                        warn!(
+                            "received node draining frame which is invalid if sent by web client"
+                        );
+                        break;
+                    }
                     WebFrameData::SSHWindowResize {
                         node_id,
                         sid,
@@ -444,6 +468,53 @@ async fn handle_web_messages(
                     } => {
                         handle_sftp_delete(state, cid, sid, node_id, msg_id, data).await;
                     }
+                    WebFrameData::SFTPDiskUsage {
+                        sid,
+                        node_id,
+                        msg_id,
+                        data,
+                    } => {
+                        handle_sftp_disk_usage(state, cid, sid, node_id, msg_id, data).await;
+                    }
+                    WebFrameData::SFTPSearch {
+                        sid,
+                        node_id,
+                        msg_id,
+                        data,
+                    } => {
+                        handle_sftp_search(state, cid, sid, node_id, msg_id, data).await;
+                    }
+                    WebFrameData::SFTPCopy {
+                        sid,
+                        node_id,
+                        msg_id,
+                        data,
+                    } => {
+                        handle_sftp_copy(state, cid, sid, node_id, msg_id, data).await;
+                    }
+                    WebFrameData::SFTPExtract {
+                        sid,
+                        node_id,
+                        msg_id,
+                        data,
+                    } => {
+                        handle_sftp_extract(state, cid, sid, node_id, msg_id, data).await;
+                    }
+                    WebFrameData::SFTPWatch {
+                        sid,
+                        node_id,
+                        msg_id,
+                        data,
+                    } => {
+                        handle_sftp_watch(state, cid, sid, node_id, msg_id, data).await;
+                    }
+                    WebFrameData::SFTPUnwatch {
+                        sid,
+                        node_id,
+                        msg_id,
+                    } => {
+                        handle_sftp_unwatch(state, cid, sid, node_id, msg_id).await;
+                    }
                     WebFrameData::SFTPListItems { .. } => {
                         warn!("received sftp list items which is invalid if sent by web client");
                         break;
@@ -466,6 +537,28 @@ async fn handle_web_messages(
                         );
                         break;
                     }
+                    WebFrameData::SFTPDiskUsageResult { .. } => {
+                        warn!(
+                            "received sftp disk usage result which is invalid if sent by web client"
+                        );
+                        break;
+                    }
+                    WebFrameData::SFTPSearchResults { .. } => {
+                        warn!(
+                            "received sftp search results which is invalid if sent by web client"
+                        );
+                        break;
+                    }
+                    WebFrameData::SFTPOperationProgress { .. } => {
+                        warn!(
+                            "received sftp operation progress which is invalid if sent by web client"
+                        );
+                        break;
+                    }
+                    WebFrameData::SFTPWatchEvent { .. } => {
+                        warn!("received sftp watch event which is invalid if sent by web client");
+                        break;
+                    }
                     WebFrameData::Error { .. } => {
                         warn!("received error frame which is invalid if sent by web client");
                         break;
@@ -799,6 +892,234 @@ async fn handle_sftp_delete(
     }
 }
 
+async fn handle_sftp_disk_usage(
+    state: &AppState,
+    cid: Uuid,
+    sid: u32,
+    target: String,
+    msg_id: Option<u32>,
+    data: phirepass_common::protocol::sftp::SFTPDiskUsage,
+) {
+    debug!("handle sftp disk usage request");
+
+    let node_id = match state.get_node_id_by_cid_and_sid(&cid, target, sid).await {
+        Ok(id) => id,
+        Err(err) => {
+            warn!("error getting node id: {err}");
+            return;
+        }
+    };
+
+    let tx = state.nodes.get(&node_id).map(|info| info.tx.clone());
+
+    let Some(tx) = tx else {
+        warn!("tx for node not found {node_id}");
+        return;
+    };
+
+    match tx
+        .send(NodeFrameData::SFTPDiskUsage {
+            cid,
+            sid,
+            msg_id,
+            data,
+        })
+        .await
+    {
+        Ok(_) => info!("sent sftp disk usage request to {node_id}"),
+        Err(err) => warn!("failed to forward sftp disk usage to node {node_id}: {err}"),
+    }
+}
+
+async fn handle_sftp_search(
+    state: &AppState,
+    cid: Uuid,
+    sid: u32,
+    target: String,
+    msg_id: Option<u32>,
+    data: phirepass_common::protocol::sftp::SFTPSearch,
+) {
+    debug!("handle sftp search request");
+
+    let node_id = match state.get_node_id_by_cid_and_sid(&cid, target, sid).await {
+        Ok(id) => id,
+        Err(err) => {
+            warn!("error getting node id: {err}");
+            return;
+        }
+    };
+
+    let tx = state.nodes.get(&node_id).map(|info| info.tx.clone());
+
+    let Some(tx) = tx else {
+        warn!("tx for node not found {node_id}");
+        return;
+    };
+
+    match tx
+        .send(NodeFrameData::SFTPSearch {
+            cid,
+            sid,
+            msg_id,
+            data,
+        })
+        .await
+    {
+        Ok(_) => info!("sent sftp search request to {node_id}"),
+        Err(err) => warn!("failed to forward sftp search to node {node_id}: {err}"),
+    }
+}
+
+async fn handle_sftp_copy(
+    state: &AppState,
+    cid: Uuid,
+    sid: u32,
+    target: String,
+    msg_id: Option<u32>,
+    data: phirepass_common::protocol::sftp::SFTPCopy,
+) {
+    debug!("handle sftp copy request");
+
+    let node_id = match state.get_node_id_by_cid_and_sid(&cid, target, sid).await {
+        Ok(id) => id,
+        Err(err) => {
+            warn!("error getting node id: {err}");
+            return;
+        }
+    };
+
+    let tx = state.nodes.get(&node_id).map(|info| info.tx.clone());
+
+    let Some(tx) = tx else {
+        warn!("tx for node not found {node_id}");
+        return;
+    };
+
+    match tx
+        .send(NodeFrameData::SFTPCopy {
+            cid,
+            sid,
+            msg_id,
+            data,
+        })
+        .await
+    {
+        Ok(_) => info!("sent sftp copy request to {node_id}"),
+        Err(err) => warn!("failed to forward sftp copy to node {node_id}: {err}"),
+    }
+}
+
+async fn handle_sftp_extract(
+    state: &AppState,
+    cid: Uuid,
+    sid: u32,
+    target: String,
+    msg_id: Option<u32>,
+    data: phirepass_common::protocol::sftp::SFTPExtract,
+) {
+    debug!("handle sftp extract request");
+
+    let node_id = match state.get_node_id_by_cid_and_sid(&cid, target, sid).await {
+        Ok(id) => id,
+        Err(err) => {
+            warn!("error getting node id: {err}");
+            return;
+        }
+    };
+
+    let tx = state.nodes.get(&node_id).map(|info| info.tx.clone());
+
+    let Some(tx) = tx else {
+        warn!("tx for node not found {node_id}");
+        return;
+    };
+
+    match tx
+        .send(NodeFrameData::SFTPExtract {
+            cid,
+            sid,
+            msg_id,
+            data,
+        })
+        .await
+    {
+        Ok(_) => info!("sent sftp extract request to {node_id}"),
+        Err(err) => warn!("failed to forward sftp extract to node {node_id}: {err}"),
+    }
+}
+
+async fn handle_sftp_watch(
+    state: &AppState,
+    cid: Uuid,
+    sid: u32,
+    target: String,
+    msg_id: Option<u32>,
+    data: phirepass_common::protocol::sftp::SFTPWatch,
+) {
+    debug!("handle sftp watch request");
+
+    let node_id = match state.get_node_id_by_cid_and_sid(&cid, target, sid).await {
+        Ok(id) => id,
+        Err(err) => {
+            warn!("error getting node id: {err}");
+            return;
+        }
+    };
+
+    let tx = state.nodes.get(&node_id).map(|info| info.tx.clone());
+
+    let Some(tx) = tx else {
+        warn!("tx for node not found {node_id}");
+        return;
+    };
+
+    match tx
+        .send(NodeFrameData::SFTPWatch {
+            cid,
+            sid,
+            msg_id,
+            data,
+        })
+        .await
+    {
+        Ok(_) => info!("sent sftp watch request to {node_id}"),
+        Err(err) => warn!("failed to forward sftp watch to node {node_id}: {err}"),
+    }
+}
+
+async fn handle_sftp_unwatch(
+    state: &AppState,
+    cid: Uuid,
+    sid: u32,
+    target: String,
+    msg_id: Option<u32>,
+) {
+    debug!("handle sftp unwatch request");
+
+    let node_id = match state.get_node_id_by_cid_and_sid(&cid, target, sid).await {
+        Ok(id) => id,
+        Err(err) => {
+            warn!("error getting node id: {err}");
+            return;
+        }
+    };
+
+    let tx = state.nodes.get(&node_id).map(|info| info.tx.clone());
+
+    let Some(tx) = tx else {
+        warn!("tx for node not found {node_id}");
+        return;
+    };
+
+    match tx
+        .send(NodeFrameData::SFTPUnwatch { cid, sid, msg_id })
+        .await
+    {
+        Ok(_) => info!("sent sftp unwatch request to {node_id}"),
+        Err(err) => warn!("failed to forward sftp unwatch to node {node_id}: {err}"),
+    }
+}
+
 async fn handle_web_resize(
     state: &AppState,
     cid: Uuid,
@@ -885,6 +1206,12 @@ async fn handle_web_open_tunnel(
 
     info!("notifying agent to open tunnel {protocol}");
 
+    let (client_ip, user) = state
+        .connections
+        .get(&cid)
+        .map(|conn| (Some(conn.ip.to_string()), conn.user.clone()))
+        .unwrap_or_default();
+
     if tx
         .send(NodeFrameData::OpenTunnel {
             protocol,
@@ -892,6 +1219,8 @@ async fn handle_web_open_tunnel(
             username,
             password,
             msg_id,
+            client_ip,
+            user,
         })
         .await
         .is_err()
@@ -921,6 +1250,48 @@ async fn notify_nodes_client_disconnect(state: &AppState, cid: &Uuid) {
     }
 }
 
+/// Authenticates the user-scoped HTTP APIs with a web session JWT or a
+/// personal access token, and hands the user with their roles to the handler.
+pub async fn require_web_user(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let user = match authenticate_web_user(&state, request.headers()).await {
+        Ok(user) => user,
+        Err(err) => {
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({"success": false, "error": err.to_string()})),
+            )
+                .into_response();
+        }
+    };
+
+    request.extensions_mut().insert(user);
+    next.run(request).await
+}
+
+async fn authenticate_web_user(state: &AppState, headers: &HeaderMap) -> anyhow::Result<WebUser> {
+    let token = extract_bearer_token(headers)?;
+
+    let user_id = if token.starts_with("pat_") {
+        let (token_id, token_secret) = extract_creds(token)?;
+        match validate_creds(state.db.clone(), token_id, token_secret).await {
+            Ok(token) => token.user_id,
+            Err(CredentialValidationError::Unauthorized(err))
+            | Err(CredentialValidationError::Internal(err)) => anyhow::bail!(err),
+        }
+    } else {
+        let claims = authenticate_web_jwt(state, &token).await?;
+        Uuid::parse_str(claims.sub.as_str())
+            .map_err(|err| anyhow::anyhow!("invalid jwt sub claim: {err}"))?
+    };
+
+    let roles = state.db.get_user_roles(&user_id).await?;
+    Ok(WebUser { user_id, roles })
+}
+
 async fn authenticate_web_jwt(state: &AppState, token: &str) -> anyhow::Result<WebJwtClaims> {
     let mut validation = Validation::new(Algorithm::HS256);
     validation.validate_exp = true;